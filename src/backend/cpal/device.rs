@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    Device, Host, SampleFormat, SupportedBufferSize, SupportedStreamConfigRange,
+};
+
+// Rates reported when a device advertises a continuous range
+const STANDARD_SAMPLE_RATES: [u32; 11] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000,
+];
+
+pub enum Selector<'a> {
+    Name(&'a str),
+    Index(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+#[derive(Clone, Debug)]
+pub struct HostInfo {
+    pub index: usize,
+    pub name: String,
+    pub is_default: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferSizeRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub host: String,
+    pub index: usize,
+    pub name: String,
+    pub direction: Direction,
+    pub channels: Vec<u16>,
+    pub sample_rates: Vec<u32>,
+    pub sample_formats: Vec<SampleFormat>,
+    // None when the driver does not report its limits
+    pub buffer_size: Option<BufferSizeRange>,
+    pub is_default: bool,
+}
+
+impl DeviceInfo {
+    pub fn max_channels(&self) -> u16 {
+        self.channels.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn supports_sample_rate(&self, sample_rate: u32) -> bool {
+        self.sample_rates.contains(&sample_rate)
+    }
+}
+
+pub fn hosts() -> Vec<HostInfo> {
+    let default = cpal::default_host().id();
+
+    cpal::available_hosts()
+        .into_iter()
+        .enumerate()
+        .map(|(index, id)| HostInfo {
+            index,
+            name: id.name().to_string(),
+            is_default: id == default,
+        })
+        .collect()
+}
+
+pub fn host(selector: Selector) -> Result<Host> {
+    let ids = cpal::available_hosts();
+
+    let id = match selector {
+        Selector::Name(name) => ids
+            .into_iter()
+            .find(|id| id.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("no audio host named {}", name))?,
+        Selector::Index(index) => ids
+            .get(index)
+            .copied()
+            .ok_or_else(|| anyhow!("no audio host at index {}", index))?,
+    };
+
+    Ok(cpal::host_from_id(id)?)
+}
+
+pub fn devices(host: &Host, direction: Direction) -> Result<Vec<DeviceInfo>> {
+    let default_name = default_device(host, direction).and_then(|d| d.name().ok());
+
+    let devices: Vec<Device> = match direction {
+        Direction::Input => host.input_devices()?.collect(),
+        Direction::Output => host.output_devices()?.collect(),
+    };
+
+    Ok(devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| describe(host, device, index, direction, default_name.as_deref()))
+        .collect())
+}
+
+pub fn select(host: &Host, direction: Direction, selector: Selector) -> Result<Device> {
+    let mut devices: Vec<Device> = match direction {
+        Direction::Input => host.input_devices()?.collect(),
+        Direction::Output => host.output_devices()?.collect(),
+    };
+
+    let index = match selector {
+        Selector::Name(name) => devices
+            .iter()
+            .position(|d| d.name().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| anyhow!("no {:?} device named {}", direction, name))?,
+        Selector::Index(index) if index < devices.len() => index,
+        Selector::Index(index) => {
+            return Err(anyhow!("no {:?} device at index {}", direction, index))
+        }
+    };
+
+    Ok(devices.swap_remove(index))
+}
+
+fn default_device(host: &Host, direction: Direction) -> Option<Device> {
+    match direction {
+        Direction::Input => host.default_input_device(),
+        Direction::Output => host.default_output_device(),
+    }
+}
+
+fn describe(
+    host: &Host,
+    device: &Device,
+    index: usize,
+    direction: Direction,
+    default_name: Option<&str>,
+) -> DeviceInfo {
+    let name = device
+        .name()
+        .unwrap_or_else(|_| "Unknown Device".to_string());
+
+    // A device that fails to report its configs is still listed, just empty
+    let configs: Vec<SupportedStreamConfigRange> = match direction {
+        Direction::Input => device
+            .supported_input_configs()
+            .map(|c| c.collect())
+            .unwrap_or_default(),
+        Direction::Output => device
+            .supported_output_configs()
+            .map(|c| c.collect())
+            .unwrap_or_default(),
+    };
+
+    let mut channels: Vec<u16> = configs.iter().map(|c| c.channels()).collect();
+    channels.sort_unstable();
+    channels.dedup();
+
+    let mut sample_rates = Vec::new();
+    for config in &configs {
+        let min = config.min_sample_rate().0;
+        let max = config.max_sample_rate().0;
+        sample_rates.push(min);
+        sample_rates.push(max);
+        sample_rates.extend(
+            STANDARD_SAMPLE_RATES
+                .iter()
+                .filter(|&&r| r > min && r < max),
+        );
+    }
+    sample_rates.sort_unstable();
+    sample_rates.dedup();
+
+    let mut sample_formats: Vec<SampleFormat> = Vec::new();
+    for config in &configs {
+        if !sample_formats.contains(&config.sample_format()) {
+            sample_formats.push(config.sample_format());
+        }
+    }
+
+    let buffer_size = configs
+        .iter()
+        .filter_map(|c| match c.buffer_size() {
+            SupportedBufferSize::Range { min, max } => Some(BufferSizeRange {
+                min: *min,
+                max: *max,
+            }),
+            SupportedBufferSize::Unknown => None,
+        })
+        .reduce(|a, b| BufferSizeRange {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        });
+
+    DeviceInfo {
+        host: host.id().name().to_string(),
+        index,
+        is_default: default_name == Some(name.as_str()),
+        name,
+        direction,
+        channels,
+        sample_rates,
+        sample_formats,
+        buffer_size,
+    }
+}
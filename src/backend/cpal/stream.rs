@@ -0,0 +1,260 @@
+use std::any::{Any, TypeId};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use cpal::{
+    traits::{DeviceTrait, StreamTrait},
+    Device, SampleFormat, SampleRate, Stream, StreamConfig,
+};
+
+use crate::core::engine::guard::GuardedMutex;
+use crate::core::engine::renderer::Renderer;
+
+// Seconds of input the ring holds before the output callback catches up
+const RING_SECONDS: u32 = 1;
+
+// Single producer, single consumer sample queue from the input callback to
+// the output callback. Samples are stored as bits so neither side locks.
+struct Ring {
+    samples: Vec<AtomicU32>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Ring {
+            samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+        }
+    }
+
+    // False when the ring is full and the sample was dropped
+    fn push(&self, sample: f32) -> bool {
+        let write = self.write.load(Ordering::Relaxed);
+        if write - self.read.load(Ordering::Acquire) == self.samples.len() {
+            return false;
+        }
+
+        self.samples[write % self.samples.len()].store(sample.to_bits(), Ordering::Relaxed);
+        self.write.store(write + 1, Ordering::Release);
+        true
+    }
+
+    fn pop(&self) -> Option<f32> {
+        let read = self.read.load(Ordering::Relaxed);
+        if read == self.write.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let sample = self.samples[read % self.samples.len()].load(Ordering::Relaxed);
+        self.read.store(read + 1, Ordering::Release);
+        Some(f32::from_bits(sample))
+    }
+
+    fn len(&self) -> usize {
+        self.write.load(Ordering::Acquire) - self.read.load(Ordering::Acquire)
+    }
+}
+
+type Slot = Arc<GuardedMutex<Option<Box<dyn Renderer>>>>;
+
+// What `CpalStreams::start` was given: the renderer, input and output devices
+pub type Stopped<R> = (R, Option<Device>, Device);
+
+// Output stream driving a `Renderer`, fed by an input stream when there is an
+// input device. Both run mono, the input's first channel is recorded and every
+// output channel plays the same.
+pub struct CpalStreams {
+    pub sample_rate: u32,
+    input: Option<Stream>,
+    output: Stream,
+    devices: (Option<Device>, Device),
+    renderer: Slot,
+    running: TypeId,
+    errors: Arc<AtomicUsize>,
+}
+
+impl CpalStreams {
+    // Builds and plays the streams at the output device's default rate, the
+    // input device has to support it as well
+    pub fn start<R: Renderer>(
+        input_device: Option<Device>,
+        output_device: Device,
+        renderer: R,
+    ) -> Result<Self> {
+        let (input, output) = (input_device.as_ref(), &output_device);
+        let supported = output.default_output_config()?;
+        if supported.sample_format() != SampleFormat::F32 {
+            return Err(anyhow!(
+                "output device plays {}, only f32 is supported",
+                supported.sample_format()
+            ));
+        }
+        let config: StreamConfig = supported.config();
+        let sample_rate = config.sample_rate.0;
+
+        let input_config = input
+            .map(|device| input_config(device, sample_rate))
+            .transpose()?;
+        let ring = Arc::new(Ring::new((sample_rate * RING_SECONDS) as usize));
+        // Frames between a sample hitting the input and its callback
+        let capture_latency = Arc::new(AtomicU32::new(0));
+        let errors = Arc::new(AtomicUsize::new(0));
+
+        let mut renderer: Box<dyn Renderer> = Box::new(renderer);
+        let block_size = renderer.block_size().max(1);
+        renderer.prepare(sample_rate, block_size);
+        let slot: Slot = Arc::new(GuardedMutex::new(Some(renderer)));
+
+        let input = match (input, input_config) {
+            (Some(device), Some(config)) => {
+                let channels = (config.channels as usize).max(1);
+                let (ring, latency, errors) =
+                    (ring.clone(), capture_latency.clone(), errors.clone());
+
+                let stream = device.build_input_stream(
+                    &config,
+                    move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                        let stamp = info.timestamp();
+                        let delay = stamp.callback.duration_since(&stamp.capture);
+                        let frames = delay.map_or(0.0, |d| d.as_secs_f64() * sample_rate as f64);
+                        latency.store(frames as u32, Ordering::Relaxed);
+
+                        for frame in data.chunks(channels) {
+                            ring.push(frame[0]);
+                        }
+                    },
+                    move |_| {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                    },
+                    None,
+                )?;
+                Some(stream)
+            }
+            _ => None,
+        };
+
+        let channels = (config.channels as usize).max(1);
+        let (mut input_block, mut output_block) = (vec![0.0; block_size], vec![0.0; block_size]);
+        let (callback_slot, callback_errors) = (slot.clone(), errors.clone());
+
+        let output = output.build_output_stream(
+            &config,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                // `stop` is taking the renderer back
+                let Ok(mut slot) = callback_slot.try_lock() else {
+                    data.fill(0.0);
+                    return;
+                };
+                let Some(renderer) = slot.as_mut() else {
+                    data.fill(0.0);
+                    return;
+                };
+
+                // Round trip from what plays now to when it is heard back
+                let stamp = info.timestamp();
+                let delay = stamp.playback.duration_since(&stamp.callback);
+                let playback = delay.map_or(0.0, |d| d.as_secs_f64() * sample_rate as f64);
+                let queued = ring.len() as u32;
+                let capture = capture_latency.load(Ordering::Relaxed);
+                renderer.set_input_latency(playback as u32 + queued + capture);
+
+                for chunk in data.chunks_mut(block_size * channels) {
+                    let frames = chunk.len() / channels;
+                    for sample in &mut input_block[..frames] {
+                        *sample = ring.pop().unwrap_or(0.0);
+                    }
+
+                    renderer.render(&input_block[..frames], &mut output_block[..frames]);
+                    for (frame, sample) in chunk.chunks_mut(channels).zip(&output_block) {
+                        frame.fill(*sample);
+                    }
+                }
+            },
+            move |_| {
+                callback_errors.fetch_add(1, Ordering::Relaxed);
+            },
+            None,
+        )?;
+
+        if let Some(input) = &input {
+            input.play()?;
+        }
+        output.play()?;
+
+        Ok(CpalStreams {
+            sample_rate,
+            input,
+            output,
+            devices: (input_device, output_device),
+            renderer: slot,
+            running: TypeId::of::<R>(),
+            errors,
+        })
+    }
+
+    // Errors the streams reported since they started
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn has_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    // Closes the streams and gives back what `start` was given
+    pub fn stop<R: Renderer>(self) -> Result<Stopped<R>> {
+        if self.running != TypeId::of::<R>() {
+            return Err(anyhow!("streams are not running that renderer"));
+        }
+
+        drop(self.input);
+        drop(self.output);
+
+        let renderer = self
+            .renderer
+            .lock()
+            .map_err(|_| anyhow!("output callback panicked"))?
+            .take()
+            .ok_or_else(|| anyhow!("renderer is gone"))?;
+
+        let renderer: Box<dyn Any> = renderer;
+        let renderer = renderer
+            .downcast::<R>()
+            .map_err(|_| anyhow!("streams were running another renderer"))?;
+        let (input, output) = self.devices;
+        Ok((*renderer, input, output))
+    }
+}
+
+// An f32 input configuration at `sample_rate`
+fn input_config(device: &Device, sample_rate: u32) -> Result<StreamConfig> {
+    let rate = SampleRate(sample_rate);
+    device
+        .supported_input_configs()?
+        .filter(|c| c.sample_format() == SampleFormat::F32)
+        .find(|c| c.min_sample_rate() <= rate && rate <= c.max_sample_rate())
+        .map(|c| c.with_sample_rate(rate).config())
+        .ok_or_else(|| anyhow!("input device cannot record f32 at {} Hz", sample_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_hands_samples_over_in_order_until_full() {
+        let ring = Ring::new(3);
+        assert!(ring.push(0.25) && ring.push(0.5) && ring.push(0.75));
+        assert!(!ring.push(1.0));
+        assert_eq!(ring.len(), 3);
+
+        assert_eq!(ring.pop(), Some(0.25));
+        assert!(ring.push(1.0));
+        let rest: Vec<f32> = std::iter::from_fn(|| ring.pop()).collect();
+        assert_eq!(rest, vec![0.5, 0.75, 1.0]);
+    }
+}
@@ -0,0 +1,463 @@
+use std::any::{Any, TypeId};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use jack::{
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, Frames, LatencyType,
+    NotificationHandler, Port, ProcessHandler, ProcessScope, TransportState,
+};
+
+use crate::core::engine::renderer::Renderer;
+
+// Named input/output port pair for one track or bus, bound to the node with `id`
+struct Strip {
+    id: String,
+    name: String,
+    input: Port<AudioIn>,
+    output: Port<AudioOut>,
+}
+
+// Values the JACK notification thread hands over to the process thread
+struct Shared {
+    sample_rate: AtomicU32,
+    buffer_size: AtomicU32,
+    rolling: AtomicBool,
+    frame: AtomicU32,
+}
+
+struct Notifications {
+    shared: Arc<Shared>,
+}
+
+impl NotificationHandler for Notifications {
+    fn sample_rate(&mut self, _: &Client, srate: Frames) -> Control {
+        self.shared.sample_rate.store(srate, Ordering::Relaxed);
+        Control::Continue
+    }
+}
+
+struct Processor {
+    // Away being re-prepared while None
+    renderer: Option<Box<dyn Renderer>>,
+    strips: Vec<Strip>,
+    master_in: Port<AudioIn>,
+    master_out: Port<AudioOut>,
+    follow_transport: bool,
+    shared: Arc<Shared>,
+    to_prepare: SyncSender<Box<dyn Renderer>>,
+    prepared: Receiver<Box<dyn Renderer>>,
+}
+
+type Preparer = (SyncSender<Box<dyn Renderer>>, Receiver<Box<dyn Renderer>>);
+
+// Re-prepares renderers sent by the process thread whenever JACK's sample rate
+// or buffer size changes, preparing allocates so it can't happen in the callback
+fn spawn_preparer(shared: Arc<Shared>) -> Preparer {
+    let (to_prepare, requests) = mpsc::sync_channel::<Box<dyn Renderer>>(1);
+    let (done, prepared) = mpsc::sync_channel(1);
+
+    thread::spawn(move || {
+        // Ends once the processor and its sender are dropped
+        for mut renderer in requests {
+            let sample_rate = shared.sample_rate.load(Ordering::Relaxed);
+            let block_size = shared.buffer_size.load(Ordering::Relaxed) as usize;
+            renderer.prepare(sample_rate, block_size);
+            if done.send(renderer).is_err() {
+                break;
+            }
+        }
+    });
+
+    (to_prepare, prepared)
+}
+
+// The start of a process cycle, apart from JACK's ports so it runs without a
+// server: follows the transport when asked and feeds each strip's input to
+// the node it is bound to
+fn begin_cycle<'a>(
+    renderer: &mut dyn Renderer,
+    transport: Option<(bool, u64)>,
+    input_latency: u32,
+    strips: impl Iterator<Item = (&'a str, &'a [f32])>,
+) {
+    if let Some((rolling, frame)) = transport {
+        let transport = renderer.transport_mut();
+        transport.playing = rolling;
+        transport.position = frame;
+    }
+    renderer.set_input_latency(input_latency);
+
+    for (id, input) in strips {
+        if let Some(index) = renderer.node_index(id) {
+            renderer.route_input(index, input);
+        }
+    }
+}
+
+// The end of a process cycle, each strip plays what its node just rendered
+fn end_cycle<'a>(renderer: &dyn Renderer, strips: impl Iterator<Item = (&'a str, &'a mut [f32])>) {
+    for (id, out) in strips {
+        let rendered = renderer
+            .node_index(id)
+            .and_then(|index| renderer.node_output(index));
+        match rendered {
+            Some(rendered) => {
+                let frames = out.len().min(rendered.len());
+                out[..frames].copy_from_slice(&rendered[..frames]);
+                out[frames..].fill(0.0);
+            }
+            None => out.fill(0.0),
+        }
+    }
+}
+
+impl Processor {
+    // JACK's transport, for the renderer to follow when `follow_transport` is set
+    fn sync_transport(&mut self, client: &Client) -> Option<(bool, u64)> {
+        let status = client.transport().query().ok()?;
+
+        let rolling = status.state == TransportState::Rolling;
+        let frame = status.pos.frame();
+
+        self.shared.rolling.store(rolling, Ordering::Relaxed);
+        self.shared.frame.store(frame, Ordering::Relaxed);
+
+        self.follow_transport.then_some((rolling, frame as u64))
+    }
+
+    // Sends the renderer off to be re-prepared when it no longer fits the
+    // server, the cycles until it is back are silent
+    fn check_renderer(&mut self, frames: usize) {
+        if let Ok(renderer) = self.prepared.try_recv() {
+            self.renderer = Some(renderer);
+        }
+
+        let sample_rate = self.shared.sample_rate.load(Ordering::Relaxed);
+        let stale = self
+            .renderer
+            .as_ref()
+            .is_some_and(|r| r.sample_rate() != sample_rate || r.block_size() < frames);
+
+        if stale {
+            if let Some(renderer) = self.renderer.take() {
+                if let Err(TrySendError::Full(renderer) | TrySendError::Disconnected(renderer)) =
+                    self.to_prepare.try_send(renderer)
+                {
+                    self.renderer = Some(renderer);
+                }
+            }
+        }
+    }
+
+    fn silence(&mut self, ps: &ProcessScope) {
+        self.master_out.as_mut_slice(ps).fill(0.0);
+        for strip in &mut self.strips {
+            strip.output.as_mut_slice(ps).fill(0.0);
+        }
+    }
+}
+
+impl ProcessHandler for Processor {
+    fn process(&mut self, client: &Client, ps: &ProcessScope) -> Control {
+        self.check_renderer(ps.n_frames() as usize);
+        let transport = self.sync_transport(client);
+
+        let Some(renderer) = self.renderer.as_mut() else {
+            self.silence(ps);
+            return Control::Continue;
+        };
+        let latency = self.master_in.get_latency_range(LatencyType::Capture).1;
+
+        let inputs = self
+            .strips
+            .iter()
+            .map(|s| (s.id.as_str(), s.input.as_slice(ps)));
+        begin_cycle(renderer.as_mut(), transport, latency, inputs);
+
+        renderer.render(
+            self.master_in.as_slice(ps),
+            self.master_out.as_mut_slice(ps),
+        );
+
+        let outputs = self
+            .strips
+            .iter_mut()
+            .map(|s| (s.id.as_str(), s.output.as_mut_slice(ps)));
+        end_cycle(renderer.as_ref(), outputs);
+
+        Control::Continue
+    }
+
+    // Runs on the process thread, a larger buffer re-prepares the renderer on
+    // the next cycle
+    fn buffer_size(&mut self, _: &Client, size: Frames) -> Control {
+        self.shared.buffer_size.store(size, Ordering::Relaxed);
+        Control::Continue
+    }
+}
+
+enum State {
+    Inactive { client: Client, strips: Vec<Strip> },
+    Active(AsyncClient<Notifications, Processor>),
+}
+
+// JACK client driving a `Renderer` from the JACK process callback
+pub struct JackBackend {
+    pub name: String,
+    pub follow_transport: bool,
+    state: Option<State>,
+    shared: Arc<Shared>,
+    // What `start` was given, so `stop` can hand back the same type
+    running: Option<TypeId>,
+}
+
+impl JackBackend {
+    // Connects to a running JACK server, it is never started on our behalf
+    pub fn new(name: &str) -> Result<Self> {
+        let (client, _status) = Client::new(name, ClientOptions::NO_START_SERVER)
+            .map_err(|e| anyhow!("could not connect to JACK server: {}", e))?;
+
+        let shared = Arc::new(Shared {
+            sample_rate: AtomicU32::new(client.sample_rate() as u32),
+            buffer_size: AtomicU32::new(client.buffer_size()),
+            rolling: AtomicBool::new(false),
+            frame: AtomicU32::new(0),
+        });
+
+        Ok(JackBackend {
+            name: client.name().to_string(),
+            follow_transport: true,
+            state: Some(State::Inactive {
+                client,
+                strips: Vec::new(),
+            }),
+            shared,
+            running: None,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.shared.sample_rate.load(Ordering::Relaxed)
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.shared.buffer_size.load(Ordering::Relaxed) as usize
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, Some(State::Active(_)))
+    }
+
+    fn client(&self) -> Result<&Client> {
+        match &self.state {
+            Some(State::Inactive { client, .. }) => Ok(client),
+            Some(State::Active(active)) => Ok(active.as_client()),
+            None => Err(anyhow!("JACK client is gone")),
+        }
+    }
+
+    // Registers `<name>_in` and `<name>_out` for the track or bus with `id`,
+    // numbering the name when another strip already has it. A strip feeds
+    // and receives whichever node has its id when the client runs.
+    pub fn register_strip(&mut self, id: &str, name: &str) -> Result<()> {
+        let Some(State::Inactive { client, strips }) = &mut self.state else {
+            return Err(anyhow!(
+                "ports must be registered before the client is started"
+            ));
+        };
+
+        if strips.iter().any(|s| s.id == id) {
+            return Err(anyhow!("track {} already has a strip", id));
+        }
+
+        let mut port = name.to_string();
+        let mut count = 1;
+        while strips.iter().any(|s| s.name == port) {
+            count += 1;
+            port = format!("{} {}", name, count);
+        }
+
+        let input = client.register_port(&format!("{}_in", port), AudioIn)?;
+        let output = client.register_port(&format!("{}_out", port), AudioOut)?;
+
+        strips.push(Strip {
+            id: id.to_string(),
+            name: port,
+            input,
+            output,
+        });
+
+        Ok(())
+    }
+
+    pub fn unregister_strip(&mut self, id: &str) -> Result<()> {
+        let Some(State::Inactive { client, strips }) = &mut self.state else {
+            return Err(anyhow!(
+                "ports must be unregistered before the client is started"
+            ));
+        };
+
+        let index = strips
+            .iter()
+            .position(|s| s.id == id)
+            .ok_or_else(|| anyhow!("track {} has no strip", id))?;
+        let strip = strips.remove(index);
+        client.unregister_port(strip.input)?;
+        client.unregister_port(strip.output)?;
+
+        Ok(())
+    }
+
+    pub fn has_strip(&self, id: &str) -> bool {
+        match &self.state {
+            Some(State::Inactive { strips, .. }) => strips.iter().any(|s| s.id == id),
+            _ => false,
+        }
+    }
+
+    pub fn strip_names(&self) -> Vec<String> {
+        match &self.state {
+            Some(State::Inactive { strips, .. }) => strips.iter().map(|s| s.name.clone()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Activates the client, handing the renderer over to the JACK process thread
+    pub fn start<R: Renderer>(&mut self, mut renderer: R) -> Result<()> {
+        let Some(State::Inactive { client, strips }) = self.state.take() else {
+            return Err(anyhow!("JACK client is already running"));
+        };
+
+        let master_in = client.register_port("master_in", AudioIn)?;
+        let master_out = client.register_port("master_out", AudioOut)?;
+
+        renderer.prepare(client.sample_rate() as u32, client.buffer_size() as usize);
+        let (to_prepare, prepared) = spawn_preparer(self.shared.clone());
+
+        let processor = Processor {
+            renderer: Some(Box::new(renderer)),
+            strips,
+            master_in,
+            master_out,
+            follow_transport: self.follow_transport,
+            shared: self.shared.clone(),
+            to_prepare,
+            prepared,
+        };
+        let notifications = Notifications {
+            shared: self.shared.clone(),
+        };
+
+        let active = client.activate_async(notifications, processor)?;
+        self.state = Some(State::Active(active));
+        self.running = Some(TypeId::of::<R>());
+
+        Ok(())
+    }
+
+    // Deactivates the client and gives back what `start` was given
+    pub fn stop<R: Renderer>(&mut self) -> Result<R> {
+        if self.running != Some(TypeId::of::<R>()) {
+            return Err(anyhow!("JACK client is not running that renderer"));
+        }
+        let Some(State::Active(active)) = self.state.take() else {
+            return Err(anyhow!("JACK client is not running"));
+        };
+
+        let (client, _, mut processor) = active.deactivate()?;
+        for port in [
+            processor.master_in.clone_unowned(),
+            processor.master_out.clone_unowned(),
+        ] {
+            let _ = client.unregister_port(port);
+        }
+
+        // A renderer away being prepared comes straight back
+        let renderer = match processor.renderer.take() {
+            Some(renderer) => renderer,
+            None => processor.prepared.recv()?,
+        };
+        self.state = Some(State::Inactive {
+            client,
+            strips: processor.strips,
+        });
+        self.running = None;
+
+        let renderer: Box<dyn Any> = renderer;
+        renderer
+            .downcast::<R>()
+            .map(|r| *r)
+            .map_err(|_| anyhow!("JACK client was running another renderer"))
+    }
+
+    // Transport requests go to JACK so every client stays in sync, the engine
+    // follows on its next cycle when `follow_transport` is set
+    pub fn play(&self) -> Result<()> {
+        Ok(self.client()?.transport().start()?)
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        Ok(self.client()?.transport().stop()?)
+    }
+
+    pub fn locate(&self, frame: u64) -> Result<()> {
+        Ok(self.client()?.transport().locate(frame as Frames)?)
+    }
+
+    pub fn is_rolling(&self) -> bool {
+        self.shared.rolling.load(Ordering::Relaxed)
+    }
+
+    pub fn transport_frame(&self) -> u64 {
+        self.shared.frame.load(Ordering::Relaxed) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::studio::{Audio, Config, Studio};
+    use crate::types::rhythm::tempo::tempo::Tempo;
+
+    #[test]
+    fn cycles_render_the_studio_through_its_strips() {
+        let mut studio = Studio::new(Config {
+            tempo: Tempo::new(120.0, None),
+            audio: Audio::null(48000, 4),
+        });
+        let voice = studio.new_audio_track("Voice");
+        let voice = studio.add_track(voice);
+        let group = studio.new_bus_track("Group");
+        let group = studio.add_track(group);
+        studio.set_arm(&voice, true);
+        studio
+            .track_mut(&voice)
+            .unwrap()
+            .audio_mut()
+            .unwrap()
+            .monitor = true;
+        studio.set_output(&voice, Some(&group)).unwrap();
+
+        // Each strip's port buffers, by track id
+        let mut ports = [(voice, [0.5; 4], [0.0; 4]), (group, [9.0; 4], [0.0; 4])];
+        let mut master = [0.0; 4];
+        for (transport, position) in [((false, 480), 480), ((true, 960), 964)] {
+            let inputs = ports.iter().map(|(id, i, _)| (id.as_str(), &i[..]));
+            begin_cycle(&mut studio, Some(transport), 32, inputs);
+            Renderer::render(&mut studio, &[0.0; 4], &mut master);
+            let outputs = ports.iter_mut().map(|(id, _, o)| (id.as_str(), &mut o[..]));
+            end_cycle(&studio, outputs);
+
+            assert_eq!(studio.engine.transport.position, position);
+            assert_eq!(studio.engine.input_latency, 32);
+        }
+
+        // The voice plays its own port into the group, which plays the master
+        assert_eq!(ports[0].2, [0.5; 4]);
+        assert_eq!(ports[1].2, [0.5; 4]);
+        assert_eq!(master, [0.5; 4]);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod cpal;
+pub mod jack;
+pub mod null;
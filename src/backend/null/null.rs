@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::core::engine::engine::Engine;
+use crate::core::wav::wav::write_wav;
+
+pub enum Capture {
+    Discard,
+    Memory,
+    File(PathBuf),
+}
+
+// Produces one input sample for a given frame position and sample rate
+pub type InputSignal = Box<dyn FnMut(u64, u32) -> f32 + Send>;
+
+// Backend without any hardware. It runs the engine clock at a fixed sample
+// rate and block size, either as fast as possible or paced to wall time, and
+// can keep what was rendered.
+pub struct NullBackend {
+    pub sample_rate: u32,
+    pub block_size: usize,
+    pub paced: bool,
+    pub position: u64,
+    // Simulated input latency in frames, reported to the engine
+    pub input_latency: u32,
+    capture: Capture,
+    captured: Vec<f32>,
+    input_signal: Option<InputSignal>,
+    loopback: Option<VecDeque<f32>>,
+    input: Vec<f32>,
+    output: Vec<f32>,
+}
+
+impl Default for NullBackend {
+    fn default() -> Self {
+        NullBackend::new(44100, 512)
+    }
+}
+
+impl NullBackend {
+    pub fn new(sample_rate: u32, block_size: usize) -> Self {
+        let block_size = block_size.max(1);
+
+        NullBackend {
+            sample_rate,
+            block_size,
+            paced: false,
+            position: 0,
+            input_latency: 0,
+            capture: Capture::Discard,
+            captured: Vec::new(),
+            input_signal: None,
+            loopback: None,
+            input: vec![0.0; block_size],
+            output: vec![0.0; block_size],
+        }
+    }
+
+    pub fn capture_to_memory(&mut self) {
+        self.capture = Capture::Memory;
+    }
+
+    // Output is kept in memory while running and written out by `finish`
+    pub fn capture_to_file<P: AsRef<Path>>(&mut self, path: P) {
+        self.capture = Capture::File(path.as_ref().to_path_buf());
+    }
+
+    // Synthetic signal fed to the engine as if it came from an input device
+    pub fn set_input<F>(&mut self, signal: F)
+    where
+        F: FnMut(u64, u32) -> f32 + Send + 'static,
+    {
+        self.input_signal = Some(Box::new(signal));
+    }
+
+    pub fn clear_input(&mut self) {
+        self.input_signal = None;
+        self.loopback = None;
+    }
+
+    // Feeds the output back into the input `delay` frames later, like a
+    // loopback cable. Delays shorter than a block come back one block late.
+    pub fn set_loopback(&mut self, delay: usize) {
+        let mut queue = VecDeque::with_capacity(delay + self.block_size * 2);
+        queue.resize(delay, 0.0);
+        self.loopback = Some(queue);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.position as f64 / self.sample_rate as f64)
+    }
+
+    pub fn captured(&self) -> &[f32] {
+        &self.captured
+    }
+
+    pub fn take_captured(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.captured)
+    }
+
+    // Runs one block through `render(input, output)`
+    pub fn process_block<F>(&mut self, mut render: F)
+    where
+        F: FnMut(&[f32], &mut [f32]),
+    {
+        match (self.loopback.as_mut(), self.input_signal.as_mut()) {
+            (Some(queue), _) => {
+                for sample in self.input.iter_mut() {
+                    *sample = queue.pop_front().unwrap_or(0.0);
+                }
+            }
+            (None, Some(signal)) => {
+                for (i, sample) in self.input.iter_mut().enumerate() {
+                    *sample = signal(self.position + i as u64, self.sample_rate);
+                }
+            }
+            (None, None) => self.input.fill(0.0),
+        }
+
+        self.output.fill(0.0);
+        render(&self.input, &mut self.output);
+
+        if let Some(queue) = self.loopback.as_mut() {
+            queue.extend(self.output.iter());
+        }
+
+        if !matches!(self.capture, Capture::Discard) {
+            self.captured.extend_from_slice(&self.output);
+        }
+
+        self.position += self.block_size as u64;
+    }
+
+    pub fn run_blocks<F>(&mut self, blocks: usize, mut render: F)
+    where
+        F: FnMut(&[f32], &mut [f32]),
+    {
+        let started = Instant::now();
+        let block = Duration::from_secs_f64(self.block_size as f64 / self.sample_rate as f64);
+
+        for i in 0..blocks {
+            self.process_block(&mut render);
+
+            if self.paced {
+                let due = block * (i as u32 + 1);
+                if let Some(wait) = due.checked_sub(started.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+        }
+    }
+
+    // Renders at least `duration` worth of audio, rounded up to whole blocks
+    pub fn run_for<F>(&mut self, duration: Duration, render: F)
+    where
+        F: FnMut(&[f32], &mut [f32]),
+    {
+        let frames = (duration.as_secs_f64() * self.sample_rate as f64).ceil() as usize;
+        let blocks = frames.div_ceil(self.block_size);
+        self.run_blocks(blocks, render);
+    }
+
+    pub fn run_engine(&mut self, engine: &mut Engine, blocks: usize) {
+        if engine.sample_rate != self.sample_rate || engine.block_size != self.block_size {
+            engine.prepare(self.sample_rate, self.block_size);
+        }
+        engine.input_latency = self.input_latency;
+
+        self.run_blocks(blocks, |input, output| engine.render(input, output));
+    }
+
+    // Writes the captured output when capturing to a file
+    pub fn finish(&mut self) -> Result<()> {
+        if let Capture::File(path) = &self.capture {
+            write_wav(path, self.sample_rate, &self.captured)?;
+        }
+
+        Ok(())
+    }
+}
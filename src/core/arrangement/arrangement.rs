@@ -0,0 +1,234 @@
+use anyhow::{anyhow, Result};
+use nanoid::nanoid;
+
+use crate::types::rhythm::tempo::bpm::bar::Bar;
+
+#[derive(Clone)]
+pub struct Marker {
+    pub id: String,
+    pub name: String,
+    pub bar: Bar,
+}
+
+// A named stretch of the song such as an intro, verse or chorus
+#[derive(Clone)]
+pub struct Section {
+    pub id: String,
+    pub name: String,
+    pub start: Bar,
+    pub end: Bar,
+}
+
+impl Section {
+    pub fn length(&self) -> Bar {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, bar: Bar) -> bool {
+        bar >= self.start && bar < self.end
+    }
+}
+
+// Left and right locators, the range the transport loops over
+#[derive(Clone, Copy)]
+pub struct Locators {
+    pub left: Bar,
+    pub right: Bar,
+}
+
+impl Default for Locators {
+    fn default() -> Self {
+        Locators {
+            left: 0.0,
+            right: 4.0,
+        }
+    }
+}
+
+// Song structure laid over the tracks, everything is in bars
+#[derive(Default)]
+pub struct Arrangement {
+    pub markers: Vec<Marker>,
+    pub sections: Vec<Section>,
+    pub locators: Locators,
+}
+
+impl Arrangement {
+    pub fn new() -> Self {
+        Arrangement::default()
+    }
+
+    fn sort(&mut self) {
+        self.markers.sort_by(|a, b| a.bar.total_cmp(&b.bar));
+        self.sections.sort_by(|a, b| a.start.total_cmp(&b.start));
+    }
+
+    // Returns the new marker's id
+    pub fn add_marker(&mut self, name: &str, bar: Bar) -> String {
+        let id = nanoid!();
+        self.markers.push(Marker {
+            id: id.clone(),
+            name: name.to_string(),
+            bar: bar.max(0.0),
+        });
+        self.sort();
+        id
+    }
+
+    // Looks a marker up by id or by name
+    pub fn marker(&self, key: &str) -> Option<&Marker> {
+        self.markers
+            .iter()
+            .find(|m| m.id == key)
+            .or_else(|| self.markers.iter().find(|m| m.name == key))
+    }
+
+    pub fn remove_marker(&mut self, key: &str) -> Option<Marker> {
+        let id = self.marker(key)?.id.clone();
+        let index = self.markers.iter().position(|m| m.id == id)?;
+        Some(self.markers.remove(index))
+    }
+
+    pub fn next_marker(&self, bar: Bar) -> Option<&Marker> {
+        self.markers.iter().find(|m| m.bar > bar)
+    }
+
+    pub fn previous_marker(&self, bar: Bar) -> Option<&Marker> {
+        self.markers.iter().rev().find(|m| m.bar < bar)
+    }
+
+    // Sections may not overlap, returns the new section's id
+    pub fn add_section(&mut self, name: &str, start: Bar, end: Bar) -> Result<String> {
+        let start = start.max(0.0);
+        if end <= start {
+            return Err(anyhow!("section {} ends before it starts", name));
+        }
+        if let Some(other) = self
+            .sections
+            .iter()
+            .find(|s| s.start < end && start < s.end)
+        {
+            return Err(anyhow!("section {} overlaps section {}", name, other.name));
+        }
+
+        let id = nanoid!();
+        self.sections.push(Section {
+            id: id.clone(),
+            name: name.to_string(),
+            start,
+            end,
+        });
+        self.sort();
+        Ok(id)
+    }
+
+    // Looks a section up by id or by name
+    pub fn section(&self, key: &str) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|s| s.id == key)
+            .or_else(|| self.sections.iter().find(|s| s.name == key))
+    }
+
+    pub fn remove_section(&mut self, key: &str) -> Option<Section> {
+        let id = self.section(key)?.id.clone();
+        let index = self.sections.iter().position(|s| s.id == id)?;
+        Some(self.sections.remove(index))
+    }
+
+    pub fn section_at(&self, bar: Bar) -> Option<&Section> {
+        self.sections.iter().find(|s| s.contains(bar))
+    }
+
+    pub fn set_locators(&mut self, left: Bar, right: Bar) {
+        self.locators = Locators {
+            left: left.min(right).max(0.0),
+            right: right.max(left),
+        };
+    }
+
+    // Opens a gap of `bars` at `at`, a section spanning `at` grows to cover it
+    pub fn insert_time(&mut self, at: Bar, bars: Bar) {
+        if bars <= 0.0 {
+            return;
+        }
+
+        for marker in &mut self.markers {
+            if marker.bar >= at {
+                marker.bar += bars;
+            }
+        }
+        for section in &mut self.sections {
+            if section.start >= at {
+                section.start += bars;
+            }
+            if section.end > at {
+                section.end += bars;
+            }
+        }
+    }
+
+    // Cuts `start..end` out and closes the gap. Markers inside are dropped and
+    // sections are trimmed, or dropped when nothing of them is left.
+    pub fn delete_time(&mut self, start: Bar, end: Bar) {
+        if end <= start {
+            return;
+        }
+
+        let length = end - start;
+        let shift = |bar: Bar| {
+            if bar >= end {
+                bar - length
+            } else {
+                bar.min(start)
+            }
+        };
+
+        self.markers.retain(|m| m.bar < start || m.bar >= end);
+        for marker in &mut self.markers {
+            marker.bar = shift(marker.bar);
+        }
+        for section in &mut self.sections {
+            section.start = shift(section.start);
+            section.end = shift(section.end);
+        }
+        self.sections.retain(|s| s.end > s.start);
+    }
+
+    // Repeats `start..end` right after itself. Markers inside are copied, and
+    // so are sections lying completely inside.
+    pub fn duplicate_time(&mut self, start: Bar, end: Bar) {
+        if end <= start {
+            return;
+        }
+
+        let length = end - start;
+        let markers: Vec<Marker> = self
+            .markers
+            .iter()
+            .filter(|m| m.bar >= start && m.bar < end)
+            .cloned()
+            .collect();
+        let sections: Vec<Section> = self
+            .sections
+            .iter()
+            .filter(|s| s.start >= start && s.end <= end)
+            .cloned()
+            .collect();
+
+        self.insert_time(end, length);
+
+        self.markers.extend(markers.into_iter().map(|m| Marker {
+            id: nanoid!(),
+            bar: m.bar + length,
+            ..m
+        }));
+        self.sections.extend(sections.into_iter().map(|s| Section {
+            id: nanoid!(),
+            start: s.start + length,
+            end: s.end + length,
+            ..s
+        }));
+        self.sort();
+    }
+}
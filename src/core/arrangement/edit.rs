@@ -0,0 +1,155 @@
+// Time editing over a track's clips. Positions are in beats, so callers lay
+// the clips out again afterwards.
+use crate::core::clip::clip::AudioClip;
+use crate::core::clip::midi_clip::MidiClip;
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+pub trait TimelineClip: Sized {
+    fn start(&self) -> Beat;
+    fn set_start(&mut self, start: Beat);
+    fn end(&self, tempo: &Tempo, sample_rate: u32) -> Beat;
+    // Splits `beats` into the clip, the right half starts exactly at the split
+    fn split_at(&self, beats: Beat, tempo: &Tempo, sample_rate: u32) -> (Self, Self);
+    fn duplicate(&self) -> Self;
+}
+
+impl TimelineClip for AudioClip {
+    fn start(&self) -> Beat {
+        self.start
+    }
+
+    fn set_start(&mut self, start: Beat) {
+        self.start = start;
+    }
+
+    fn end(&self, tempo: &Tempo, sample_rate: u32) -> Beat {
+        let start = tempo.beats_to_samples(self.start, sample_rate);
+        tempo.samples_to_beats(start + self.length, sample_rate)
+    }
+
+    fn split_at(&self, beats: Beat, tempo: &Tempo, sample_rate: u32) -> (Self, Self) {
+        let start = tempo.beats_to_samples(self.start, sample_rate);
+        let at = tempo.beats_to_samples(self.start + beats, sample_rate) - start;
+        let (left, mut right) = self.split(at);
+        right.start = self.start + beats;
+        (left, right)
+    }
+
+    fn duplicate(&self) -> Self {
+        AudioClip::duplicate(self)
+    }
+}
+
+impl TimelineClip for MidiClip {
+    fn start(&self) -> Beat {
+        self.start
+    }
+
+    fn set_start(&mut self, start: Beat) {
+        self.start = start;
+    }
+
+    fn end(&self, _: &Tempo, _: u32) -> Beat {
+        self.start + self.ticks_to_beats(self.length)
+    }
+
+    fn split_at(&self, beats: Beat, _: &Tempo, _: u32) -> (Self, Self) {
+        let (left, mut right) = self.split(self.beats_to_ticks(beats));
+        right.start = self.start + beats;
+        (left, right)
+    }
+
+    fn duplicate(&self) -> Self {
+        MidiClip::duplicate(self)
+    }
+}
+
+// Cuts every clip that crosses `at` in two
+fn split_clips<C: TimelineClip>(clips: &mut Vec<C>, at: Beat, tempo: &Tempo, sample_rate: u32) {
+    let mut index = 0;
+    while index < clips.len() {
+        let clip = &clips[index];
+        if clip.start() < at && at < clip.end(tempo, sample_rate) {
+            let (left, right) = clip.split_at(at - clip.start(), tempo, sample_rate);
+            clips[index] = left;
+            clips.insert(index + 1, right);
+            index += 1;
+        }
+        index += 1;
+    }
+}
+
+// Opens a gap of `length` beats at `at`, splitting clips that cross it
+pub fn insert_time<C: TimelineClip>(
+    clips: &mut Vec<C>,
+    at: Beat,
+    length: Beat,
+    tempo: &Tempo,
+    sample_rate: u32,
+) {
+    if length <= 0.0 {
+        return;
+    }
+
+    split_clips(clips, at, tempo, sample_rate);
+    for clip in clips.iter_mut() {
+        if clip.start() >= at {
+            clip.set_start(clip.start() + length);
+        }
+    }
+}
+
+// Removes everything in `start..end` and moves later clips back to close the gap
+pub fn delete_time<C: TimelineClip>(
+    clips: &mut Vec<C>,
+    start: Beat,
+    end: Beat,
+    tempo: &Tempo,
+    sample_rate: u32,
+) {
+    if end <= start {
+        return;
+    }
+
+    split_clips(clips, start, tempo, sample_rate);
+    split_clips(clips, end, tempo, sample_rate);
+    clips.retain(|c| c.start() < start || c.start() >= end);
+
+    for clip in clips.iter_mut() {
+        if clip.start() >= end {
+            clip.set_start(clip.start() - (end - start));
+        }
+    }
+}
+
+// Repeats `start..end` right after itself, later clips move out of the way
+pub fn duplicate_time<C: TimelineClip>(
+    clips: &mut Vec<C>,
+    start: Beat,
+    end: Beat,
+    tempo: &Tempo,
+    sample_rate: u32,
+) {
+    if end <= start {
+        return;
+    }
+
+    let length = end - start;
+    split_clips(clips, start, tempo, sample_rate);
+    split_clips(clips, end, tempo, sample_rate);
+
+    let copies: Vec<C> = clips
+        .iter()
+        .filter(|c| c.start() >= start && c.start() < end)
+        .map(|c| {
+            let mut copy = c.duplicate();
+            copy.set_start(c.start() + length);
+            copy
+        })
+        .collect();
+
+    insert_time(clips, end, length, tempo, sample_rate);
+    clips.extend(copies);
+    clips.sort_by(|a, b| a.start().total_cmp(&b.start()));
+}
@@ -0,0 +1,507 @@
+use nanoid::nanoid;
+
+use crate::core::param::param::{Param, ParamId};
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+// Points a write pass can take, recording stops once it is full
+const PASS_CAPACITY: usize = 1 << 14;
+// Recorded points this close to the line through their neighbours are dropped
+const THIN_TOLERANCE: f32 = 1e-4;
+
+// Shape of the segment from a breakpoint to the next one
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum CurveShape {
+    #[default]
+    Linear,
+    // Constant ratio per beat, linear when the ends differ in sign or touch zero
+    Exponential,
+    // Holds the value until the next breakpoint
+    Step,
+    // Cubic ease with two control values, (1/3, 2/3) is linear
+    Bezier(f32, f32),
+}
+
+impl CurveShape {
+    // Value between `from` and `to` at `t` in 0..=1
+    pub fn interpolate(&self, from: f32, to: f32, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+
+        match *self {
+            CurveShape::Linear => from + (to - from) * t,
+            CurveShape::Exponential if from * to > 0.0 => from * (to / from).powf(t),
+            CurveShape::Exponential => from + (to - from) * t,
+            CurveShape::Step => from,
+            CurveShape::Bezier(c1, c2) => {
+                let u = 1.0 - t;
+                let eased = 3.0 * u * u * t * c1 + 3.0 * u * t * t * c2 + t * t * t;
+                from + (to - from) * eased
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Breakpoint {
+    pub beat: Beat,
+    // In the parameter's own units
+    pub value: f32,
+    pub shape: CurveShape,
+}
+
+impl Breakpoint {
+    pub fn new(beat: Beat, value: f32, shape: CurveShape) -> Self {
+        Breakpoint { beat, value, shape }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum AutomationMode {
+    Off,
+    #[default]
+    Read,
+    // Records the parameter for as long as the transport runs
+    Write,
+    // Records while the control is touched, reads otherwise
+    Touch,
+    // Like touch, but keeps recording from the first touch until the transport stops
+    Latch,
+}
+
+// Breakpoints driving one parameter, looked up by id
+pub struct AutomationLane {
+    pub id: String,
+    pub param: ParamId,
+    // Writing modes need `set_mode`, which reserves the pass buffer
+    pub mode: AutomationMode,
+    // Sorted by beat, two points on one beat make a jump
+    pub points: Vec<Breakpoint>,
+    target: Option<Param>,
+    samples: Vec<u64>,
+    // Tempo and rate the samples were laid out with
+    tempo: Tempo,
+    sample_rate: u32,
+    touched: bool,
+    latched: bool,
+    // (sample, value) written during the current pass
+    pass: Vec<(u64, f32)>,
+    // Room a commit merges into before swapping with `points`
+    scratch: Vec<Breakpoint>,
+}
+
+impl AutomationLane {
+    pub fn new(param: &str) -> Self {
+        AutomationLane {
+            id: nanoid!(),
+            param: param.to_string(),
+            mode: AutomationMode::default(),
+            points: Vec::new(),
+            target: None,
+            samples: Vec::new(),
+            tempo: Tempo::default(),
+            sample_rate: 0,
+            touched: false,
+            latched: false,
+            pass: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    // Copy with a new id, pointed at `param`
+    pub fn duplicate(&self, param: &str) -> Self {
+        let mut lane = AutomationLane {
+            id: nanoid!(),
+            param: param.to_string(),
+            mode: AutomationMode::default(),
+            points: self.points.clone(),
+            target: None,
+            samples: self.samples.clone(),
+            tempo: self.tempo.clone(),
+            sample_rate: self.sample_rate,
+            touched: false,
+            latched: false,
+            pass: Vec::new(),
+            scratch: Vec::new(),
+        };
+        lane.set_mode(self.mode);
+        lane
+    }
+
+    // Connects the lane to the parameter it drives
+    pub fn bind(&mut self, param: Option<Param>) {
+        self.target = param.filter(|p| p.id == self.param);
+    }
+
+    pub fn target(&self) -> Option<&Param> {
+        self.target.as_ref()
+    }
+
+    pub fn add_point(&mut self, point: Breakpoint) {
+        let index = self.points.partition_point(|p| p.beat <= point.beat);
+        self.points.insert(index, point);
+        self.resample();
+        self.reserve();
+    }
+
+    pub fn remove_point(&mut self, index: usize) -> Option<Breakpoint> {
+        if index >= self.points.len() {
+            return None;
+        }
+
+        let point = self.points.remove(index);
+        self.resample();
+        Some(point)
+    }
+
+    pub fn remove_points_in(&mut self, start: Beat, end: Beat) {
+        self.points.retain(|p| p.beat < start || p.beat >= end);
+        self.resample();
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.resample();
+    }
+
+    // Resolves breakpoints into samples, call again when the tempo changes
+    pub fn layout(&mut self, tempo: &Tempo, sample_rate: u32) {
+        self.tempo = tempo.clone();
+        self.sample_rate = sample_rate;
+        self.resample();
+        self.reserve();
+    }
+
+    fn resample(&mut self) {
+        self.samples.clear();
+        self.samples.extend(
+            self.points
+                .iter()
+                .map(|p| self.tempo.beats_to_samples(p.beat, self.sample_rate)),
+        );
+    }
+
+    // Value in the segment after point `before - 1`, at `x` along `position`
+    fn segment_value(&self, before: usize, x: f64, position: impl Fn(usize) -> f64) -> Option<f32> {
+        let first = self.points.first()?;
+        if before == 0 {
+            return Some(first.value);
+        }
+
+        let a = before - 1;
+        let Some(next) = self.points.get(a + 1) else {
+            return Some(self.points[a].value);
+        };
+
+        let (x0, x1) = (position(a), position(a + 1));
+        let t = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+        let point = &self.points[a];
+        Some(point.shape.interpolate(point.value, next.value, t as f32))
+    }
+
+    pub fn value_at(&self, beat: Beat) -> Option<f32> {
+        let before = self.points.partition_point(|p| p.beat <= beat);
+        self.segment_value(before, beat as f64, |i| self.points[i].beat as f64)
+    }
+
+    // Value approached from the left, differs from `value_at` on a jump
+    pub fn value_before(&self, beat: Beat) -> Option<f32> {
+        let before = self.points.partition_point(|p| p.beat < beat);
+        self.segment_value(before, beat as f64, |i| self.points[i].beat as f64)
+    }
+
+    fn shape_at(&self, beat: Beat) -> CurveShape {
+        let before = self.points.partition_point(|p| p.beat <= beat);
+        before
+            .checked_sub(1)
+            .map(|i| self.points[i].shape)
+            .unwrap_or_default()
+    }
+
+    pub fn value_at_sample(&self, sample: u64) -> Option<f32> {
+        let before = self.samples.partition_point(|s| *s <= sample);
+        self.segment_value(before, sample as f64, |i| self.samples[i] as f64)
+    }
+
+    pub fn value_before_sample(&self, sample: u64) -> Option<f32> {
+        let before = self.samples.partition_point(|s| *s < sample);
+        self.segment_value(before, sample as f64, |i| self.samples[i] as f64)
+    }
+
+    // First breakpoint strictly after `sample`
+    pub fn next_point(&self, sample: u64) -> Option<u64> {
+        let index = self.samples.partition_point(|s| *s <= sample);
+        self.samples.get(index).copied()
+    }
+
+    pub fn has_point_at(&self, sample: u64) -> bool {
+        self.samples.binary_search(&sample).is_ok()
+    }
+
+    // Call from the control thread, writing modes reserve room for a pass so
+    // recording never allocates
+    pub fn set_mode(&mut self, mode: AutomationMode) {
+        self.mode = mode;
+        self.reserve();
+    }
+
+    // Tops up the room a pass and its commit need, so neither allocates
+    fn reserve(&mut self) {
+        if self.mode == AutomationMode::Off || self.mode == AutomationMode::Read {
+            return;
+        }
+
+        let points = self.points.len() + PASS_CAPACITY;
+        self.pass
+            .reserve_exact(PASS_CAPACITY.saturating_sub(self.pass.len()));
+        self.points
+            .reserve_exact(points.saturating_sub(self.points.len()));
+        self.scratch
+            .reserve_exact(points.saturating_sub(self.scratch.len()));
+        self.samples
+            .reserve_exact(points.saturating_sub(self.samples.len()));
+    }
+
+    pub fn touch(&mut self) {
+        self.touched = true;
+        if self.mode == AutomationMode::Latch {
+            self.latched = true;
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.touched = false;
+    }
+
+    pub fn is_touched(&self) -> bool {
+        self.touched
+    }
+
+    pub fn is_writing(&self) -> bool {
+        match self.mode {
+            AutomationMode::Write => true,
+            AutomationMode::Touch => self.touched,
+            AutomationMode::Latch => self.latched,
+            AutomationMode::Off | AutomationMode::Read => false,
+        }
+    }
+
+    pub fn is_reading(&self) -> bool {
+        self.mode != AutomationMode::Off && !self.is_writing() && !self.points.is_empty()
+    }
+
+    // Adds a written value, a jump back (the transport looped) starts a new
+    // pass. Values past the reserved room are dropped rather than growing it.
+    pub fn record(&mut self, sample: u64, value: f32) {
+        if self.pass.last().is_some_and(|(last, _)| sample < *last) {
+            self.commit();
+        }
+        if self.pass.len() < self.pass.capacity() {
+            self.pass.push((sample, value));
+        }
+    }
+
+    pub fn has_pass(&self) -> bool {
+        !self.pass.is_empty()
+    }
+
+    // Transport stopped, ends the pass and any latch
+    pub fn stop(&mut self) {
+        self.latched = false;
+        self.commit();
+    }
+
+    // Replaces the breakpoints under the written pass with what was written.
+    // Runs on the audio thread, so it thins the pass in place and merges into
+    // the reserved scratch; points past the reserved room are dropped.
+    pub fn commit(&mut self) {
+        let (Some(first), Some(last)) = (self.pass.first().copied(), self.pass.last().copied())
+        else {
+            return;
+        };
+        if self.sample_rate == 0 {
+            self.pass.clear();
+            return;
+        }
+
+        let (tempo, sample_rate) = (&self.tempo, self.sample_rate);
+        let to_beat = |sample: u64| tempo.samples_to_beats(sample, sample_rate);
+        let (start, end) = (to_beat(first.0), to_beat(last.0));
+
+        // Keeps the values a straight line from the last kept one misses
+        let mut kept: usize = 0;
+        for i in 0..self.pass.len() {
+            let (sample, value) = self.pass[i];
+            let redundant = match (kept.checked_sub(1), self.pass.get(i + 1)) {
+                (Some(previous), Some(&(next_sample, next_value))) => {
+                    let (previous_sample, previous_value) = self.pass[previous];
+                    let previous_beat = to_beat(previous_sample);
+                    let t =
+                        (to_beat(sample) - previous_beat) / (to_beat(next_sample) - previous_beat);
+                    let expected = previous_value + (next_value - previous_value) * t;
+                    (expected - value).abs() <= THIN_TOLERANCE
+                }
+                _ => false,
+            };
+
+            if !redundant {
+                self.pass[kept] = (sample, value);
+                kept += 1;
+            }
+        }
+        self.pass.truncate(kept);
+
+        let room = self.scratch.capacity().min(self.samples.capacity());
+        let index = self.points.partition_point(|p| p.beat < start);
+        let after = self.points.partition_point(|p| p.beat <= end);
+
+        self.scratch.clear();
+        self.scratch
+            .extend_from_slice(&self.points[..index.min(room)]);
+        let written = self
+            .pass
+            .iter()
+            .map(|(sample, value)| Breakpoint::new(to_beat(*sample), *value, CurveShape::Linear));
+        self.scratch
+            .extend(written.take(room.saturating_sub(self.scratch.len())));
+        let rest = &self.points[after..];
+        self.scratch
+            .extend_from_slice(&rest[..rest.len().min(room - self.scratch.len())]);
+
+        std::mem::swap(&mut self.points, &mut self.scratch);
+        self.pass.clear();
+        self.resample();
+    }
+
+    // Opens a gap of `length` beats at `at`, holding the value across it
+    pub fn insert_time(&mut self, at: Beat, length: Beat) {
+        if length <= 0.0 || self.points.is_empty() {
+            return;
+        }
+
+        let split = self.points.partition_point(|p| p.beat < at);
+        let straddles = split > 0 && split < self.points.len();
+        let arriving = self.value_before(at);
+        let leaving = self.value_at(at);
+        let shape = self.shape_at(at);
+
+        let mut after: Vec<Breakpoint> = self.points.drain(split..).collect();
+        if straddles {
+            self.points.push(Breakpoint::new(
+                at,
+                arriving.unwrap_or(0.0),
+                CurveShape::Linear,
+            ));
+            if after[0].beat > at {
+                after.insert(0, Breakpoint::new(at, leaving.unwrap_or(0.0), shape));
+            }
+        }
+
+        self.points.extend(after.into_iter().map(|p| Breakpoint {
+            beat: p.beat + length,
+            ..p
+        }));
+        self.resample();
+        self.reserve();
+    }
+
+    // Cuts `start..end` out and closes the gap
+    pub fn delete_time(&mut self, start: Beat, end: Beat) {
+        if end <= start || self.points.is_empty() {
+            return;
+        }
+
+        let arriving = self.value_before(start);
+        let leaving = self.value_at(end);
+        let shape = self.shape_at(end);
+
+        let first = self.points.partition_point(|p| p.beat < start);
+        let last = self.points.partition_point(|p| p.beat < end);
+        let mut after: Vec<Breakpoint> = self.points.drain(first..).skip(last - first).collect();
+
+        if first > 0 && !after.is_empty() {
+            self.points.push(Breakpoint::new(
+                start,
+                arriving.unwrap_or(0.0),
+                CurveShape::Linear,
+            ));
+            if after[0].beat > end {
+                after.insert(0, Breakpoint::new(end, leaving.unwrap_or(0.0), shape));
+            }
+        }
+
+        self.points.extend(after.into_iter().map(|p| Breakpoint {
+            beat: p.beat - (end - start),
+            ..p
+        }));
+        self.resample();
+    }
+
+    // Repeats `start..end` right after itself
+    pub fn duplicate_time(&mut self, start: Beat, end: Beat) {
+        if end <= start || self.points.is_empty() {
+            return;
+        }
+
+        let length = end - start;
+        let mut copies = vec![Breakpoint::new(
+            start,
+            self.value_at(start).unwrap_or(0.0),
+            self.shape_at(start),
+        )];
+        copies.extend(
+            self.points
+                .iter()
+                .filter(|p| p.beat > start && p.beat < end)
+                .copied(),
+        );
+        copies.push(Breakpoint::new(
+            end,
+            self.value_before(end).unwrap_or(0.0),
+            CurveShape::Linear,
+        ));
+
+        self.insert_time(end, length);
+
+        // The point left at `end` is where the original arrives, keep it
+        let gap = self.points.partition_point(|p| p.beat <= end);
+        let rest = self.points.partition_point(|p| p.beat < end + length);
+        self.points.splice(
+            gap..rest,
+            copies.into_iter().map(|p| Breakpoint {
+                beat: p.beat + length,
+                ..p
+            }),
+        );
+        self.resample();
+        self.reserve();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_thins_the_pass_into_reserved_room() {
+        let mut lane = AutomationLane::new("volume");
+        lane.layout(&Tempo::new(120.0, None), 48000);
+        lane.add_point(Breakpoint::new(0.0, 0.5, CurveShape::Linear));
+        lane.add_point(Breakpoint::new(8.0, 0.5, CurveShape::Linear));
+        lane.set_mode(AutomationMode::Write);
+
+        let capacities = (lane.points.capacity(), lane.scratch.capacity());
+        // A straight ramp over the first two beats, then a jump back (loop)
+        for i in 0..=48 {
+            lane.record(i * 1000, i as f32 / 48.0);
+        }
+        lane.record(0, 1.0);
+        lane.stop();
+
+        let points: Vec<(Beat, f32)> = lane.points.iter().map(|p| (p.beat, p.value)).collect();
+        assert_eq!(points, vec![(0.0, 1.0), (2.0, 1.0), (8.0, 0.5)]);
+        assert_eq!(
+            (lane.points.capacity(), lane.scratch.capacity()),
+            (capacities.1, capacities.0)
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use std::f32::consts::FRAC_PI_2;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use nanoid::nanoid;
+
+use crate::core::wav::wav::read_wav;
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FadeCurve {
+    Linear,
+    EqualPower,
+    Exponential,
+    Logarithmic,
+    SCurve,
+}
+
+impl FadeCurve {
+    // Gain for a fade-in at `t` (0.0 to 1.0), fade-outs run it backwards
+    pub fn gain(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EqualPower => (t * FRAC_PI_2).sin(),
+            FadeCurve::Exponential => t * t,
+            FadeCurve::Logarithmic => 1.0 - (1.0 - t) * (1.0 - t),
+            FadeCurve::SCurve => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Fade {
+    pub length: u64,
+    pub curve: FadeCurve,
+}
+
+impl Default for Fade {
+    fn default() -> Self {
+        Fade {
+            length: 0,
+            curve: FadeCurve::Linear,
+        }
+    }
+}
+
+pub enum ClipSource {
+    File(PathBuf),
+    Buffer,
+}
+
+// A region of audio placed on the timeline. `start` is musical and turned
+// into samples with `layout`; offsets, lengths and fades are in samples of
+// the rate the clip plays at.
+#[derive(Clone)]
+pub struct AudioClip {
+    pub id: String,
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub start: Beat,
+    pub source_offset: u64,
+    pub length: u64,
+    pub gain: f32,
+    pub fade_in: Fade,
+    pub fade_out: Fade,
+    pub reverse: bool,
+    // Repeats this many samples from `source_offset` to fill the clip
+    pub loop_length: Option<u64>,
+    // How far into the loop the clip begins, right halves of splits keep playing
+    // where the loop had got to
+    pub loop_start: u64,
+    samples: Arc<Vec<f32>>,
+    source_sample_rate: u32,
+    start_sample: u64,
+    sample_rate: u32,
+}
+
+impl AudioClip {
+    pub fn from_buffer(name: &str, samples: Vec<f32>, sample_rate: u32) -> Self {
+        let length = samples.len() as u64;
+
+        AudioClip {
+            id: nanoid!(),
+            name: name.to_string(),
+            path: None,
+            start: 0.0,
+            source_offset: 0,
+            length,
+            gain: 1.0,
+            fade_in: Fade::default(),
+            fade_out: Fade::default(),
+            reverse: false,
+            loop_length: None,
+            loop_start: 0,
+            samples: Arc::new(samples),
+            source_sample_rate: sample_rate,
+            start_sample: 0,
+            sample_rate,
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let (sample_rate, samples) = read_wav(path)?;
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Audio Clip".to_string());
+
+        let mut clip = AudioClip::from_buffer(&name, samples, sample_rate);
+        clip.path = Some(path.to_path_buf());
+        Ok(clip)
+    }
+
+    pub fn source(&self) -> ClipSource {
+        match &self.path {
+            Some(path) => ClipSource::File(path.clone()),
+            None => ClipSource::Buffer,
+        }
+    }
+
+    pub fn source_len(&self) -> u64 {
+        self.samples.len() as u64
+    }
+
+    // Copy with a new id sharing the same audio
+    pub fn duplicate(&self) -> Self {
+        AudioClip {
+            id: nanoid!(),
+            ..self.clone()
+        }
+    }
+
+    // Resolves the musical start into samples, call again when the tempo changes
+    pub fn layout(&mut self, tempo: &Tempo, sample_rate: u32) {
+        if sample_rate != self.sample_rate && self.sample_rate > 0 {
+            // Keep lengths the same duration at the new rate
+            let ratio = sample_rate as f64 / self.sample_rate as f64;
+            let scale = |v: u64| (v as f64 * ratio).round() as u64;
+            self.source_offset = scale(self.source_offset);
+            self.length = scale(self.length);
+            self.fade_in.length = scale(self.fade_in.length);
+            self.fade_out.length = scale(self.fade_out.length);
+            self.loop_length = self.loop_length.map(scale);
+            self.loop_start = scale(self.loop_start);
+        }
+
+        self.sample_rate = sample_rate;
+        self.start_sample = tempo.beats_to_samples(self.start, sample_rate);
+    }
+
+    pub fn start_sample(&self) -> u64 {
+        self.start_sample
+    }
+
+    pub fn end_sample(&self) -> u64 {
+        self.start_sample + self.length
+    }
+
+    pub fn overlaps(&self, other: &AudioClip) -> bool {
+        self.start_sample < other.end_sample() && other.start_sample < self.end_sample()
+    }
+
+    fn fade_gain(&self, index: u64) -> f32 {
+        let mut gain = 1.0;
+
+        if index < self.fade_in.length {
+            gain *= self
+                .fade_in
+                .curve
+                .gain(index as f32 / self.fade_in.length as f32);
+        }
+
+        let remaining = self.length - index;
+        if remaining <= self.fade_out.length {
+            gain *= self
+                .fade_out
+                .curve
+                .gain(remaining as f32 / self.fade_out.length as f32);
+        }
+
+        gain
+    }
+
+    // Reads the source at a (fractional) index, silent outside the buffer
+    fn source_at(&self, index: f64) -> f32 {
+        if index < 0.0 {
+            return 0.0;
+        }
+
+        let i = index.floor() as usize;
+        let frac = (index - i as f64) as f32;
+        let a = self.samples.get(i).copied().unwrap_or(0.0);
+        let b = self.samples.get(i + 1).copied().unwrap_or(0.0);
+
+        a + (b - a) * frac
+    }
+
+    // Sample `index` frames into the clip, with looping, reverse, gain and fades
+    pub fn sample_at(&self, index: u64) -> f32 {
+        if index >= self.length {
+            return 0.0;
+        }
+
+        let (region, phase) = match self.loop_length.filter(|l| *l > 0) {
+            Some(length) => (length, self.loop_start),
+            None => (self.length, 0),
+        };
+        let mut offset = (index + phase) % region;
+        if self.reverse {
+            offset = region - 1 - offset;
+        }
+
+        let ratio = self.source_sample_rate as f64 / self.sample_rate.max(1) as f64;
+        let source = (self.source_offset + offset) as f64 * ratio;
+
+        self.source_at(source) * self.gain * self.fade_gain(index)
+    }
+
+    // Splits `at` samples into the clip. Fade-ins stay on the left, fade-outs
+    // on the right, and a looped right half carries on from the loop's phase.
+    pub fn split(&self, at: u64) -> (AudioClip, AudioClip) {
+        let at = at.min(self.length);
+        let mut left = AudioClip {
+            id: nanoid!(),
+            length: at,
+            fade_out: Fade::default(),
+            ..self.clone()
+        };
+        let mut right = AudioClip {
+            id: nanoid!(),
+            length: self.length - at,
+            fade_in: Fade::default(),
+            ..self.clone()
+        };
+
+        match self.loop_length.filter(|l| *l > 0) {
+            Some(length) => right.loop_start = (self.loop_start + at) % length,
+            None if self.reverse => left.source_offset += self.length - at,
+            None => right.source_offset += at,
+        }
+
+        left.fade_in.length = left.fade_in.length.min(left.length);
+        right.fade_out.length = right.fade_out.length.min(right.length);
+        (left, right)
+    }
+
+    // Adds the part of the clip that falls in `position..position + output.len()`
+    pub fn render(&self, position: u64, output: &mut [f32]) {
+        let end = position + output.len() as u64;
+        if end <= self.start_sample || position >= self.end_sample() {
+            return;
+        }
+
+        let from = self.start_sample.max(position);
+        let to = self.end_sample().min(end);
+
+        for timeline in from..to {
+            output[(timeline - position) as usize] += self.sample_at(timeline - self.start_sample);
+        }
+    }
+}
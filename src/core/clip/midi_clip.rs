@@ -0,0 +1,600 @@
+use nanoid::nanoid;
+
+use crate::core::groove::groove::Groove;
+use crate::core::midi::message::MidiMessage;
+pub use crate::types::rhythm::position::position::DEFAULT_PPQ;
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+use crate::types::rhythm::tempo::tempo::Tempo;
+use crate::types::theory::chord_track::{ChordTrack, SnapTarget};
+use crate::types::theory::scale::Key;
+
+pub type Tick = u64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Note {
+    pub pitch: u8,
+    pub velocity: u8,
+    pub release_velocity: u8,
+    pub channel: u8,
+    pub start: Tick,
+    pub length: Tick,
+}
+
+impl Note {
+    pub fn new(pitch: u8, velocity: u8, start: Tick, length: Tick) -> Self {
+        Note {
+            pitch: pitch.min(127),
+            velocity: velocity.min(127),
+            release_velocity: 64,
+            channel: 0,
+            start,
+            length,
+        }
+    }
+
+    pub fn end(&self) -> Tick {
+        self.start + self.length
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiEventKind {
+    ControlChange { controller: u8, value: u8 },
+    // -8192 to 8191, 0 is centre
+    PitchBend(i16),
+    ChannelPressure(u8),
+    PolyPressure { pitch: u8, pressure: u8 },
+    ProgramChange(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MidiEvent {
+    pub tick: Tick,
+    pub channel: u8,
+    pub kind: MidiEventKind,
+}
+
+impl MidiEvent {
+    pub fn message(&self) -> MidiMessage {
+        let channel = self.channel;
+
+        match self.kind {
+            MidiEventKind::ControlChange { controller, value } => MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            },
+            MidiEventKind::PitchBend(value) => MidiMessage::PitchBend { channel, value },
+            MidiEventKind::ChannelPressure(pressure) => {
+                MidiMessage::ChannelPressure { channel, pressure }
+            }
+            MidiEventKind::PolyPressure { pitch, pressure } => MidiMessage::PolyPressure {
+                channel,
+                pitch,
+                pressure,
+            },
+            MidiEventKind::ProgramChange(program) => {
+                MidiMessage::ProgramChange { channel, program }
+            }
+        }
+    }
+
+    // Anything but notes, which clips keep as `Note`s
+    pub fn from_message(tick: Tick, message: MidiMessage) -> Option<Self> {
+        let kind = match message {
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => MidiEventKind::ControlChange { controller, value },
+            MidiMessage::PitchBend { value, .. } => MidiEventKind::PitchBend(value),
+            MidiMessage::ChannelPressure { pressure, .. } => {
+                MidiEventKind::ChannelPressure(pressure)
+            }
+            MidiMessage::PolyPressure {
+                pitch, pressure, ..
+            } => MidiEventKind::PolyPressure { pitch, pressure },
+            MidiMessage::ProgramChange { program, .. } => MidiEventKind::ProgramChange(program),
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } => return None,
+        };
+        Some(MidiEvent {
+            tick,
+            channel: message.channel(),
+            kind,
+        })
+    }
+}
+
+// Notes and controller data for one region of a MIDI track. Ticks are
+// relative to the start of the clip, `start` places it on the timeline.
+#[derive(Clone)]
+pub struct MidiClip {
+    pub id: String,
+    pub name: String,
+    pub ppq: u32,
+    pub start: Beat,
+    pub length: Tick,
+    pub loop_length: Option<Tick>,
+    pub notes: Vec<Note>,
+    pub events: Vec<MidiEvent>,
+    // Played on top of the notes without changing them
+    pub groove: Option<Groove>,
+    start_sample: u64,
+    // Tempo and rate the clip was laid out with
+    tempo: Tempo,
+    sample_rate: u32,
+}
+
+impl Default for MidiClip {
+    fn default() -> Self {
+        MidiClip::new("MIDI Clip", DEFAULT_PPQ as Tick * 4)
+    }
+}
+
+impl MidiClip {
+    pub fn new(name: &str, length: Tick) -> Self {
+        MidiClip {
+            id: nanoid!(),
+            name: name.to_string(),
+            ppq: DEFAULT_PPQ,
+            start: 0.0,
+            length,
+            loop_length: None,
+            notes: Vec::new(),
+            events: Vec::new(),
+            groove: None,
+            start_sample: 0,
+            tempo: Tempo::default(),
+            sample_rate: 0,
+        }
+    }
+
+    // Copy with a new id
+    pub fn duplicate(&self) -> Self {
+        MidiClip {
+            id: nanoid!(),
+            ..self.clone()
+        }
+    }
+
+    // Resolves the clip onto the sample timeline, call again when the tempo changes
+    pub fn layout(&mut self, tempo: &Tempo, sample_rate: u32) {
+        self.tempo = tempo.clone();
+        self.sample_rate = sample_rate;
+        self.start_sample = tempo.beats_to_samples(self.start, sample_rate);
+    }
+
+    // Moves the clip to `start` on the tempo it was laid out with, which is
+    // not copied again, so it is safe on the audio thread
+    pub fn place(&mut self, start: Beat) {
+        self.start = start;
+        self.start_sample = self.tempo.beats_to_samples(start, self.sample_rate);
+    }
+
+    pub fn start_sample(&self) -> u64 {
+        self.start_sample
+    }
+
+    pub fn end_sample(&self) -> u64 {
+        self.tick_to_sample(self.length)
+    }
+
+    // Timeline sample of a tick inside the clip, following tempo changes
+    pub fn tick_to_sample(&self, tick: Tick) -> u64 {
+        let beats = self.start as f64 + tick as f64 / self.ppq as f64;
+        (self.tempo.beats_to_sample(beats, self.sample_rate).round() as u64).max(self.start_sample)
+    }
+
+    // First tick at or after a timeline sample, so that
+    // `sample_to_tick(tick_to_sample(t)) == t`
+    pub fn sample_to_tick(&self, sample: u64) -> Tick {
+        if self.sample_rate == 0 || sample <= self.start_sample {
+            return 0;
+        }
+
+        let beats = self.tempo.sample_to_beats(sample as f64, self.sample_rate) - self.start as f64;
+        let mut tick = (beats * self.ppq as f64).floor().max(0.0) as Tick;
+        while self.tick_to_sample(tick) < sample {
+            tick += 1;
+        }
+        while tick > 0 && self.tick_to_sample(tick - 1) >= sample {
+            tick -= 1;
+        }
+        tick
+    }
+
+    pub fn beats_to_ticks(&self, beats: Beat) -> Tick {
+        (beats.max(0.0) as f64 * self.ppq as f64).round() as Tick
+    }
+
+    pub fn ticks_to_beats(&self, ticks: Tick) -> Beat {
+        (ticks as f64 / self.ppq as f64) as Beat
+    }
+
+    fn sort(&mut self) {
+        self.notes.sort_by_key(|n| (n.start, n.pitch));
+        self.events.sort_by_key(|e| e.tick);
+    }
+
+    pub fn add_note(&mut self, note: Note) {
+        let index = self
+            .notes
+            .partition_point(|n| (n.start, n.pitch) <= (note.start, note.pitch));
+        self.notes.insert(index, note);
+    }
+
+    pub fn add_event(&mut self, event: MidiEvent) {
+        let index = self.events.partition_point(|e| e.tick <= event.tick);
+        self.events.insert(index, event);
+    }
+
+    pub fn remove_notes_in(&mut self, start: Tick, end: Tick) {
+        self.notes.retain(|n| n.start < start || n.start >= end);
+    }
+
+    pub fn notes_in(&self, start: Tick, end: Tick) -> impl Iterator<Item = &Note> {
+        self.notes
+            .iter()
+            .filter(move |n| n.start >= start && n.start < end)
+    }
+
+    pub fn events_in(&self, start: Tick, end: Tick) -> impl Iterator<Item = &MidiEvent> {
+        self.events
+            .iter()
+            .filter(move |e| e.tick >= start && e.tick < end)
+    }
+
+    // Length of one pass through the clip when it loops
+    pub fn cycle_length(&self) -> Tick {
+        self.loop_length.filter(|l| *l > 0).unwrap_or(self.length)
+    }
+
+    // Notes that would leave the MIDI range stay where they are
+    pub fn transpose(&mut self, semitones: i8) {
+        for note in &mut self.notes {
+            let pitch = note.pitch as i16 + semitones as i16;
+            if (0..=127).contains(&pitch) {
+                note.pitch = pitch as u8;
+            }
+        }
+
+        for event in &mut self.events {
+            if let MidiEventKind::PolyPressure { pitch, .. } = &mut event.kind {
+                let moved = *pitch as i16 + semitones as i16;
+                if (0..=127).contains(&moved) {
+                    *pitch = moved as u8;
+                }
+            }
+        }
+        self.sort();
+    }
+
+    // Moves every note to its nearest note in `key`
+    pub fn snap_to_key(&mut self, key: &Key) {
+        self.map_pitches(|_, pitch| key.snap(pitch));
+    }
+
+    // Snaps each note to the key or chord sounding where it starts on the
+    // chord track
+    pub fn snap_to_chord_track(&mut self, chord_track: &ChordTrack, target: SnapTarget) {
+        let (start, ppq) = (self.start, self.ppq.max(1) as f64);
+        self.map_pitches(|tick, pitch| {
+            let beat = start + (tick as f64 / ppq) as Beat;
+            chord_track.snap(beat, pitch, target)
+        });
+    }
+
+    // Moves notes `steps` degrees along `key`, snapping those outside it
+    pub fn transpose_diatonic(&mut self, key: &Key, steps: i32) {
+        self.map_pitches(|_, pitch| key.transpose(pitch, steps));
+    }
+
+    // Rewrites a part written in `from` for `to`, each degree keeping its place
+    pub fn transpose_to_key(&mut self, from: &Key, to: &Key) {
+        self.map_pitches(|_, pitch| from.transpose_to(to, pitch));
+    }
+
+    fn map_pitches<F: Fn(Tick, u8) -> u8>(&mut self, pitch_fn: F) {
+        for note in &mut self.notes {
+            note.pitch = pitch_fn(note.start, note.pitch);
+        }
+        for event in &mut self.events {
+            if let MidiEventKind::PolyPressure { pitch, .. } = &mut event.kind {
+                *pitch = pitch_fn(event.tick, *pitch);
+            }
+        }
+        self.sort();
+    }
+
+    // Moves note starts towards the nearest grid line. `strength` of 1.0
+    // snaps fully, `ends` also quantizes note ends. With a groove the grid
+    // lines are moved by its timing first, so swing is quantized in;
+    // velocities are left to `apply_groove`.
+    pub fn quantize(&mut self, grid: Tick, strength: f32, ends: bool, groove: Option<&Groove>) {
+        if grid == 0 {
+            return;
+        }
+
+        let strength = strength.clamp(0.0, 1.0) as f64;
+        let ppq = self.ppq.max(1) as f64;
+        let start = self.start as f64;
+        let line = |tick: Tick| -> f64 {
+            let line = (tick as f64 / grid as f64).round() * grid as f64;
+            match groove {
+                Some(groove) => (groove.apply(start + line / ppq, 1).0 - start) * ppq,
+                None => line,
+            }
+        };
+        let snap = |tick: Tick| -> Tick {
+            let moved = tick as f64 + (line(tick) - tick as f64) * strength;
+            moved.round().max(0.0) as Tick
+        };
+
+        for note in &mut self.notes {
+            let end = note.end();
+            note.start = snap(note.start);
+
+            let end = if ends { snap(end) } else { end };
+            note.length = end.saturating_sub(note.start).max(1);
+        }
+        self.sort();
+    }
+
+    // Where a note at `tick` plays and how loud once the clip's groove is
+    // applied
+    pub fn grooved(&self, tick: Tick, velocity: u8) -> (Tick, u8) {
+        match &self.groove {
+            Some(groove) => self.groove_note(groove, tick, velocity),
+            None => (tick, velocity),
+        }
+    }
+
+    fn groove_note(&self, groove: &Groove, tick: Tick, velocity: u8) -> (Tick, u8) {
+        let ppq = self.ppq.max(1) as f64;
+        let (beat, velocity) = groove.apply(self.start as f64 + tick as f64 / ppq, velocity);
+        let tick = ((beat - self.start as f64) * ppq).round().max(0.0) as Tick;
+        (tick, velocity)
+    }
+
+    // Moves notes and scales velocities by `groove` for good. Lengths are kept.
+    pub fn apply_groove(&mut self, groove: &Groove) {
+        let moved: Vec<(Tick, u8)> = self
+            .notes
+            .iter()
+            .map(|n| self.groove_note(groove, n.start, n.velocity))
+            .collect();
+
+        for (note, (start, velocity)) in self.notes.iter_mut().zip(moved) {
+            note.start = start;
+            note.velocity = velocity;
+        }
+        self.sort();
+    }
+
+    // Stretches every note up to the next note onset
+    pub fn legato(&mut self) {
+        self.sort();
+
+        let starts: Vec<Tick> = self.notes.iter().map(|n| n.start).collect();
+        for note in &mut self.notes {
+            let next = starts
+                .iter()
+                .find(|s| **s > note.start)
+                .copied()
+                .unwrap_or(self.length.max(note.end()));
+            note.length = next - note.start;
+        }
+    }
+
+    // Copy with every pass of the loop written out as notes and events,
+    // notes crossing a loop boundary ending there as they play
+    pub fn unrolled(&self) -> MidiClip {
+        let cycle = self.cycle_length().max(1);
+        if cycle >= self.length {
+            return MidiClip {
+                loop_length: None,
+                ..self.clone()
+            };
+        }
+
+        let mut clip = MidiClip {
+            loop_length: None,
+            notes: Vec::new(),
+            events: Vec::new(),
+            ..self.clone()
+        };
+        let mut pass_start: Tick = 0;
+        while pass_start < self.length {
+            let pass_end = (pass_start + cycle).min(self.length);
+
+            for note in self.notes.iter().take_while(|n| n.start < cycle) {
+                let start = pass_start + note.start;
+                if start < pass_end {
+                    clip.notes.push(Note {
+                        start,
+                        length: note.length.min(pass_end - start),
+                        ..note.clone()
+                    });
+                }
+            }
+            for event in self.events.iter().take_while(|e| e.tick < cycle) {
+                let tick = pass_start + event.tick;
+                if tick < pass_end {
+                    clip.events.push(MidiEvent {
+                        tick,
+                        ..event.clone()
+                    });
+                }
+            }
+            pass_start += cycle;
+        }
+        clip
+    }
+
+    // Splits at `at` ticks, cutting notes that cross the split in two. A
+    // looped clip is unrolled first, so both halves play every pass.
+    pub fn split(&self, at: Tick) -> (MidiClip, MidiClip) {
+        let clip = self.unrolled();
+        let at = at.min(clip.length);
+
+        let mut left = MidiClip {
+            id: nanoid!(),
+            length: at,
+            loop_length: None,
+            notes: Vec::new(),
+            events: Vec::new(),
+            ..clip.clone()
+        };
+        let mut right = MidiClip {
+            id: nanoid!(),
+            start: clip.start + clip.ticks_to_beats(at),
+            length: clip.length - at,
+            loop_length: None,
+            notes: Vec::new(),
+            events: Vec::new(),
+            ..clip.clone()
+        };
+
+        for note in &clip.notes {
+            if note.start < at {
+                left.notes.push(Note {
+                    length: note.length.min(at - note.start),
+                    ..note.clone()
+                });
+            }
+            if note.end() > at {
+                let start = note.start.max(at);
+                right.notes.push(Note {
+                    start: start - at,
+                    length: note.end() - start,
+                    ..note.clone()
+                });
+            }
+        }
+
+        for event in &clip.events {
+            if event.tick < at {
+                left.events.push(event.clone());
+            } else {
+                right.events.push(MidiEvent {
+                    tick: event.tick - at,
+                    ..event.clone()
+                });
+            }
+        }
+
+        (left, right)
+    }
+
+    // Pulls another clip's notes and events into this one, keeping their
+    // timeline positions and converting between resolutions
+    pub fn merge(&mut self, other: &MidiClip) {
+        let ppq = self.ppq as f64;
+        let scale = |tick: Tick| (tick as f64 * ppq / other.ppq as f64).round() as i64;
+        let offset = ((other.start - self.start) as f64 * ppq).round() as i64;
+
+        if offset < 0 {
+            // The other clip starts earlier, so this one grows to the left
+            let shift = (-offset) as Tick;
+            for note in &mut self.notes {
+                note.start += shift;
+            }
+            for event in &mut self.events {
+                event.tick += shift;
+            }
+            self.length += shift;
+            self.start = other.start;
+        }
+
+        let base = offset.max(0);
+        for note in &other.notes {
+            self.notes.push(Note {
+                start: (base + scale(note.start)) as Tick,
+                length: scale(note.length) as Tick,
+                ..note.clone()
+            });
+        }
+        for event in &other.events {
+            self.events.push(MidiEvent {
+                tick: (base + scale(event.tick)) as Tick,
+                ..event.clone()
+            });
+        }
+
+        self.length = self.length.max((base + scale(other.length)) as Tick);
+        self.sort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(clip: &MidiClip) -> Vec<(Tick, Tick)> {
+        clip.notes.iter().map(|n| (n.start, n.length)).collect()
+    }
+
+    #[test]
+    fn quantizes_to_a_swung_grid() {
+        let mut clip = MidiClip::new("hats", 960);
+        clip.start = 1.0;
+        for start in [10, 250, 470, 700] {
+            clip.add_note(Note::new(42, 100, start, 200));
+        }
+
+        let mut straight = clip.clone();
+        straight.quantize(240, 1.0, false, None);
+        assert_eq!(
+            starts(&straight),
+            [(0, 210), (240, 210), (480, 190), (720, 180)]
+        );
+
+        // Off-beat sixteenths land halfway to the next line
+        let swing = Groove::swing(0.25, 1.0);
+        let mut swung = clip.clone();
+        swung.quantize(240, 1.0, true, Some(&swing));
+        assert_eq!(
+            starts(&swung),
+            [(0, 360), (360, 120), (480, 360), (840, 120)]
+        );
+        assert!(swung.notes.iter().all(|n| n.velocity == 100));
+
+        let mut half = clip;
+        half.quantize(240, 0.5, false, Some(&swing));
+        assert_eq!(half.notes[1].start, 305);
+    }
+
+    #[test]
+    fn splitting_a_loop_keeps_every_pass() {
+        // Four passes of one beat, the note crossing the loop end is cut there
+        let mut clip = MidiClip::new("loop", 960 * 4);
+        clip.loop_length = Some(960);
+        clip.add_note(Note::new(60, 100, 0, 240));
+        clip.add_note(Note::new(62, 100, 720, 480));
+        clip.add_event(MidiEvent {
+            tick: 480,
+            channel: 0,
+            kind: MidiEventKind::ControlChange {
+                controller: 1,
+                value: 64,
+            },
+        });
+
+        let (left, right) = clip.split(1440);
+        assert_eq!(left.loop_length, None);
+        assert_eq!(right.loop_length, None);
+        assert_eq!(starts(&left), [(0, 240), (720, 240), (960, 240)]);
+        assert_eq!(right.start, 1.5);
+        assert_eq!(
+            starts(&right),
+            [
+                (240, 240),
+                (480, 240),
+                (1200, 240),
+                (1440, 240),
+                (2160, 240)
+            ]
+        );
+        let ticks = |c: &MidiClip| c.events.iter().map(|e| e.tick).collect::<Vec<_>>();
+        assert_eq!(ticks(&left), [480]);
+        assert_eq!(ticks(&right), [0, 960, 1920]);
+    }
+}
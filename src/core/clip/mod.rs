@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod clip;
+pub mod midi_clip;
@@ -0,0 +1,42 @@
+// Fixed-capacity ring buffer used to hold back a node's output
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    write: usize,
+}
+
+impl DelayLine {
+    pub fn new(capacity: usize) -> Self {
+        DelayLine {
+            buffer: vec![0.0; capacity + 1],
+            write: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len() - 1
+    }
+
+    // Only call outside the audio callback, growing the line allocates
+    pub fn reserve(&mut self, capacity: usize) {
+        if capacity > self.capacity() {
+            self.buffer = vec![0.0; capacity + 1];
+            self.write = 0;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill(0.0);
+    }
+
+    // Delays `samples` in place by `delay` frames
+    pub fn process(&mut self, samples: &mut [f32], delay: usize) {
+        let delay = delay.min(self.capacity());
+        let len = self.buffer.len();
+
+        for sample in samples.iter_mut() {
+            self.buffer[self.write] = *sample;
+            *sample = self.buffer[(self.write + len - delay) % len];
+            self.write = (self.write + 1) % len;
+        }
+    }
+}
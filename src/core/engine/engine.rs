@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::delay::DelayLine;
+use super::guard::RtGuard;
+use super::node::{Node, ProcessContext};
+use super::routing::{self, Route};
+use super::transport::Transport;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+pub const DEFAULT_BLOCK_SIZE: usize = 512;
+
+// Drives a graph of nodes one block at a time from whatever backend owns the
+// audio callback. Each node renders into its own buffer and the results are
+// summed into the callback's output.
+pub struct Engine {
+    pub sample_rate: u32,
+    pub block_size: usize,
+    pub transport: Transport,
+    pub input_latency: u32,
+    // Delay parallel paths so nodes with latency stay phase-aligned
+    pub compensate_latency: bool,
+    pub nodes: Vec<Box<dyn Node>>,
+    outputs: Vec<Vec<f32>>,
+    inputs: Vec<Vec<f32>>,
+    routed: Vec<bool>,
+    // How far into the routed inputs this cycle has rendered
+    routed_offset: usize,
+    // Per node id, sized by `prepare_graph` so the callback never allocates
+    delays: HashMap<String, DelayLine>,
+    // Per graph index, only followed while there is one for every graph node
+    routes: Vec<Route>,
+    order: Vec<usize>,
+    // What plays into each summing node this block
+    sums: Vec<Vec<f32>>,
+    guard: Option<RtGuard>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new(DEFAULT_SAMPLE_RATE, DEFAULT_BLOCK_SIZE)
+    }
+}
+
+impl Engine {
+    pub fn new(sample_rate: u32, block_size: usize) -> Self {
+        Engine {
+            sample_rate,
+            block_size,
+            transport: Transport::default(),
+            input_latency: 0,
+            compensate_latency: true,
+            nodes: Vec::new(),
+            outputs: Vec::new(),
+            inputs: Vec::new(),
+            routed: Vec::new(),
+            routed_offset: 0,
+            delays: HashMap::new(),
+            routes: Vec::new(),
+            order: Vec::new(),
+            sums: Vec::new(),
+            guard: None,
+        }
+    }
+
+    pub fn add_node(&mut self, mut node: Box<dyn Node>) {
+        node.prepare(self.sample_rate, self.block_size);
+        let capacity = self.delays.values().map(|d| d.capacity()).max();
+        let capacity = capacity.unwrap_or(0).max(node.latency() as usize);
+        self.delays
+            .insert(node.id().to_string(), DelayLine::new(capacity));
+        self.nodes.push(node);
+        self.reserve_buffers(self.nodes.len());
+        self.reserve_latency(capacity as u32);
+    }
+
+    pub fn remove_node(&mut self, name: &str) -> Option<Box<dyn Node>> {
+        let index = self.nodes.iter().position(|n| n.name() == name)?;
+        let node = self.nodes.remove(index);
+        self.delays.remove(node.id());
+        Some(node)
+    }
+
+    // Re-prepares every node, call whenever the backend changes its configuration
+    pub fn prepare(&mut self, sample_rate: u32, block_size: usize) {
+        self.sample_rate = sample_rate;
+        self.block_size = block_size;
+
+        let buffers = self.outputs.iter_mut().chain(self.inputs.iter_mut());
+        for buffer in buffers.chain(self.sums.iter_mut()) {
+            buffer.resize(block_size, 0.0);
+        }
+
+        for node in &mut self.nodes {
+            node.prepare(sample_rate, block_size);
+        }
+
+        self.reserve_latency(self.latency());
+    }
+
+    // Largest latency among the engine's own nodes
+    pub fn latency(&self) -> u32 {
+        self.nodes.iter().map(|n| n.latency()).max().unwrap_or(0)
+    }
+
+    // Largest latency of a graph rendered alongside the engine's nodes
+    pub fn graph_latency<N: Node>(&self, graph: &[N]) -> u32 {
+        graph
+            .iter()
+            .map(|n| n.latency())
+            .max()
+            .unwrap_or(0)
+            .max(self.latency())
+    }
+
+    // Makes sure compensation up to `latency` frames will not allocate
+    pub fn reserve_latency(&mut self, latency: u32) {
+        for delay in self.delays.values_mut() {
+            delay.reserve(latency as usize);
+        }
+    }
+
+    // Gives every node of a graph rendered alongside the engine's nodes its
+    // delay line, sized for the graph's latency, and drops the lines of
+    // nodes that left. Call outside the callback whenever the graph or a
+    // node's latency changes.
+    pub fn prepare_graph<N: Node>(&mut self, graph: &[N]) {
+        self.reserve_buffers(graph.len() + self.nodes.len());
+
+        let latency = self.graph_latency(graph) as usize;
+        let ids = || {
+            graph
+                .iter()
+                .map(|n| n.id())
+                .chain(self.nodes.iter().map(|n| n.id()))
+        };
+        self.delays.retain(|id, _| ids().any(|i| i == id));
+        for id in ids() {
+            match self.delays.get_mut(id) {
+                Some(line) => line.reserve(latency),
+                None => {
+                    self.delays.insert(id.to_string(), DelayLine::new(latency));
+                }
+            }
+        }
+    }
+
+    // Routes the graph rendered alongside the engine's nodes, one route per
+    // graph node. Fails without changing anything when a node plays into one
+    // that does not sum or routing loops back on itself.
+    pub fn set_routes(&mut self, routes: Vec<Route>) -> anyhow::Result<()> {
+        self.order = routing::render_order(&routes)?;
+        self.sums.resize(routes.len(), Vec::new());
+        for sum in &mut self.sums {
+            sum.resize(self.block_size, 0.0);
+        }
+        self.routes = routes;
+        Ok(())
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    // Makes sure a graph of `count` nodes can render without allocating
+    pub fn reserve_graph(&mut self, count: usize) {
+        self.reserve_buffers(count + self.nodes.len());
+    }
+
+    fn reserve_buffers(&mut self, total: usize) {
+        if self.outputs.len() < total {
+            self.outputs.resize(total, vec![0.0; self.block_size]);
+            self.inputs.resize(total, vec![0.0; self.block_size]);
+            self.routed.resize(total, false);
+        }
+    }
+
+    // Feeds the node at `index` its own input for the rest of the cycle
+    // instead of the shared one, e.g. from a per-track backend port
+    pub fn route_input(&mut self, index: usize, samples: &[f32]) {
+        self.reserve_buffers(index + 1);
+
+        let buffer = &mut self.inputs[index];
+        if buffer.len() < samples.len() {
+            buffer.resize(samples.len(), 0.0);
+        }
+        buffer[..samples.len()].copy_from_slice(samples);
+        buffer[samples.len()..].fill(0.0);
+        self.routed[index] = true;
+    }
+
+    pub fn enable_guard(&mut self) {
+        if self.guard.is_none() {
+            self.guard = Some(RtGuard::default());
+        }
+    }
+
+    pub fn disable_guard(&mut self) -> Option<RtGuard> {
+        self.guard.take()
+    }
+
+    pub fn guard(&self) -> Option<&RtGuard> {
+        self.guard.as_ref()
+    }
+
+    pub fn guard_mut(&mut self) -> Option<&mut RtGuard> {
+        self.guard.as_mut()
+    }
+
+    // Name of the node at `index` in render order, the graph's nodes first
+    pub fn node_name<'a, N: Node>(&'a self, graph: &'a [N], index: usize) -> Option<&'a str> {
+        match index.checked_sub(graph.len()) {
+            None => Some(graph[index].name()),
+            Some(own) => self.nodes.get(own).map(|n| n.name()),
+        }
+    }
+
+    // The guard's violations with the names of the nodes they happened in
+    pub fn report<N: Node>(&self, graph: &[N]) -> Vec<String> {
+        self.guard
+            .iter()
+            .flat_map(|g| g.violations())
+            .map(|v| {
+                let name = self.node_name(graph, v.node).unwrap_or("removed node");
+                format!("{} ({})", v, name)
+            })
+            .collect()
+    }
+
+    // Most recent block rendered by the node at `index`
+    pub fn node_output(&self, index: usize) -> Option<&[f32]> {
+        self.outputs.get(index).map(|o| o.as_slice())
+    }
+
+    pub fn render(&mut self, input: &[f32], output: &mut [f32]) {
+        self.render_graph::<Box<dyn Node>>(&mut [], input, output);
+        self.end_cycle();
+    }
+
+    // Drops the inputs routed for this cycle, a cycle may be rendered in any
+    // number of `render_graph` calls before it ends
+    pub fn end_cycle(&mut self) {
+        self.routed.fill(false);
+        self.routed_offset = 0;
+    }
+
+    // Renders an externally owned graph followed by the engine's own nodes.
+    // Output longer than the block size is rendered in block sized chunks.
+    pub fn render_graph<N: Node>(&mut self, graph: &mut [N], input: &[f32], output: &mut [f32]) {
+        output.fill(0.0);
+
+        let mut nodes = std::mem::take(&mut self.nodes);
+        let block_size = self.block_size.max(1);
+        let mut offset = 0;
+
+        while offset < output.len() {
+            // Blocks end at the loop end so the wrap lands between blocks
+            let frames = self
+                .transport
+                .frames_until_wrap(block_size.min(output.len() - offset));
+            let block_input = input
+                .get(offset..(offset + frames).min(input.len()))
+                .unwrap_or(&[]);
+            let block_output = &mut output[offset..offset + frames];
+
+            let routed_offset = self.routed_offset + offset;
+            self.render_block(graph, &mut nodes, routed_offset, block_input, block_output);
+            self.transport.advance(frames);
+            offset += frames;
+        }
+
+        self.routed_offset += output.len();
+        self.nodes = nodes;
+    }
+
+    fn render_block<N: Node>(
+        &mut self,
+        graph: &mut [N],
+        nodes: &mut [Box<dyn Node>],
+        offset: usize,
+        input: &[f32],
+        output: &mut [f32],
+    ) {
+        let frames = output.len();
+        let context = ProcessContext {
+            sample_rate: self.sample_rate,
+            frames,
+            position: self.transport.position,
+            playing: self.transport.playing,
+            input_latency: self.input_latency,
+        };
+
+        if self.outputs.len() < graph.len() + nodes.len() {
+            // Only happens when the graph grew without `reserve_graph`
+            self.reserve_buffers(graph.len() + nodes.len());
+        }
+
+        let latency = if self.compensate_latency {
+            graph
+                .iter()
+                .map(|n| n.latency())
+                .chain(nodes.iter().map(|n| n.latency()))
+                .max()
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        let started = Instant::now();
+        let mut slowest: Option<(usize, Duration)> = None;
+
+        // Without a route per node the graph plays straight to the output
+        let routing = self.routes.len() == graph.len();
+        if routing {
+            for sum in &mut self.sums {
+                if sum.len() < frames {
+                    sum.resize(frames, 0.0);
+                }
+                sum[..frames].fill(0.0);
+            }
+        }
+
+        let graph_len = graph.len();
+        for step in 0..graph_len + nodes.len() {
+            let index = match self.order.get(step) {
+                Some(index) if routing && step < graph_len => *index,
+                _ => step,
+            };
+            let node: &mut dyn Node = match index.checked_sub(graph_len) {
+                None => &mut graph[index],
+                Some(own) => nodes[own].as_mut(),
+            };
+            let route = self
+                .routes
+                .get(index)
+                .filter(|_| routing && index < graph_len);
+
+            let buffer = &mut self.outputs[index];
+            if buffer.len() < frames {
+                buffer.resize(frames, 0.0);
+            }
+            let buffer = &mut buffer[..frames];
+            buffer.fill(0.0);
+
+            // Taken out while the node plays it, putting it back does not allocate
+            let summed = match route {
+                Some(route) if route.summing => std::mem::take(&mut self.sums[index]),
+                _ => Vec::new(),
+            };
+            let input = if route.is_some_and(|r| r.summing) {
+                &summed[..frames]
+            } else if self.routed[index] {
+                let routed = &self.inputs[index];
+                &routed[offset.min(routed.len())..(offset + frames).min(routed.len())]
+            } else {
+                input
+            };
+
+            if let Some(guard) = self.guard.as_mut() {
+                guard.begin();
+                node.process(&context, input, buffer);
+                let elapsed = guard.end(index);
+
+                if slowest.is_none_or(|(_, s)| elapsed > s) {
+                    slowest = Some((index, elapsed));
+                }
+            } else {
+                node.process(&context, input, buffer);
+            }
+
+            if route.is_some_and(|r| r.summing) {
+                self.sums[index] = summed;
+            } else {
+                // A node added or slowed down without `prepare_graph` is
+                // compensated as far as its line reaches. What plays into a
+                // summing node is already aligned.
+                let delay = (latency - node.latency().min(latency)) as usize;
+                if let Some(line) = self.delays.get_mut(node.id()).filter(|_| delay > 0) {
+                    line.process(buffer, delay);
+                }
+            }
+
+            let target = match route.and_then(|r| r.output) {
+                Some(bus) => &mut self.sums[bus][..frames],
+                None => &mut *output,
+            };
+            for (out, sample) in target.iter_mut().zip(buffer.iter()) {
+                *out += sample;
+            }
+
+            // Sends are taken after the node's own level
+            for (bus, level) in route.iter().flat_map(|r| r.sends.iter()) {
+                for (out, sample) in self.sums[*bus][..frames].iter_mut().zip(buffer.iter()) {
+                    *out += sample * level;
+                }
+            }
+        }
+
+        if let (Some(guard), Some((index, _))) = (self.guard.as_mut(), slowest) {
+            let elapsed = started.elapsed();
+            let budget = Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+
+            if elapsed > budget {
+                guard.overrun(index, elapsed, budget);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts up by `step`, reporting `latency` it does not really have
+    struct Ramp {
+        id: &'static str,
+        step: f32,
+        value: f32,
+        latency: u32,
+    }
+
+    impl Node for Ramp {
+        fn name(&self) -> &str {
+            "Ramp"
+        }
+
+        fn id(&self) -> &str {
+            self.id
+        }
+
+        fn process(&mut self, _: &ProcessContext, _: &[f32], output: &mut [f32]) {
+            for sample in output {
+                self.value += self.step;
+                *sample = self.value;
+            }
+        }
+
+        fn latency(&self) -> u32 {
+            self.latency
+        }
+    }
+
+    // Plays back whatever it is given
+    struct Pass;
+
+    impl Node for Pass {
+        fn name(&self) -> &str {
+            "Pass"
+        }
+
+        fn process(&mut self, _: &ProcessContext, input: &[f32], output: &mut [f32]) {
+            output.copy_from_slice(input);
+        }
+    }
+
+    #[test]
+    fn sums_outputs_and_sends_into_a_bus() {
+        let ramp = |id, step| Ramp {
+            id,
+            step,
+            value: 0.0,
+            latency: 0,
+        };
+        let mut graph: Vec<Box<dyn Node>> = vec![
+            Box::new(Pass),
+            Box::new(ramp("a", 1.0)),
+            Box::new(ramp("b", 2.0)),
+        ];
+        let mut engine = Engine::new(48000, 4);
+        engine.prepare_graph(&graph);
+
+        // The bus comes first in the graph but renders after what plays into it
+        let routes = vec![
+            Route {
+                summing: true,
+                ..Route::default()
+            },
+            Route {
+                output: Some(0),
+                ..Route::default()
+            },
+            Route {
+                sends: vec![(0, 0.5)],
+                ..Route::default()
+            },
+        ];
+        engine.set_routes(routes).unwrap();
+
+        // The bus hears the hardware input nowhere
+        let mut output = [0.0; 4];
+        engine.render_graph(&mut graph, &[9.0; 4], &mut output);
+        assert_eq!(output, [4.0, 8.0, 12.0, 16.0]);
+        assert_eq!(engine.node_output(0), Some(&[2.0, 4.0, 6.0, 8.0][..]));
+
+        let looped = vec![
+            Route {
+                output: Some(1),
+                summing: true,
+                ..Route::default()
+            },
+            Route {
+                output: Some(0),
+                summing: true,
+                ..Route::default()
+            },
+            Route::default(),
+        ];
+        assert!(engine.set_routes(looped).is_err());
+        let into_a_track = vec![
+            Route::default(),
+            Route::default(),
+            Route {
+                output: Some(1),
+                ..Route::default()
+            },
+        ];
+        assert!(engine.set_routes(into_a_track).is_err());
+        assert_eq!(engine.routes()[1].output, Some(0));
+    }
+
+    #[test]
+    fn delay_lines_follow_their_node_through_the_graph() {
+        let ramp = |id, step, latency| Ramp {
+            id,
+            step,
+            value: 0.0,
+            latency,
+        };
+        // "b" is silent, "a" is held back two frames to match it
+        let mut graph = vec![ramp("a", 1.0, 0), ramp("b", 0.0, 2)];
+        let mut engine = Engine::new(48000, 4);
+        engine.prepare_graph(&graph);
+
+        let mut output = [0.0; 4];
+        engine.render_graph(&mut graph, &[], &mut output);
+        assert_eq!(output, [0.0, 0.0, 1.0, 2.0]);
+
+        graph.reverse();
+        engine.prepare_graph(&graph);
+        engine.render_graph(&mut graph, &[], &mut output);
+        assert_eq!(output, [3.0, 4.0, 5.0, 6.0]);
+    }
+}
@@ -0,0 +1,298 @@
+// Real-time safety guard for the render callback.
+//
+// Allocations are only seen with `GuardedAllocator` as the global allocator,
+// which the `rt-guard` feature installs, and blocking locks are only seen
+// when they go through `GuardedMutex`. Both cost a thread-local lookup and
+// nothing else while the guard is not tracking the current thread.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::sync::{LockResult, MutexGuard, TryLockResult};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<usize> = const { Cell::new(0) };
+    static LOCKS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn is_tracking() -> bool {
+    TRACKING.try_with(|t| t.get()).unwrap_or(false)
+}
+
+fn note_allocation(size: usize) {
+    if is_tracking() {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        let _ = ALLOCATED_BYTES.try_with(|b| b.set(b.get() + size));
+    }
+}
+
+fn note_lock() {
+    if is_tracking() {
+        let _ = LOCKS.try_with(|l| l.set(l.get() + 1));
+    }
+}
+
+pub struct GuardedAllocator<A: GlobalAlloc = System> {
+    inner: A,
+}
+
+impl GuardedAllocator<System> {
+    pub const fn new() -> Self {
+        GuardedAllocator { inner: System }
+    }
+}
+
+impl Default for GuardedAllocator<System> {
+    fn default() -> Self {
+        GuardedAllocator::new()
+    }
+}
+
+impl<A: GlobalAlloc> GuardedAllocator<A> {
+    pub const fn wrap(inner: A) -> Self {
+        GuardedAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for GuardedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        note_allocation(layout.size());
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        note_allocation(layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        note_allocation(new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+}
+
+// A `std::sync::Mutex` whose blocking `lock` is reported by the guard.
+// `try_lock` never blocks, so it is allowed in the callback.
+#[derive(Default)]
+pub struct GuardedMutex<T> {
+    inner: std::sync::Mutex<T>,
+}
+
+impl<T> GuardedMutex<T> {
+    pub fn new(value: T) -> Self {
+        GuardedMutex {
+            inner: std::sync::Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        note_lock();
+        self.inner.lock()
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+}
+
+pub enum ViolationKind {
+    Allocation { count: usize, bytes: usize },
+    BlockingLock { count: usize },
+    DeadlineOverrun { elapsed: Duration, budget: Duration },
+}
+
+// `node` is the node's place in render order, `Engine::node_name` turns it
+// into a name off the audio thread
+pub struct Violation {
+    pub node: usize,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ViolationKind::Allocation { count, bytes } => write!(
+                f,
+                "node {}: {} heap allocation(s), {} bytes, in render callback",
+                self.node, count, bytes
+            ),
+            ViolationKind::BlockingLock { count } => write!(
+                f,
+                "node {}: {} blocking lock(s) in render callback",
+                self.node, count
+            ),
+            ViolationKind::DeadlineOverrun { elapsed, budget } => write!(
+                f,
+                "node {}: render took {:?}, budget was {:?}",
+                self.node, elapsed, budget
+            ),
+        }
+    }
+}
+
+// Collects violations for the engine while debug mode is on. Storage is
+// reserved up front and recording pauses tracking, so reporting a problem
+// does not itself show up as one.
+pub struct RtGuard {
+    violations: Vec<Violation>,
+    capacity: usize,
+    dropped: usize,
+    started: Option<Instant>,
+}
+
+impl Default for RtGuard {
+    fn default() -> Self {
+        RtGuard::new(256)
+    }
+}
+
+impl RtGuard {
+    pub fn new(capacity: usize) -> Self {
+        RtGuard {
+            violations: Vec::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+            started: None,
+        }
+    }
+
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        self.dropped = 0;
+        std::mem::replace(&mut self.violations, Vec::with_capacity(self.capacity))
+    }
+
+    // Number of violations that did not fit and were discarded
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty() && self.dropped == 0
+    }
+
+    // Starts tracking the current thread on behalf of a node
+    pub fn begin(&mut self) {
+        ALLOCATIONS.with(|a| a.set(0));
+        ALLOCATED_BYTES.with(|b| b.set(0));
+        LOCKS.with(|l| l.set(0));
+        TRACKING.with(|t| t.set(true));
+        self.started = Some(Instant::now());
+    }
+
+    // Stops tracking and records whatever the node did since `begin`
+    pub fn end(&mut self, node: usize) -> Duration {
+        TRACKING.with(|t| t.set(false));
+        let elapsed = self.started.take().map(|s| s.elapsed()).unwrap_or_default();
+
+        let allocations = ALLOCATIONS.with(|a| a.get());
+        if allocations > 0 {
+            let bytes = ALLOCATED_BYTES.with(|b| b.get());
+            self.record(
+                node,
+                ViolationKind::Allocation {
+                    count: allocations,
+                    bytes,
+                },
+            );
+        }
+
+        let locks = LOCKS.with(|l| l.get());
+        if locks > 0 {
+            self.record(node, ViolationKind::BlockingLock { count: locks });
+        }
+
+        elapsed
+    }
+
+    pub fn overrun(&mut self, node: usize, elapsed: Duration, budget: Duration) {
+        self.record(node, ViolationKind::DeadlineOverrun { elapsed, budget });
+    }
+
+    fn record(&mut self, node: usize, kind: ViolationKind) {
+        if self.violations.len() >= self.capacity {
+            self.dropped += 1;
+            return;
+        }
+
+        self.violations.push(Violation { node, kind });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::null::NullBackend;
+    use crate::core::engine::engine::Engine;
+    use crate::core::engine::node::{Node, ProcessContext};
+
+    struct Quiet;
+
+    impl Node for Quiet {
+        fn name(&self) -> &str {
+            "Quiet"
+        }
+
+        fn process(&mut self, _: &ProcessContext, _: &[f32], _: &mut [f32]) {}
+    }
+
+    // Allocates a scratch buffer and takes a blocking lock every block
+    struct Careless(GuardedMutex<Vec<f32>>);
+
+    impl Node for Careless {
+        fn name(&self) -> &str {
+            "Careless"
+        }
+
+        fn process(&mut self, _: &ProcessContext, _: &[f32], output: &mut [f32]) {
+            let scratch = std::hint::black_box(vec![0.5f32; output.len()]);
+            self.0.lock().unwrap().clone_from(&scratch);
+            output.copy_from_slice(&scratch);
+        }
+    }
+
+    #[test]
+    fn reports_what_a_node_does_in_the_null_backend() {
+        let mut engine = Engine::new(48000, 64);
+        engine.add_node(Box::new(Quiet));
+        engine.add_node(Box::new(Careless(GuardedMutex::new(Vec::with_capacity(
+            64,
+        )))));
+        engine.enable_guard();
+
+        NullBackend::new(48000, 64).run_engine(&mut engine, 2);
+
+        // A slow machine may overrun as well, that is not what is tested
+        let violations: Vec<&Violation> = engine
+            .guard()
+            .unwrap()
+            .violations()
+            .iter()
+            .filter(|v| !matches!(v.kind, ViolationKind::DeadlineOverrun { .. }))
+            .collect();
+        let found: Vec<(usize, bool)> = violations
+            .iter()
+            .map(|v| (v.node, matches!(v.kind, ViolationKind::Allocation { .. })))
+            .collect();
+        assert_eq!(found, vec![(1, true), (1, false), (1, true), (1, false)]);
+        assert!(matches!(
+            violations[0].kind,
+            ViolationKind::Allocation { bytes: 256, .. }
+        ));
+        assert_eq!(engine.node_name::<Quiet>(&[], 1), Some("Careless"));
+        assert!(engine
+            .report::<Quiet>(&[])
+            .iter()
+            .any(|line| line.ends_with("(Careless)")));
+    }
+}
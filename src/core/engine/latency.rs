@@ -0,0 +1,75 @@
+use super::node::{Node, ProcessContext};
+
+// Level the returning impulse has to reach to count as detected
+const DEFAULT_THRESHOLD: f32 = 0.25;
+
+// Measures round-trip latency of an audio device: it plays a single impulse
+// and counts frames until it comes back on the input. Connect the device's
+// output to its input (a loopback cable or the driver's loopback) first.
+pub struct LatencyProbe {
+    pub threshold: f32,
+    // Frames to wait for the impulse before giving up
+    pub timeout: u64,
+    elapsed: Option<u64>,
+    result: Option<u32>,
+}
+
+impl Default for LatencyProbe {
+    fn default() -> Self {
+        LatencyProbe::new(DEFAULT_THRESHOLD, 44100)
+    }
+}
+
+impl LatencyProbe {
+    pub fn new(threshold: f32, timeout: u64) -> Self {
+        LatencyProbe {
+            threshold,
+            timeout,
+            elapsed: None,
+            result: None,
+        }
+    }
+
+    // Fires a new impulse on the next block
+    pub fn restart(&mut self) {
+        self.elapsed = None;
+        self.result = None;
+    }
+
+    // Round-trip latency in frames, once the impulse has come back
+    pub fn result(&self) -> Option<u32> {
+        self.result
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.result.is_some() || self.elapsed.is_some_and(|e| e >= self.timeout)
+    }
+}
+
+impl Node for LatencyProbe {
+    fn name(&self) -> &str {
+        "Latency Probe"
+    }
+
+    fn process(&mut self, context: &ProcessContext, input: &[f32], output: &mut [f32]) {
+        if self.is_done() {
+            return;
+        }
+
+        let elapsed = match self.elapsed {
+            Some(elapsed) => elapsed,
+            None => {
+                if let Some(first) = output.first_mut() {
+                    *first = 1.0;
+                }
+                0
+            }
+        };
+
+        if let Some(frame) = input.iter().position(|s| s.abs() >= self.threshold) {
+            self.result = Some((elapsed + frame as u64) as u32);
+        }
+
+        self.elapsed = Some(elapsed + context.frames as u64);
+    }
+}
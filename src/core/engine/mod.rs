@@ -0,0 +1,9 @@
+pub mod delay;
+#[allow(clippy::module_inception)]
+pub mod engine;
+pub mod guard;
+pub mod latency;
+pub mod node;
+pub mod renderer;
+pub mod routing;
+pub mod transport;
@@ -0,0 +1,53 @@
+// Everything a node needs to know about the block it is rendering
+pub struct ProcessContext {
+    pub sample_rate: u32,
+    pub frames: usize,
+    pub position: u64,
+    pub playing: bool,
+    // Frames between a sound reaching the input and arriving in `input`
+    pub input_latency: u32,
+}
+
+// A unit of audio processing driven by the engine's render callback
+pub trait Node: Send {
+    fn name(&self) -> &str;
+
+    // Unique in a graph, the engine keeps a node's state under it
+    fn id(&self) -> &str {
+        self.name()
+    }
+
+    // Called outside the audio callback whenever the sample rate or block size changes
+    fn prepare(&mut self, _sample_rate: u32, _block_size: usize) {}
+
+    // Renders `context.frames` samples into `output`, which arrives zeroed
+    fn process(&mut self, context: &ProcessContext, input: &[f32], output: &mut [f32]);
+
+    // Frames this node delays its signal by (lookahead, linear-phase filters,
+    // oversampling). The engine delays every other path to match.
+    fn latency(&self) -> u32 {
+        0
+    }
+}
+
+impl Node for Box<dyn Node> {
+    fn name(&self) -> &str {
+        self.as_ref().name()
+    }
+
+    fn id(&self) -> &str {
+        self.as_ref().id()
+    }
+
+    fn prepare(&mut self, sample_rate: u32, block_size: usize) {
+        self.as_mut().prepare(sample_rate, block_size);
+    }
+
+    fn process(&mut self, context: &ProcessContext, input: &[f32], output: &mut [f32]) {
+        self.as_mut().process(context, input, output);
+    }
+
+    fn latency(&self) -> u32 {
+        self.as_ref().latency()
+    }
+}
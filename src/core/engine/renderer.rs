@@ -0,0 +1,59 @@
+use std::any::Any;
+
+use super::engine::Engine;
+use super::transport::Transport;
+
+// Whatever a backend's callback drives, the engine on its own or a whole
+// studio. `index` is a node's place in the graph, as `Engine::route_input`
+// and `Engine::node_output` take it.
+pub trait Renderer: Any + Send {
+    // Called off the audio thread whenever the backend's configuration changes
+    fn prepare(&mut self, sample_rate: u32, block_size: usize);
+    fn sample_rate(&self) -> u32;
+    fn block_size(&self) -> usize;
+    fn transport_mut(&mut self) -> &mut Transport;
+    fn set_input_latency(&mut self, frames: u32);
+    // Index of the node with `id`, for backends with per-node ports
+    fn node_index(&self, id: &str) -> Option<usize>;
+    fn route_input(&mut self, index: usize, samples: &[f32]);
+    fn render(&mut self, input: &[f32], output: &mut [f32]);
+    fn node_output(&self, index: usize) -> Option<&[f32]>;
+}
+
+impl Renderer for Engine {
+    fn prepare(&mut self, sample_rate: u32, block_size: usize) {
+        Engine::prepare(self, sample_rate, block_size);
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn transport_mut(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    fn set_input_latency(&mut self, frames: u32) {
+        self.input_latency = frames;
+    }
+
+    fn node_index(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|n| n.id() == id)
+    }
+
+    fn route_input(&mut self, index: usize, samples: &[f32]) {
+        Engine::route_input(self, index, samples);
+    }
+
+    fn render(&mut self, input: &[f32], output: &mut [f32]) {
+        Engine::render(self, input, output);
+    }
+
+    fn node_output(&self, index: usize) -> Option<&[f32]> {
+        Engine::node_output(self, index)
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+
+// Where a graph node plays, by index in the graph. Nodes without an output
+// play into the engine's output.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Route {
+    pub output: Option<usize>,
+    // (bus, level) pairs, taken after the node's own level
+    pub sends: Vec<(usize, f32)>,
+    // Takes its input from what plays into it rather than the backend's
+    pub summing: bool,
+}
+
+impl Route {
+    fn targets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.output
+            .into_iter()
+            .chain(self.sends.iter().map(|(bus, _)| *bus))
+    }
+}
+
+// Graph indices in an order that renders every node before whatever it
+// plays into, keeping the graph's order otherwise
+pub fn render_order(routes: &[Route]) -> Result<Vec<usize>> {
+    if let Some(index) = routes
+        .iter()
+        .position(|r| r.targets().any(|t| t >= routes.len() || !routes[t].summing))
+    {
+        return Err(anyhow!(
+            "node {} plays into a node that does not sum",
+            index
+        ));
+    }
+
+    let mut waiting = vec![0usize; routes.len()];
+    for target in routes.iter().flat_map(|r| r.targets()) {
+        waiting[target] += 1;
+    }
+
+    let mut order = Vec::with_capacity(routes.len());
+    let mut done = vec![false; routes.len()];
+    while order.len() < routes.len() {
+        let next = (0..routes.len())
+            .find(|i| !done[*i] && waiting[*i] == 0)
+            .ok_or_else(|| anyhow!("routing loops back on itself"))?;
+
+        done[next] = true;
+        order.push(next);
+        for target in routes[next].targets() {
+            waiting[target] -= 1;
+        }
+    }
+    Ok(order)
+}
@@ -0,0 +1,56 @@
+// Sample-based play head shared by everything the engine renders
+#[derive(Clone, Default)]
+pub struct Transport {
+    pub playing: bool,
+    pub position: u64,
+    // Start and end in samples, playback wraps from end back to start
+    pub loop_range: Option<(u64, u64)>,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Transport::default()
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn seek(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    pub fn set_loop(&mut self, start: u64, end: u64) {
+        self.loop_range = (end > start).then_some((start, end));
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.loop_range = None;
+    }
+
+    // Frames that can be rendered before the play head has to wrap
+    pub fn frames_until_wrap(&self, frames: usize) -> usize {
+        match self.loop_range {
+            Some((_, end)) if self.playing && self.position < end => {
+                frames.min((end - self.position) as usize)
+            }
+            _ => frames,
+        }
+    }
+
+    pub fn advance(&mut self, frames: usize) {
+        if self.playing {
+            self.position += frames as u64;
+
+            if let Some((start, end)) = self.loop_range {
+                if self.position >= end {
+                    self.position = start;
+                }
+            }
+        }
+    }
+}
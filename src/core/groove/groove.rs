@@ -0,0 +1,215 @@
+use crate::core::clip::clip::AudioClip;
+use crate::core::clip::midi_clip::MidiClip;
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+// Onset detection works on windows this long
+const ONSET_WINDOW: f64 = 0.005;
+// A window this much louder than the ones before it starts a hit
+const ONSET_RISE: f32 = 2.0;
+// Hits quieter than this, relative to the loudest window, are ignored
+const ONSET_FLOOR: f32 = 0.05;
+// Shortest gap between two hits
+const ONSET_HOLD: f64 = 0.05;
+
+// Offsets for one grid line of a groove
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrooveStep {
+    // In grid steps, -0.5 to 0.5
+    pub timing: f32,
+    // Scales note velocities, 1.0 leaves them alone
+    pub velocity: f32,
+}
+
+impl Default for GrooveStep {
+    fn default() -> Self {
+        GrooveStep {
+            timing: 0.0,
+            velocity: 1.0,
+        }
+    }
+}
+
+// Timing and velocity feel repeating every `steps.len()` grid lines of
+// `resolution` quarter notes, counted from the start of the song
+#[derive(Clone, Debug)]
+pub struct Groove {
+    pub name: String,
+    pub resolution: Beat,
+    pub steps: Vec<GrooveStep>,
+    // How much of the groove is applied, 0 to 1
+    pub timing_amount: f32,
+    pub velocity_amount: f32,
+}
+
+impl Default for Groove {
+    fn default() -> Self {
+        Groove::swing(0.25, 0.0)
+    }
+}
+
+impl Groove {
+    pub fn new(name: &str, resolution: Beat, steps: Vec<GrooveStep>) -> Self {
+        Groove {
+            name: name.to_string(),
+            resolution,
+            steps,
+            timing_amount: 1.0,
+            velocity_amount: 1.0,
+        }
+    }
+
+    // Delays every other grid line. 0.0 is straight, 1.0 puts the off-beat
+    // halfway to the next line (75% swing) and about 0.67 is a triplet shuffle.
+    pub fn swing(resolution: Beat, amount: f32) -> Self {
+        let off = GrooveStep {
+            timing: amount.clamp(0.0, 1.0) * 0.5,
+            velocity: 1.0,
+        };
+        let name = format!("Swing {}%", (50.0 + amount.clamp(0.0, 1.0) * 25.0).round());
+
+        Groove::new(&name, resolution, vec![GrooveStep::default(), off])
+    }
+
+    // Averages where `hits` (beat, level) land against the grid. Levels are
+    // compared to the mean level, steps without hits are left straight.
+    pub fn from_hits(name: &str, hits: &[(f64, f32)], resolution: Beat, steps: usize) -> Self {
+        let steps = steps.max(1);
+        let grid = resolution.max(f32::EPSILON) as f64;
+
+        // (timing sum, level sum, hits)
+        let mut sums = vec![(0.0f64, 0.0f32, 0u32); steps];
+        for (beat, level) in hits {
+            let line = (beat / grid).round();
+            let step = (line as i64).rem_euclid(steps as i64) as usize;
+
+            sums[step].0 += beat / grid - line;
+            sums[step].1 += level;
+            sums[step].2 += 1;
+        }
+
+        let mean = hits.iter().map(|h| h.1).sum::<f32>() / hits.len().max(1) as f32;
+        let steps = sums
+            .into_iter()
+            .map(|(timing, level, count)| match count {
+                0 => GrooveStep::default(),
+                _ => GrooveStep {
+                    timing: (timing / count as f64) as f32,
+                    velocity: if mean > 0.0 {
+                        level / count as f32 / mean
+                    } else {
+                        1.0
+                    },
+                },
+            })
+            .collect();
+
+        Groove::new(name, resolution, steps)
+    }
+
+    // Groove played by the notes of a clip
+    pub fn from_midi(clip: &MidiClip, resolution: Beat, steps: usize) -> Self {
+        let hits: Vec<(f64, f32)> = clip
+            .notes
+            .iter()
+            .map(|note| {
+                let beat = clip.start as f64 + note.start as f64 / clip.ppq.max(1) as f64;
+                (beat, note.velocity as f32 / 127.0)
+            })
+            .collect();
+
+        Groove::from_hits(&clip.name, &hits, resolution, steps)
+    }
+
+    // Groove of the transients in a clip laid out with `tempo` and `sample_rate`
+    pub fn from_audio(
+        clip: &AudioClip,
+        tempo: &Tempo,
+        sample_rate: u32,
+        resolution: Beat,
+        steps: usize,
+    ) -> Self {
+        let hits: Vec<(f64, f32)> = onsets(clip, sample_rate)
+            .into_iter()
+            .map(|(sample, level)| {
+                let sample = (clip.start_sample() + sample) as f64;
+                (tempo.sample_to_beats(sample, sample_rate), level)
+            })
+            .collect();
+
+        Groove::from_hits(&clip.name, &hits, resolution, steps)
+    }
+
+    pub fn step_at(&self, beat: f64) -> GrooveStep {
+        if self.steps.is_empty() || self.resolution <= 0.0 {
+            return GrooveStep::default();
+        }
+
+        let line = (beat / self.resolution as f64).round() as i64;
+        self.steps[line.rem_euclid(self.steps.len() as i64) as usize]
+    }
+
+    // Where a note on `beat` moves to, and its new velocity
+    pub fn apply(&self, beat: f64, velocity: u8) -> (f64, u8) {
+        let step = self.step_at(beat);
+        let timing = (step.timing * self.timing_amount) as f64 * self.resolution as f64;
+        let scale = 1.0 + (step.velocity - 1.0) * self.velocity_amount;
+
+        let velocity = (velocity as f32 * scale).round().clamp(1.0, 127.0) as u8;
+        ((beat + timing).max(0.0), velocity)
+    }
+}
+
+// (sample, level) of every transient, levels relative to the loudest window
+fn onsets(clip: &AudioClip, sample_rate: u32) -> Vec<(u64, f32)> {
+    let window = ((sample_rate as f64 * ONSET_WINDOW) as u64).max(1);
+    let hold = (sample_rate as f64 * ONSET_HOLD) as u64;
+
+    let levels: Vec<f32> = (0..clip.length.div_ceil(window))
+        .map(|w| {
+            let start = w * window;
+            let end = (start + window).min(clip.length);
+            let sum: f32 = (start..end).map(|i| clip.sample_at(i).powi(2)).sum();
+            (sum / (end - start) as f32).sqrt()
+        })
+        .collect();
+
+    let loudest = levels.iter().copied().fold(0.0, f32::max);
+    if loudest <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut hits = Vec::new();
+    let mut last: Option<u64> = None;
+    for (w, level) in levels.iter().enumerate() {
+        let before = levels[w.saturating_sub(4)..w]
+            .iter()
+            .copied()
+            .fold(0.0, f32::max);
+        let start = w as u64 * window;
+
+        let rising = *level > before * ONSET_RISE && *level > loudest * ONSET_FLOOR;
+        if rising && last.is_none_or(|l| start - l >= hold) {
+            // The hit is as loud as its peak shortly after
+            let peak = levels[w..(w + 4).min(levels.len())]
+                .iter()
+                .copied()
+                .fold(0.0, f32::max);
+            hits.push((first_above(clip, start, window * 4, 0.3), peak / loudest));
+            last = Some(start);
+        }
+    }
+    hits
+}
+
+// First sample from `start` reaching `ratio` of the peak within `length`
+fn first_above(clip: &AudioClip, start: u64, length: u64, ratio: f32) -> u64 {
+    let end = (start + length).min(clip.length);
+    let peak = (start..end)
+        .map(|i| clip.sample_at(i).abs())
+        .fold(0.0, f32::max);
+
+    (start..end)
+        .find(|i| clip.sample_at(*i).abs() >= peak * ratio)
+        .unwrap_or(start)
+}
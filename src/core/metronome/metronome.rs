@@ -0,0 +1,308 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::core::engine::node::{Node, ProcessContext};
+use crate::core::oscillator::oscillator::Oscillator;
+use crate::core::wav::wav::read_wav;
+use crate::types::rhythm::tempo::tempo::Tempo;
+use crate::types::rhythm::time_signature::time_signature::{Accent, TimeSignatures};
+
+// Length and decay of the synthesized clicks
+const CLICK_LENGTH: Duration = Duration::from_millis(40);
+const CLICK_DECAY: f32 = 120.0;
+// Pitches for downbeats, group starts and the other beats
+const CLICK_PITCHES: [f32; 3] = [1760.0, 1320.0, 880.0];
+// Group starts reuse the downbeat sample this much quieter
+const GROUP_GAIN: f32 = 0.7;
+pub const MAX_COUNT_IN: u32 = 2;
+
+// Clicks recorded or loaded by the user
+#[derive(Clone)]
+pub struct ClickSamples {
+    pub sample_rate: u32,
+    pub downbeat: Vec<f32>,
+    pub beat: Vec<f32>,
+}
+
+impl ClickSamples {
+    pub fn from_files<P: AsRef<Path>>(downbeat: P, beat: P) -> Result<Self> {
+        let (sample_rate, downbeat) = read_wav(downbeat)?;
+        let (beat_rate, beat) = read_wav(beat)?;
+
+        Ok(ClickSamples {
+            sample_rate,
+            downbeat,
+            beat: resample(&beat, beat_rate, sample_rate),
+        })
+    }
+}
+
+#[derive(Clone, Default)]
+pub enum ClickSound {
+    #[default]
+    Sine,
+    Square,
+    Samples(ClickSamples),
+}
+
+impl ClickSound {
+    // One click per accent, in the order of `Accent`
+    fn render(&self, sample_rate: u32) -> [Vec<f32>; 3] {
+        match self {
+            ClickSound::Sine | ClickSound::Square => CLICK_PITCHES.map(|pitch| {
+                let mut oscillator = Oscillator::new(sample_rate, pitch, CLICK_LENGTH, 1.0);
+                match self {
+                    ClickSound::Square => oscillator.square_wave(),
+                    _ => oscillator.sine_wave(),
+                };
+                oscillator.am(|t| (-t * CLICK_DECAY).exp());
+
+                oscillator
+                    .samples
+                    .iter()
+                    .map(|s| *s as f32 / i16::MAX as f32)
+                    .collect()
+            }),
+            ClickSound::Samples(samples) => {
+                let downbeat = resample(&samples.downbeat, samples.sample_rate, sample_rate);
+                let group = downbeat.iter().map(|s| s * GROUP_GAIN).collect();
+                let beat = resample(&samples.beat, samples.sample_rate, sample_rate);
+                [downbeat, group, beat]
+            }
+        }
+    }
+}
+
+// Linear interpolation, good enough for clicks
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() || from == 0 {
+        return samples.to_vec();
+    }
+
+    let step = from as f64 / to as f64;
+    let length = (samples.len() as f64 / step) as usize;
+    (0..length)
+        .map(|i| {
+            let at = i as f64 * step;
+            let index = at as usize;
+            let next = samples.get(index + 1).copied().unwrap_or(0.0);
+            let t = (at - index as f64) as f32;
+            samples[index] + (next - samples[index]) * t
+        })
+        .collect()
+}
+
+// Clicks played before the transport starts, at the tempo and meter found
+// where playback will begin
+struct CountIn {
+    played: u64,
+    length: u64,
+    pulse: f64,
+    time_signature: TimeSignatures,
+}
+
+// Click that follows the transport and the tempo map, pulsing in the meter's
+// note value and accenting downbeats and the start of each group
+pub struct Metronome {
+    pub enabled: bool,
+    pub volume: f32,
+    // Bars counted in by `start_count_in`, up to MAX_COUNT_IN
+    pub count_in: u32,
+    sound: ClickSound,
+    clicks: [Vec<f32>; 3],
+    tempo: Tempo,
+    sample_rate: u32,
+    // (click, next sample of it) for the click that is sounding
+    voice: Option<(usize, usize)>,
+    counting: Option<CountIn>,
+}
+
+impl Default for Metronome {
+    fn default() -> Self {
+        Metronome {
+            enabled: false,
+            volume: 0.5,
+            count_in: 0,
+            sound: ClickSound::default(),
+            clicks: Default::default(),
+            tempo: Tempo::default(),
+            sample_rate: 0,
+            voice: None,
+            counting: None,
+        }
+    }
+}
+
+impl Metronome {
+    pub fn new(tempo: &Tempo, sample_rate: u32) -> Self {
+        let mut metronome = Metronome::default();
+        metronome.layout(tempo, sample_rate);
+        metronome
+    }
+
+    pub fn sound(&self) -> &ClickSound {
+        &self.sound
+    }
+
+    pub fn set_sound(&mut self, sound: ClickSound) {
+        self.clicks = sound.render(self.sample_rate);
+        self.sound = sound;
+        self.voice = None;
+    }
+
+    // Call after tempo or sample rate changes
+    pub fn layout(&mut self, tempo: &Tempo, sample_rate: u32) {
+        self.tempo = tempo.clone();
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.clicks = self.sound.render(sample_rate);
+            self.voice = None;
+        }
+    }
+
+    // Starts counting in `count_in` bars for playback from `position`.
+    // Returns the count-in's length in samples, 0 when there is none.
+    pub fn start_count_in(&mut self, position: u64) -> u64 {
+        let bars = self.count_in.min(MAX_COUNT_IN);
+        if bars == 0 {
+            self.counting = None;
+            return 0;
+        }
+
+        let beats = self
+            .tempo
+            .sample_to_beats(position as f64, self.sample_rate);
+        let time_signature = self
+            .tempo
+            .time_signature_at(self.tempo.beat_to_bar(beats) as f32);
+        let seconds = time_signature.beat_length() as f64 * 60.0 / self.tempo.bpm_at(beats) as f64;
+        let pulse = seconds * self.sample_rate as f64;
+
+        let length = (pulse * (time_signature.beats() as u32 * bars) as f64).round() as u64;
+        self.counting = Some(CountIn {
+            played: 0,
+            length,
+            pulse,
+            time_signature,
+        });
+        length
+    }
+
+    pub fn cancel_count_in(&mut self) {
+        self.counting = None;
+    }
+
+    // Samples left before the count-in ends
+    pub fn counting_in(&self) -> Option<u64> {
+        self.counting.as_ref().map(|c| c.length - c.played)
+    }
+
+    fn trigger(&mut self, accent: Accent) {
+        let click = match accent {
+            Accent::Downbeat => 0,
+            Accent::Group => 1,
+            Accent::Beat => 2,
+        };
+        self.voice = Some((click, 0));
+    }
+
+    // Plays the sounding click into `output` from `from` on
+    fn play(&mut self, from: usize, to: usize, output: &mut [f32]) {
+        let Some((click, mut index)) = self.voice else {
+            return;
+        };
+        let samples = &self.clicks[click];
+
+        for sample in &mut output[from..to] {
+            let Some(value) = samples.get(index) else {
+                self.voice = None;
+                return;
+            };
+            *sample += value * self.volume;
+            index += 1;
+        }
+        self.voice = Some((click, index));
+    }
+
+    // Renders the count-in and returns the frames it covered
+    fn process_count_in(&mut self, output: &mut [f32]) -> usize {
+        let Some(counting) = self.counting.as_ref() else {
+            return 0;
+        };
+        let (start, frames) = (
+            counting.played,
+            (counting.length - counting.played) as usize,
+        );
+        let frames = frames.min(output.len());
+        let (pulse, time_signature) = (counting.pulse, counting.time_signature);
+
+        let mut offset = 0;
+        let mut index = (start as f64 / pulse - 1e-9).ceil() as u64;
+        loop {
+            let at = (index as f64 * pulse).round() as u64;
+            if at >= start + frames as u64 {
+                break;
+            }
+            let at = (at - start) as usize;
+            self.play(offset, at, output);
+            offset = at;
+
+            self.trigger(time_signature.accent((index % time_signature.beats() as u64) as u32));
+            index += 1;
+        }
+        self.play(offset, frames, output);
+
+        let counting = self.counting.as_mut().unwrap();
+        counting.played += frames as u64;
+        if counting.played >= counting.length {
+            self.counting = None;
+        }
+        frames
+    }
+}
+
+impl Node for Metronome {
+    fn name(&self) -> &str {
+        "Metronome"
+    }
+
+    fn prepare(&mut self, sample_rate: u32, _block_size: usize) {
+        let tempo = self.tempo.clone();
+        self.layout(&tempo, sample_rate);
+    }
+
+    // Adds the clicks on top of whatever is in `output`
+    fn process(&mut self, context: &ProcessContext, _input: &[f32], output: &mut [f32]) {
+        let frames = context.frames.min(output.len());
+        let counted = self.process_count_in(&mut output[..frames]);
+
+        if !context.playing || !self.enabled {
+            self.play(counted, frames, output);
+            return;
+        }
+
+        // A pulse on sample x sounds from round(x), so each block takes the
+        // pulses from half a sample before it
+        let sample_rate = self.sample_rate;
+        let start = context.position + counted as u64;
+        let from = self.tempo.sample_to_beats(start as f64 - 0.5, sample_rate);
+        let to = self
+            .tempo
+            .sample_to_beats((context.position + frames as u64) as f64 - 0.5, sample_rate);
+
+        let mut offset = counted;
+        let mut pulse = self.tempo.next_pulse(from);
+        while pulse.0 < to {
+            let at = self.tempo.beats_to_sample(pulse.0, sample_rate).round() as u64;
+            let at = (at.max(start) - context.position) as usize;
+            self.play(offset, at, output);
+            offset = at;
+
+            self.trigger(pulse.1);
+            pulse = self.tempo.next_pulse(pulse.0 + 1e-6);
+        }
+        self.play(offset, frames, output);
+    }
+}
@@ -0,0 +1,150 @@
+// Channel voice messages exchanged between MIDI tracks, live input and instruments
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        pitch: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        pitch: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    // -8192 to 8191, 0 is centre
+    PitchBend {
+        channel: u8,
+        value: i16,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u8,
+    },
+    PolyPressure {
+        channel: u8,
+        pitch: u8,
+        pressure: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+}
+
+pub const ALL_NOTES_OFF: u8 = 123;
+
+impl MidiMessage {
+    pub fn channel(&self) -> u8 {
+        match *self {
+            MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::PitchBend { channel, .. }
+            | MidiMessage::ChannelPressure { channel, .. }
+            | MidiMessage::PolyPressure { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. } => channel,
+        }
+    }
+
+    pub fn all_notes_off(channel: u8) -> Self {
+        MidiMessage::ControlChange {
+            channel,
+            controller: ALL_NOTES_OFF,
+            value: 0,
+        }
+    }
+
+    // Raw bytes, returned with the number of bytes used
+    pub fn to_bytes(&self) -> ([u8; 3], usize) {
+        match *self {
+            MidiMessage::NoteOn {
+                channel,
+                pitch,
+                velocity,
+            } => ([0x90 | (channel & 0x0F), pitch & 0x7F, velocity & 0x7F], 3),
+            MidiMessage::NoteOff {
+                channel,
+                pitch,
+                velocity,
+            } => ([0x80 | (channel & 0x0F), pitch & 0x7F, velocity & 0x7F], 3),
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (
+                [0xB0 | (channel & 0x0F), controller & 0x7F, value & 0x7F],
+                3,
+            ),
+            MidiMessage::PitchBend { channel, value } => {
+                let raw = (value.clamp(-8192, 8191) + 8192) as u16;
+                let lsb = (raw & 0x7F) as u8;
+                let msb = (raw >> 7) as u8;
+                ([0xE0 | (channel & 0x0F), lsb, msb], 3)
+            }
+            MidiMessage::ChannelPressure { channel, pressure } => {
+                ([0xD0 | (channel & 0x0F), pressure & 0x7F, 0], 2)
+            }
+            MidiMessage::PolyPressure {
+                channel,
+                pitch,
+                pressure,
+            } => ([0xA0 | (channel & 0x0F), pitch & 0x7F, pressure & 0x7F], 3),
+            MidiMessage::ProgramChange { channel, program } => {
+                ([0xC0 | (channel & 0x0F), program & 0x7F, 0], 2)
+            }
+        }
+    }
+
+    // Parses a channel voice message, a note-on with zero velocity is a note-off
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let status = *bytes.first()?;
+        let channel = status & 0x0F;
+        let data = |i: usize| bytes.get(i).map(|b| b & 0x7F);
+
+        Some(match status & 0xF0 {
+            0x80 => MidiMessage::NoteOff {
+                channel,
+                pitch: data(1)?,
+                velocity: data(2)?,
+            },
+            0x90 if data(2)? == 0 => MidiMessage::NoteOff {
+                channel,
+                pitch: data(1)?,
+                velocity: 64,
+            },
+            0x90 => MidiMessage::NoteOn {
+                channel,
+                pitch: data(1)?,
+                velocity: data(2)?,
+            },
+            0xA0 => MidiMessage::PolyPressure {
+                channel,
+                pitch: data(1)?,
+                pressure: data(2)?,
+            },
+            0xB0 => MidiMessage::ControlChange {
+                channel,
+                controller: data(1)?,
+                value: data(2)?,
+            },
+            0xC0 => MidiMessage::ProgramChange {
+                channel,
+                program: data(1)?,
+            },
+            0xD0 => MidiMessage::ChannelPressure {
+                channel,
+                pressure: data(1)?,
+            },
+            0xE0 => MidiMessage::PitchBend {
+                channel,
+                value: ((data(2)? as i16) << 7 | data(1)? as i16) - 8192,
+            },
+            _ => return None,
+        })
+    }
+}
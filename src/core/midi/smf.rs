@@ -0,0 +1,861 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use super::message::MidiMessage;
+use crate::core::clip::midi_clip::{MidiClip, MidiEvent, Note, Tick};
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+use crate::types::rhythm::tempo::tempo::{Tempo, TempoShape};
+use crate::types::rhythm::time_signature::time_signature::TimeSignatures;
+use crate::types::theory::chord_track::ChordTrack;
+use crate::types::theory::scale::{Key, ScaleType};
+
+// Tempo ramps are written as steps this many to the quarter note
+const RAMP_STEPS: u32 = 4;
+
+// Type 0 keeps everything on one track, type 1 has a track per part with the
+// tempo map on the first. Type 2 files are not supported.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SmfFormat {
+    SingleTrack,
+    MultiTrack,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetaEvent {
+    TrackName(String),
+    // Microseconds per quarter note
+    Tempo(u32),
+    // `denominator` is the note value, 8 for x/8
+    TimeSignature {
+        numerator: u8,
+        denominator: u8,
+        clocks_per_click: u8,
+        thirty_seconds_per_quarter: u8,
+    },
+    // Flats below zero
+    KeySignature {
+        sharps: i8,
+        minor: bool,
+    },
+    EndOfTrack,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SmfEventKind {
+    Message(MidiMessage),
+    Meta(MetaEvent),
+}
+
+// Ticks are absolute, from the start of the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmfEvent {
+    pub tick: Tick,
+    pub kind: SmfEventKind,
+}
+
+impl SmfEvent {
+    pub fn message(tick: Tick, message: MidiMessage) -> Self {
+        SmfEvent {
+            tick,
+            kind: SmfEventKind::Message(message),
+        }
+    }
+
+    pub fn meta(tick: Tick, meta: MetaEvent) -> Self {
+        SmfEvent {
+            tick,
+            kind: SmfEventKind::Meta(meta),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SmfTrack {
+    pub events: Vec<SmfEvent>,
+}
+
+impl SmfTrack {
+    pub fn name(&self) -> Option<&str> {
+        self.events.iter().find_map(|e| match &e.kind {
+            SmfEventKind::Meta(MetaEvent::TrackName(name)) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    fn messages(&self) -> impl Iterator<Item = (Tick, MidiMessage)> + '_ {
+        self.events.iter().filter_map(|e| match e.kind {
+            SmfEventKind::Message(message) => Some((e.tick, message)),
+            _ => None,
+        })
+    }
+}
+
+// A Standard MIDI File with its ticks per quarter note
+#[derive(Clone, Debug)]
+pub struct Smf {
+    pub format: SmfFormat,
+    pub ppq: u16,
+    pub tracks: Vec<SmfTrack>,
+}
+
+impl Smf {
+    pub fn new(format: SmfFormat, ppq: u16) -> Self {
+        Smf {
+            format,
+            ppq: ppq.clamp(1, 0x7FFF),
+            tracks: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Smf::parse(&std::fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 14 || &bytes[0..4] != b"MThd" {
+            return Err(anyhow!("not a MIDI file"));
+        }
+
+        let header_len = u32::from_be_bytes(bytes[4..8].try_into()?) as usize;
+        let format = match u16::from_be_bytes([bytes[8], bytes[9]]) {
+            0 => SmfFormat::SingleTrack,
+            1 => SmfFormat::MultiTrack,
+            format => return Err(anyhow!("unsupported MIDI file type {}", format)),
+        };
+        let division = u16::from_be_bytes([bytes[12], bytes[13]]);
+        if division & 0x8000 != 0 {
+            return Err(anyhow!("SMPTE timed MIDI files are not supported"));
+        }
+
+        let mut smf = Smf::new(format, division);
+        let mut offset = 8 + header_len;
+
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size = u32::from_be_bytes(bytes[offset + 4..offset + 8].try_into()?) as usize;
+            let body = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+
+            // Unknown chunks are skipped
+            if id == b"MTrk" {
+                smf.tracks.push(parse_track(body)?);
+            }
+            offset += 8 + size;
+        }
+        Ok(smf)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let format: u16 = match self.format {
+            SmfFormat::SingleTrack => 0,
+            SmfFormat::MultiTrack => 1,
+        };
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"MThd");
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&format.to_be_bytes());
+        bytes.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.ppq.to_be_bytes());
+
+        for track in &self.tracks {
+            let body = write_track(track);
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&body);
+        }
+        bytes
+    }
+
+    fn metas(&self) -> Vec<(Tick, &MetaEvent)> {
+        let mut metas: Vec<(Tick, &MetaEvent)> = self
+            .tracks
+            .iter()
+            .flat_map(|t| &t.events)
+            .filter_map(|e| match &e.kind {
+                SmfEventKind::Meta(meta) => Some((e.tick, meta)),
+                _ => None,
+            })
+            .collect();
+        metas.sort_by_key(|(tick, _)| *tick);
+        metas
+    }
+
+    fn beats(&self, tick: Tick) -> f64 {
+        tick as f64 / self.ppq.max(1) as f64
+    }
+
+    // Whether the file sets a tempo or meter, files without one are 120 BPM 4/4
+    pub fn has_tempo_map(&self) -> bool {
+        self.metas()
+            .iter()
+            .any(|(_, meta)| matches!(meta, MetaEvent::Tempo(_) | MetaEvent::TimeSignature { .. }))
+    }
+
+    // Tempo map from the tempo and time signature events. Meters that don't
+    // fall on a bar line move to the nearest one.
+    pub fn tempo(&self) -> Tempo {
+        let mut tempo = Tempo::default();
+        let metas = self.metas();
+
+        for (tick, meta) in &metas {
+            if let MetaEvent::TimeSignature {
+                numerator,
+                denominator,
+                ..
+            } = meta
+            {
+                let Ok(time_signature) = TimeSignatures::new(*numerator, *denominator) else {
+                    continue;
+                };
+                let bar = tempo.beat_to_bar(self.beats(*tick)).round() as u32;
+                match bar {
+                    0 => tempo.set_time_signature(time_signature),
+                    bar => tempo.add_meter_change(bar, time_signature),
+                }
+            }
+        }
+
+        for (tick, meta) in &metas {
+            if let MetaEvent::Tempo(micros) = meta {
+                let bpm = 60_000_000.0 / (*micros).max(1) as f32;
+                match *tick {
+                    0 => tempo.set_bpm(bpm),
+                    tick => {
+                        let bar = tempo.beat_to_bar(self.beats(tick)) as f32;
+                        tempo.add_tempo_change(bar, bpm, TempoShape::Jump);
+                    }
+                }
+            }
+        }
+        tempo
+    }
+
+    // Key signatures as (beat, key)
+    pub fn keys(&self) -> Vec<(Beat, Key)> {
+        self.metas()
+            .into_iter()
+            .filter_map(|(tick, meta)| match meta {
+                MetaEvent::KeySignature { sharps, minor } => Some((
+                    self.beats(tick) as Beat,
+                    key_from_signature(*sharps, *minor),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // A clip per track with notes or controllers, one per channel for type 0.
+    // Clips keep the file's resolution and start on the bar of their first
+    // event.
+    pub fn clips(&self, tempo: &Tempo) -> Vec<MidiClip> {
+        let mut clips = Vec::new();
+
+        for (index, track) in self.tracks.iter().enumerate() {
+            let name = track
+                .name()
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| format!("Track {}", index + 1));
+            let messages: Vec<(Tick, MidiMessage)> = track.messages().collect();
+            let end = track.events.last().map(|e| e.tick).unwrap_or(0);
+
+            match self.format {
+                SmfFormat::MultiTrack => {
+                    clips.extend(self.clip(&name, &messages, end, tempo));
+                }
+                SmfFormat::SingleTrack => {
+                    for channel in 0..16 {
+                        let messages: Vec<(Tick, MidiMessage)> = messages
+                            .iter()
+                            .filter(|(_, m)| m.channel() == channel)
+                            .copied()
+                            .collect();
+                        let name = match track.name() {
+                            Some(name) => format!("{} {}", name, channel + 1),
+                            None => format!("Channel {}", channel + 1),
+                        };
+                        clips.extend(self.clip(&name, &messages, end, tempo));
+                    }
+                }
+            }
+        }
+        clips
+    }
+
+    fn clip(
+        &self,
+        name: &str,
+        messages: &[(Tick, MidiMessage)],
+        end: Tick,
+        tempo: &Tempo,
+    ) -> Option<MidiClip> {
+        let first = messages.first()?.0;
+        let ppq = self.ppq.max(1) as f64;
+
+        let start_bar = tempo.beat_to_bar(self.beats(first)).floor();
+        let start = tempo.bar_to_beat(start_bar);
+        let offset = (start * ppq).round() as Tick;
+
+        let mut notes = Vec::new();
+        let mut events = Vec::new();
+        // Note-ons waiting for their note-off, per channel and pitch
+        let mut sounding: HashMap<(u8, u8), VecDeque<(Tick, u8)>> = HashMap::new();
+
+        for (tick, message) in messages {
+            let tick = tick - offset;
+            match *message {
+                MidiMessage::NoteOn {
+                    channel,
+                    pitch,
+                    velocity,
+                } => sounding
+                    .entry((channel, pitch))
+                    .or_default()
+                    .push_back((tick, velocity)),
+                MidiMessage::NoteOff {
+                    channel,
+                    pitch,
+                    velocity,
+                } => {
+                    let started = sounding
+                        .get_mut(&(channel, pitch))
+                        .and_then(|s| s.pop_front());
+                    if let Some((start, on_velocity)) = started {
+                        let mut note = Note::new(pitch, on_velocity, start, tick - start);
+                        note.channel = channel;
+                        note.release_velocity = velocity;
+                        notes.push(note);
+                    }
+                }
+                message => events.extend(MidiEvent::from_message(tick, message)),
+            }
+        }
+
+        // Notes never let go end with the track
+        for ((channel, pitch), started) in sounding {
+            for (start, velocity) in started {
+                let mut note = Note::new(pitch, velocity, start, (end - offset).max(start) - start);
+                note.channel = channel;
+                notes.push(note);
+            }
+        }
+        if notes.is_empty() && events.is_empty() {
+            return None;
+        }
+
+        // Lengthened to the end of the last bar used
+        let last = notes
+            .iter()
+            .map(|n| n.end())
+            .chain(events.iter().map(|e: &MidiEvent| e.tick))
+            .max()
+            .unwrap_or(0)
+            + offset;
+        let end_bar = tempo
+            .beat_to_bar(self.beats(last))
+            .ceil()
+            .max(start_bar + 1.0);
+        let length = (tempo.bar_to_beat(end_bar) * ppq).round() as Tick - offset;
+
+        let mut clip = MidiClip::new(name, length);
+        clip.ppq = self.ppq as u32;
+        clip.start = start as Beat;
+        for note in notes {
+            clip.add_note(note);
+        }
+        for event in events {
+            clip.add_event(event);
+        }
+        Some(clip)
+    }
+
+    // Type 1 file of the tempo map, the keys of a chord track and a track per
+    // (name, clips) part. Clips are written as they play, looped and grooved.
+    pub fn export(
+        parts: &[(&str, &[MidiClip])],
+        tempo: &Tempo,
+        chord_track: Option<&ChordTrack>,
+        ppq: u16,
+    ) -> Self {
+        let mut smf = Smf::new(SmfFormat::MultiTrack, ppq);
+
+        let mut conductor = SmfTrack::default();
+        conductor.events.extend(tempo_events(tempo, smf.ppq));
+        if let Some(chord_track) = chord_track {
+            conductor.events.extend(key_events(chord_track, smf.ppq));
+        }
+        smf.tracks.push(conductor);
+
+        for (name, clips) in parts {
+            let mut track = SmfTrack::default();
+            track
+                .events
+                .push(SmfEvent::meta(0, MetaEvent::TrackName(name.to_string())));
+            for clip in clips.iter() {
+                track
+                    .events
+                    .extend(clip_events(clip, clip.start as f64, smf.ppq));
+            }
+            smf.tracks.push(track);
+        }
+        smf
+    }
+
+    // Type 0 file of one clip from its first tick, with the tempo and meter
+    // where it starts
+    pub fn from_clip(clip: &MidiClip, tempo: &Tempo) -> Self {
+        let ppq = clip.ppq.clamp(1, 0x7FFF) as u16;
+        let mut smf = Smf::new(SmfFormat::SingleTrack, ppq);
+
+        let start = clip.start as f64;
+        let bar = tempo.beat_to_bar(start) as f32;
+        let local = Tempo::new(tempo.bpm_at(start), tempo.time_signature_at(bar));
+
+        let mut track = SmfTrack::default();
+        track
+            .events
+            .push(SmfEvent::meta(0, MetaEvent::TrackName(clip.name.clone())));
+        track.events.extend(tempo_events(&local, ppq));
+        track.events.extend(clip_events(clip, 0.0, ppq));
+        smf.tracks.push(track);
+        smf
+    }
+}
+
+fn key_from_signature(sharps: i8, minor: bool) -> Key {
+    let major = (sharps as i32 * 7).rem_euclid(12) as u8;
+    match minor {
+        true => Key::new(major + 9, ScaleType::Minor),
+        false => Key::new(major, ScaleType::Major),
+    }
+}
+
+// Only major and minor keys have a signature, Gb and Db win over F# and C#
+fn signature(key: &Key) -> Option<(i8, bool)> {
+    let (major, minor) = match key.scale {
+        ScaleType::Major => (key.tonic, false),
+        ScaleType::Minor => ((key.tonic + 3) % 12, true),
+        _ => return None,
+    };
+    (-6..=5)
+        .find(|sharps: &i8| (*sharps as i32 * 7).rem_euclid(12) as u8 == major)
+        .map(|sharps| (sharps, minor))
+}
+
+fn tick_at(beat: f64, ppq: u16) -> Tick {
+    (beat.max(0.0) * ppq as f64).round() as Tick
+}
+
+fn tempo_event(tick: Tick, bpm: f32) -> SmfEvent {
+    let micros = (60_000_000.0 / bpm.max(1.0) as f64).round() as u32;
+    SmfEvent::meta(tick, MetaEvent::Tempo(micros.min(0xFF_FFFF)))
+}
+
+fn meter_event(tick: Tick, time_signature: TimeSignatures) -> SmfEvent {
+    let meter = time_signature.time_signature();
+    // Compound meters click on the dotted beat
+    let compound = meter.is_additive() && meter.groups().iter().all(|g| *g == 3);
+    let click = 24.0 * meter.beat_length() * if compound { 3.0 } else { 1.0 };
+
+    SmfEvent::meta(
+        tick,
+        MetaEvent::TimeSignature {
+            numerator: meter.beats,
+            denominator: meter.note,
+            clocks_per_click: click.round().clamp(1.0, 255.0) as u8,
+            thirty_seconds_per_quarter: 8,
+        },
+    )
+}
+
+// Tempo and meter events. SMF tempos can only jump, so ramps are written as
+// steps, each at the average tempo that keeps its length in seconds.
+fn tempo_events(tempo: &Tempo, ppq: u16) -> Vec<SmfEvent> {
+    let mut events = vec![
+        meter_event(0, tempo.time_signature),
+        tempo_event(0, tempo.bpm.bpm),
+    ];
+
+    for meter in tempo.meter_changes() {
+        let beat = tempo.bar_to_beat(meter.bar as f64);
+        events.push(meter_event(tick_at(beat, ppq), meter.time_signature));
+    }
+
+    let changes = tempo.tempo_changes();
+    for (index, change) in changes.iter().enumerate() {
+        let beat = tempo.bar_to_beat(change.bar as f64);
+        let next = changes.get(index + 1);
+
+        match (change.shape, next) {
+            (TempoShape::Linear | TempoShape::Exponential, Some(next)) => {
+                let end = tempo.bar_to_beat(next.bar as f64);
+                let step = 1.0 / RAMP_STEPS as f64;
+
+                let mut from = beat;
+                while from < end {
+                    let to = (from + step).min(end);
+                    let seconds = tempo.beats_to_seconds(to) - tempo.beats_to_seconds(from);
+                    let bpm = (60.0 * (to - from) / seconds.max(1e-9)) as f32;
+                    events.push(tempo_event(tick_at(from, ppq), bpm));
+                    from = to;
+                }
+            }
+            _ => events.push(tempo_event(tick_at(beat, ppq), change.bpm)),
+        }
+    }
+    events
+}
+
+fn key_events(chord_track: &ChordTrack, ppq: u16) -> Vec<SmfEvent> {
+    let keys =
+        std::iter::once((0.0, chord_track.key)).chain(chord_track.keys.as_slice().iter().copied());
+
+    keys.filter_map(|(beat, key)| {
+        let (sharps, minor) = signature(&key)?;
+        Some(SmfEvent::meta(
+            tick_at(beat as f64, ppq),
+            MetaEvent::KeySignature { sharps, minor },
+        ))
+    })
+    .collect()
+}
+
+// What a clip plays with it placed `start` beats into the file, following its
+// loop and groove the way the track schedules it
+fn clip_events(clip: &MidiClip, start: f64, ppq: u16) -> Vec<SmfEvent> {
+    let clip_ppq = clip.ppq.max(1) as f64;
+    let at = |tick: Tick| tick_at(start + tick as f64 / clip_ppq, ppq);
+
+    let mut events = Vec::new();
+    let cycle = clip.cycle_length().max(1);
+
+    let mut pass_start: Tick = 0;
+    while pass_start < clip.length {
+        let pass_end = (pass_start + cycle).min(clip.length);
+
+        for note in clip.notes.iter().take_while(|n| n.start < cycle) {
+            if pass_start + note.start >= pass_end {
+                continue;
+            }
+            let (on, velocity) = clip.grooved(pass_start + note.start, note.velocity);
+            let on = on.clamp(pass_start, pass_end - 1);
+            let off = (on + note.length).min(pass_end);
+            // Offs sort before ons on a shared tick, so a note that rounds to
+            // nothing at the file's resolution still lasts a tick
+            let (on, off) = (at(on), at(off).max(at(on) + 1));
+
+            events.push(SmfEvent::message(
+                on,
+                MidiMessage::NoteOn {
+                    channel: note.channel,
+                    pitch: note.pitch,
+                    velocity: velocity.max(1),
+                },
+            ));
+            events.push(SmfEvent::message(
+                off,
+                MidiMessage::NoteOff {
+                    channel: note.channel,
+                    pitch: note.pitch,
+                    velocity: note.release_velocity,
+                },
+            ));
+        }
+
+        for event in clip.events.iter().take_while(|e| e.tick < cycle) {
+            let tick = pass_start + event.tick;
+            if tick < pass_end {
+                events.push(SmfEvent::message(at(tick), event.message()));
+            }
+        }
+        pass_start += cycle;
+    }
+    events
+}
+
+fn read_varlen(bytes: &[u8], offset: &mut usize) -> Result<u32> {
+    let mut value = 0u32;
+    for _ in 0..4 {
+        let byte = *bytes
+            .get(*offset)
+            .ok_or_else(|| anyhow!("MIDI track ends inside a number"))?;
+        *offset += 1;
+        value = value << 7 | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("MIDI number longer than four bytes"))
+}
+
+fn write_varlen(bytes: &mut Vec<u8>, value: u32) {
+    let value = value.min(0x0FFF_FFFF);
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+fn parse_track(bytes: &[u8]) -> Result<SmfTrack> {
+    let mut track = SmfTrack::default();
+    let mut offset = 0;
+    let mut tick: Tick = 0;
+    let mut running: Option<u8> = None;
+
+    while offset < bytes.len() {
+        tick += read_varlen(bytes, &mut offset)? as Tick;
+
+        let Some(&(mut status)) = bytes.get(offset) else {
+            break;
+        };
+        if status & 0x80 != 0 {
+            offset += 1;
+        } else {
+            // Running status, the data byte belongs to the previous status
+            status = running.ok_or_else(|| anyhow!("MIDI data byte without a status"))?;
+        }
+
+        match status {
+            0xFF => {
+                let kind = *bytes
+                    .get(offset)
+                    .ok_or_else(|| anyhow!("MIDI track ends inside an event"))?;
+                offset += 1;
+                let length = read_varlen(bytes, &mut offset)? as usize;
+                let data = bytes
+                    .get(offset..offset + length)
+                    .ok_or_else(|| anyhow!("MIDI track ends inside an event"))?;
+                offset += length;
+
+                match parse_meta(kind, data) {
+                    Some(MetaEvent::EndOfTrack) => {
+                        track
+                            .events
+                            .push(SmfEvent::meta(tick, MetaEvent::EndOfTrack));
+                        break;
+                    }
+                    Some(meta) => track.events.push(SmfEvent::meta(tick, meta)),
+                    None => {}
+                }
+            }
+            // System exclusive, skipped
+            0xF0 | 0xF7 => {
+                let length = read_varlen(bytes, &mut offset)? as usize;
+                offset += length;
+                running = None;
+            }
+            status => {
+                let size = match status & 0xF0 {
+                    0xC0 | 0xD0 => 1,
+                    _ => 2,
+                };
+                let data = bytes
+                    .get(offset..offset + size)
+                    .ok_or_else(|| anyhow!("MIDI track ends inside an event"))?;
+                offset += size;
+                running = Some(status);
+
+                let mut message = [status, 0, 0];
+                message[1..=size].copy_from_slice(data);
+                if let Some(message) = MidiMessage::from_bytes(&message[..=size]) {
+                    track.events.push(SmfEvent::message(tick, message));
+                }
+            }
+        }
+    }
+    Ok(track)
+}
+
+fn parse_meta(kind: u8, data: &[u8]) -> Option<MetaEvent> {
+    Some(match (kind, data) {
+        (0x03, name) => MetaEvent::TrackName(String::from_utf8_lossy(name).into_owned()),
+        (0x2F, _) => MetaEvent::EndOfTrack,
+        (0x51, [a, b, c, ..]) => MetaEvent::Tempo(u32::from_be_bytes([0, *a, *b, *c])),
+        (0x58, [numerator, power, clocks, thirty_seconds, ..]) => MetaEvent::TimeSignature {
+            numerator: *numerator,
+            denominator: 1u8.checked_shl(*power as u32)?,
+            clocks_per_click: *clocks,
+            thirty_seconds_per_quarter: *thirty_seconds,
+        },
+        (0x59, [sharps, minor, ..]) => MetaEvent::KeySignature {
+            sharps: *sharps as i8,
+            minor: *minor != 0,
+        },
+        _ => return None,
+    })
+}
+
+fn write_meta(bytes: &mut Vec<u8>, meta: &MetaEvent) {
+    let (kind, data) = match meta {
+        MetaEvent::TrackName(name) => (0x03, name.as_bytes().to_vec()),
+        MetaEvent::Tempo(micros) => (0x51, micros.to_be_bytes()[1..].to_vec()),
+        MetaEvent::TimeSignature {
+            numerator,
+            denominator,
+            clocks_per_click,
+            thirty_seconds_per_quarter,
+        } => (
+            0x58,
+            vec![
+                *numerator,
+                denominator.max(&1).trailing_zeros() as u8,
+                *clocks_per_click,
+                *thirty_seconds_per_quarter,
+            ],
+        ),
+        MetaEvent::KeySignature { sharps, minor } => (0x59, vec![*sharps as u8, *minor as u8]),
+        MetaEvent::EndOfTrack => (0x2F, Vec::new()),
+    };
+
+    bytes.extend_from_slice(&[0xFF, kind]);
+    write_varlen(bytes, data.len() as u32);
+    bytes.extend_from_slice(&data);
+}
+
+// Events in time order. On a tick meta events come first, then note-offs,
+// then controllers and programs ahead of the notes they set up. A single end
+// of track closes it.
+fn write_track(track: &SmfTrack) -> Vec<u8> {
+    let order = |event: &SmfEvent| match &event.kind {
+        SmfEventKind::Meta(_) => 0,
+        SmfEventKind::Message(MidiMessage::NoteOff { .. }) => 1,
+        SmfEventKind::Message(MidiMessage::NoteOn { .. }) => 3,
+        SmfEventKind::Message(_) => 2,
+    };
+
+    let end = track.events.iter().map(|e| e.tick).max().unwrap_or(0);
+    let mut events: Vec<&SmfEvent> = track
+        .events
+        .iter()
+        .filter(|e| e.kind != SmfEventKind::Meta(MetaEvent::EndOfTrack))
+        .collect();
+    events.sort_by_key(|e| (e.tick, order(e)));
+
+    let mut bytes = Vec::new();
+    let mut tick: Tick = 0;
+    for event in events {
+        write_varlen(&mut bytes, (event.tick - tick) as u32);
+        tick = event.tick;
+
+        match &event.kind {
+            SmfEventKind::Meta(meta) => write_meta(&mut bytes, meta),
+            SmfEventKind::Message(message) => {
+                let (data, size) = message.to_bytes();
+                bytes.extend_from_slice(&data[..size]);
+            }
+        }
+    }
+
+    write_varlen(&mut bytes, (end - tick) as u32);
+    write_meta(&mut bytes, &MetaEvent::EndOfTrack);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(clip: &MidiClip) -> Vec<(u8, Tick, Tick)> {
+        clip.notes
+            .iter()
+            .map(|n| (n.pitch, n.start, n.length))
+            .collect()
+    }
+
+    #[test]
+    fn type_0_reads_running_status_and_silent_note_ons() {
+        let mut bytes = b"MThd\0\0\0\x06\0\0\0\x01\0\x60".to_vec();
+        let track: &[u8] = &[
+            0x00, 0xFF, 0x51, 0x03, 0x09, 0x27, 0xC0, // 100 bpm
+            0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08, // 3/4
+            0x00, 0x90, 0x3C, 0x64, // C4 on
+            0x60, 0x3E, 0x50, // D4 on, running status
+            0x60, 0x3C, 0x00, // C4 off as a silent note-on
+            0x00, 0x3E, 0x00, // D4 off
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        bytes.extend_from_slice(b"MTrk");
+        bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(track);
+
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!((smf.format, smf.ppq), (SmfFormat::SingleTrack, 96));
+        assert_eq!(
+            smf.tracks[0].messages().nth(2),
+            Some((
+                192,
+                MidiMessage::NoteOff {
+                    channel: 0,
+                    pitch: 0x3C,
+                    velocity: 64
+                }
+            ))
+        );
+
+        let tempo = smf.tempo();
+        assert_eq!(tempo.bpm.bpm, 100.0);
+        assert_eq!(tempo.time_signature.quarter_notes(), 3.0);
+
+        let clips = smf.clips(&tempo);
+        assert_eq!(clips.len(), 1);
+        assert_eq!((clips[0].name.as_str(), clips[0].ppq), ("Channel 1", 96));
+        assert_eq!(notes(&clips[0]), vec![(0x3C, 0, 192), (0x3E, 96, 96)]);
+        assert_eq!(clips[0].length, 288);
+
+        // Written back without running status, it reads the same
+        let again = Smf::parse(&smf.to_bytes()).unwrap();
+        assert_eq!(again.tracks[0].events, smf.tracks[0].events);
+    }
+
+    #[test]
+    fn type_1_round_trips_the_tempo_map_and_converts_resolution() {
+        let mut tempo = Tempo::new(120.0, TimeSignatures::FourFour);
+        tempo.add_tempo_change(1.0, 90.0, TempoShape::Jump);
+        tempo.add_meter_change(2, TimeSignatures::ThreeFour);
+
+        let mut clip = MidiClip::new("Bass", 1920);
+        clip.ppq = 480;
+        clip.start = 4.0;
+        clip.add_note(Note::new(40, 100, 240, 480));
+        clip.add_note(Note::new(43, 90, 960, 240));
+
+        let smf = Smf::export(&[("Bass", &[clip])], &tempo, None, 96);
+        let smf = Smf::parse(&smf.to_bytes()).unwrap();
+        assert_eq!(
+            (smf.format, smf.ppq, smf.tracks.len()),
+            (SmfFormat::MultiTrack, 96, 2)
+        );
+
+        let read = smf.tempo();
+        assert_eq!(read.bpm.bpm, 120.0);
+        let changes: Vec<(f32, f32)> = read
+            .tempo_changes()
+            .iter()
+            .map(|c| (c.bar, (c.bpm * 100.0).round() / 100.0))
+            .collect();
+        assert_eq!(changes, vec![(1.0, 90.0)]);
+        let meters: Vec<(u32, f32)> = read
+            .meter_changes()
+            .iter()
+            .map(|m| (m.bar, m.time_signature.quarter_notes()))
+            .collect();
+        assert_eq!(meters, vec![(2, 3.0)]);
+
+        let clips = smf.clips(&read);
+        assert_eq!(clips.len(), 1);
+        assert_eq!(
+            (clips[0].name.as_str(), clips[0].start, clips[0].ppq),
+            ("Bass", 4.0, 96)
+        );
+        assert_eq!(notes(&clips[0]), vec![(40, 48, 96), (43, 192, 48)]);
+    }
+}
@@ -1,3 +1,64 @@
-pub struct Channel {}
+use nanoid::nanoid;
 
-impl Channel {}
+use crate::core::param::param::Param;
+use crate::core::param::smoothed::{SmoothedParam, DEFAULT_RAMP};
+
+pub struct Channel {
+    pub id: String,
+    pub name: String,
+    pub samples: Vec<i16>,
+    // Processing latency of whatever produced `samples`, in frames
+    pub latency: u32,
+    volume: SmoothedParam,
+    gain: SmoothedParam,
+}
+
+impl Channel {
+    pub fn new(name: &str, sample_rate: u32) -> Self {
+        let id = nanoid!();
+        let volume = Param::new(&format!("channel.{}.volume", id), "Volume", 0.0, 1.0, 1.0);
+        let gain = Param::new(&format!("channel.{}.gain", id), "Gain", 0.0, 4.0, 1.0);
+
+        Channel {
+            id,
+            name: name.to_string(),
+            samples: Vec::new(),
+            latency: 0,
+            volume: volume.smoothed(sample_rate, DEFAULT_RAMP),
+            gain: gain.smoothed(sample_rate, DEFAULT_RAMP),
+        }
+    }
+
+    // New channel with the same settings
+    pub fn duplicate(&self, name: &str, sample_rate: u32) -> Self {
+        let mut channel = Channel::new(name, sample_rate);
+        channel.latency = self.latency;
+        channel.volume().set(self.volume().get());
+        channel.gain().set(self.gain().get());
+        channel.volume.snap();
+        channel.gain.snap();
+        channel
+    }
+
+    pub fn volume(&self) -> &Param {
+        self.volume.param()
+    }
+
+    pub fn gain(&self) -> &Param {
+        self.gain.param()
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        vec![self.volume().clone(), self.gain().clone()]
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.volume.set_sample_rate(sample_rate);
+        self.gain.set_sample_rate(sample_rate);
+    }
+
+    // Smoothed volume * gain for the next sample, called from the audio side
+    pub fn next_level(&mut self) -> f32 {
+        self.volume.next_value() * self.gain.next_value()
+    }
+}
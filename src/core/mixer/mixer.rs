@@ -1,58 +1,107 @@
 use super::channel::Channel;
+use crate::core::param::param::Param;
+use crate::core::param::smoothed::{SmoothedParam, DEFAULT_RAMP};
 
-struct Mixer {
-    channels: Vec<Channel>,
-    master_volume: f32,
-    master_low_filter: f32,
-    master_mid_filter: f32,
-    master_high_filter: f32,
+pub struct Mixer {
+    pub channels: Vec<Channel>,
+    master_volume: SmoothedParam,
+    master_low_filter: Param,
+    master_mid_filter: Param,
+    master_high_filter: Param,
     mixed_samples: Vec<i16>, // Field to store the mixed output
 }
 
 impl Mixer {
-    fn new() -> Self {
+    pub fn new(sample_rate: u32) -> Self {
+        let master_volume = Param::new("mixer.master.volume", "Master Volume", 0.0, 1.0, 1.0);
+
         Mixer {
             channels: Vec::new(),
-            master_volume: 1.0,
-            master_low_filter: 0.0,
-            master_mid_filter: 0.0,
-            master_high_filter: 0.0,
+            master_volume: master_volume.smoothed(sample_rate, DEFAULT_RAMP),
+            master_low_filter: Param::new("mixer.master.low", "Master Low", -1.0, 1.0, 0.0),
+            master_mid_filter: Param::new("mixer.master.mid", "Master Mid", -1.0, 1.0, 0.0),
+            master_high_filter: Param::new("mixer.master.high", "Master High", -1.0, 1.0, 0.0),
             mixed_samples: Vec::new(),
         }
     }
 
-    fn add_channel(&mut self, channel: Channel) {
+    pub fn add_channel(&mut self, channel: Channel) {
         self.channels.push(channel);
     }
 
-    fn set_master_volume(&mut self, volume: f32) {
-        self.master_volume = volume;
+    pub fn master_volume(&self) -> &Param {
+        self.master_volume.param()
+    }
+
+    // Safe to call from any thread, the audio side picks the value up smoothly
+    pub fn set_master_volume(&self, volume: f32) {
+        self.master_volume.param().set(volume);
+    }
+
+    pub fn set_master_low_filter(&self, value: f32) {
+        self.master_low_filter.set(value);
+    }
+
+    pub fn set_master_mid_filter(&self, value: f32) {
+        self.master_mid_filter.set(value);
+    }
+
+    pub fn set_master_high_filter(&self, value: f32) {
+        self.master_high_filter.set(value);
     }
 
-    // Methods to set master filter values would go here
-    // ...
+    // Every knob on the mixer, including each channel's
+    pub fn params(&self) -> Vec<Param> {
+        let mut params = vec![
+            self.master_volume().clone(),
+            self.master_low_filter.clone(),
+            self.master_mid_filter.clone(),
+            self.master_high_filter.clone(),
+        ];
 
-    fn mix(&mut self) {
+        for channel in &self.channels {
+            params.extend(channel.params());
+        }
+
+        params
+    }
+
+    pub fn latency(&self) -> u32 {
+        self.channels.iter().map(|c| c.latency).max().unwrap_or(0)
+    }
+
+    pub fn mixed_samples(&self) -> &[i16] {
+        &self.mixed_samples
+    }
+
+    pub fn mix(&mut self) {
         self.mixed_samples.clear(); // Clear any previous mixed samples
 
         if self.channels.is_empty() {
             return; // If there are no channels, there is nothing to mix
         }
 
+        // Channels with less latency are delayed to line up with the slowest one
+        let latency = self.latency() as usize;
+
         // Assume all channels have the same length for simplicity
-        let mix_length = self.channels[0].samples.len();
+        let mix_length = self.channels[0].samples.len() + latency;
         for i in 0..mix_length {
             let mut mixed_sample = 0.0;
-            for channel in &self.channels {
-                let sample = channel.samples[i] as f32;
+            for channel in &mut self.channels {
+                let delay = latency - channel.latency as usize;
+                let sample = i
+                    .checked_sub(delay)
+                    .and_then(|i| channel.samples.get(i))
+                    .copied()
+                    .unwrap_or(0) as f32;
                 // Apply individual channel volume and gain here
                 // Apply individual channel filters here
-                mixed_sample += sample * channel.volume * channel.gain;
+                mixed_sample += sample * channel.next_level();
             }
             // Apply master volume and clipping prevention here
-            let final_sample = (mixed_sample * self.master_volume)
-                .min(i16::MAX as f32)
-                .max(i16::MIN as f32) as i16;
+            let final_sample = (mixed_sample * self.master_volume.next_value())
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
             self.mixed_samples.push(final_sample);
         }
     }
@@ -1,2 +1,3 @@
 pub mod channel;
-//pub mod mixer;
+#[allow(clippy::module_inception)]
+pub mod mixer;
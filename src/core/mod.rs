@@ -1,4 +1,14 @@
+pub mod arrangement;
+pub mod automation;
 pub mod clip;
+pub mod engine;
+pub mod groove;
 //pub mod envelope;
+pub mod metronome;
+pub mod midi;
 pub mod mixer;
+pub mod oscillator;
+pub mod param;
+pub mod session;
 pub mod track;
+pub mod wav;
@@ -1,25 +1,45 @@
 use std::f32::consts::PI;
 use std::time::Duration;
 
+use crate::types::hertz::Hz;
+use crate::types::pitch::pitch::Pitch;
+
 pub struct Oscillator {
     pub sample_rate: u32,
-    pub frequency: f32,
+    pub frequency: Hz,
     pub amplitude: f32,
     pub duration: Duration,
     pub samples: Vec<i16>,
 }
 
 impl Oscillator {
-    pub fn new(sample_rate: u32, frequency: f32, duration: Duration, amplitude: f32) -> Oscillator {
+    // Frequencies the sample rate can't carry are clamped below Nyquist
+    pub fn new<F: Into<Hz>>(
+        sample_rate: u32,
+        frequency: F,
+        duration: Duration,
+        amplitude: f32,
+    ) -> Oscillator {
         Oscillator {
             sample_rate,
-            frequency,
+            frequency: frequency.into().clamp_to_nyquist(sample_rate),
             duration,
             amplitude,
             samples: Vec::new(),
         }
     }
 
+    // Leaves the frequency alone when it would alias
+    pub fn set_frequency<F: Into<Hz>>(&mut self, frequency: F) -> anyhow::Result<()> {
+        self.frequency = frequency.into().validate(self.sample_rate)?;
+        Ok(())
+    }
+
+    // Tunes to a note in equal temperament with A4 at `a4`
+    pub fn set_pitch<A: Into<Hz>>(&mut self, pitch: &Pitch, a4: A) -> anyhow::Result<()> {
+        self.set_frequency(pitch.frequency(a4))
+    }
+
     fn create_sine_wave(&self, t: f32, f: f32) -> f32 {
         (f * t * 2.0 * PI).sin()
     }
@@ -49,7 +69,7 @@ impl Oscillator {
         let mut samples: Vec<i16> = Vec::with_capacity(total_samples);
 
         for t in (0..total_samples).map(|x| x as f32 / self.sample_rate as f32) {
-            let v = oscillator_op(t, self.frequency);
+            let v = oscillator_op(t, self.frequency.hz);
             let scaled_value = v * self.amplitude * 32767.0;
             samples.push(scaled_value as i16);
         }
@@ -86,7 +106,7 @@ impl Oscillator {
         F: Fn(f32) -> f32,
     {
         let mut phase = 0.0;
-        let phase_step = self.frequency * 2.0 * PI / self.sample_rate as f32;
+        let phase_step = self.frequency.hz * 2.0 * PI / self.sample_rate as f32;
 
         for (i, sample) in self.samples.iter_mut().enumerate() {
             let t = i as f32 / self.sample_rate as f32;
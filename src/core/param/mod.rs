@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+pub mod param;
+pub mod params;
+pub mod smoothed;
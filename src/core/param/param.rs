@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::smoothed::SmoothedParam;
+
+pub type ParamId = String;
+
+// Ramp hint meaning "use the smoother's own ramp time"
+const NO_RAMP: u32 = u32::MAX;
+
+// A user-visible knob. Clones share the same value, so a clone can be handed to
+// the audio thread while the control thread keeps calling `set`. The value is
+// stored as the bits of an f32 in an atomic, so neither side ever blocks.
+#[derive(Clone)]
+pub struct Param {
+    pub id: ParamId,
+    pub name: String,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    value: Arc<AtomicU32>,
+    // Samples the next change should take to arrive, set by automation
+    ramp: Arc<AtomicU32>,
+}
+
+impl Param {
+    pub fn new(id: &str, name: &str, min: f32, max: f32, default: f32) -> Self {
+        let default = default.clamp(min, max);
+
+        Param {
+            id: id.to_string(),
+            name: name.to_string(),
+            min,
+            max,
+            default,
+            value: Arc::new(AtomicU32::new(default.to_bits())),
+            ramp: Arc::new(AtomicU32::new(NO_RAMP)),
+        }
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.value.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.set_ramped(value, NO_RAMP);
+    }
+
+    // Sets a value that smoothers reach in exactly `samples`, so automation
+    // lands where its breakpoints are
+    pub fn set_ramped(&self, value: f32, samples: u32) {
+        // NaN would poison every smoother downstream, so ignore it
+        if value.is_nan() {
+            return;
+        }
+
+        let clamped = value.clamp(self.min, self.max);
+        self.ramp.store(samples, Ordering::Relaxed);
+        self.value.store(clamped.to_bits(), Ordering::Relaxed);
+    }
+
+    // Ramp length requested with the current value, if any
+    pub fn ramp_hint(&self) -> Option<u32> {
+        let samples = self.ramp.load(Ordering::Relaxed);
+        (samples != NO_RAMP).then_some(samples)
+    }
+
+    pub fn reset(&self) {
+        self.set(self.default);
+    }
+
+    // Value mapped to 0.0..=1.0 across the parameter range
+    pub fn get_normalized(&self) -> f32 {
+        if self.max == self.min {
+            return 0.0;
+        }
+
+        (self.get() - self.min) / (self.max - self.min)
+    }
+
+    pub fn set_normalized(&self, normalized: f32) {
+        let normalized = normalized.clamp(0.0, 1.0);
+        self.set(self.min + normalized * (self.max - self.min));
+    }
+
+    pub fn smoothed(&self, sample_rate: u32, ramp: Duration) -> SmoothedParam {
+        SmoothedParam::new(self.clone(), sample_rate, ramp)
+    }
+}
@@ -0,0 +1,52 @@
+use super::param::Param;
+
+// Registry of every parameter exposed by a studio, looked up by id
+#[derive(Clone, Default)]
+pub struct Params {
+    params: Vec<Param>,
+}
+
+impl Params {
+    pub fn new() -> Self {
+        Params::default()
+    }
+
+    // Registers a parameter, replacing any previous one with the same id
+    pub fn register(&mut self, param: Param) -> Param {
+        self.params.retain(|p| p.id != param.id);
+        self.params.push(param.clone());
+        param
+    }
+
+    pub fn unregister(&mut self, id: &str) -> Option<Param> {
+        let index = self.params.iter().position(|p| p.id == id)?;
+        Some(self.params.remove(index))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Param> {
+        self.params.iter().find(|p| p.id == id)
+    }
+
+    // Returns false if no parameter with this id exists
+    pub fn set(&self, id: &str, value: f32) -> bool {
+        match self.get(id) {
+            Some(param) => {
+                param.set(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Param> {
+        self.params.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use super::param::Param;
+
+pub const DEFAULT_RAMP: Duration = Duration::from_millis(20);
+
+// Audio-side view of a `Param`. Each call to `next_value` reads the shared target and
+// moves linearly towards it over the ramp time, so sudden knob changes do not
+// produce zipper noise.
+pub struct SmoothedParam {
+    param: Param,
+    ramp: Duration,
+    ramp_samples: u32,
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
+}
+
+impl SmoothedParam {
+    pub fn new(param: Param, sample_rate: u32, ramp: Duration) -> Self {
+        let value = param.get();
+
+        SmoothedParam {
+            param,
+            ramp,
+            ramp_samples: Self::samples_for(sample_rate, ramp),
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    fn samples_for(sample_rate: u32, ramp: Duration) -> u32 {
+        (sample_rate as f32 * ramp.as_secs_f32()).round() as u32
+    }
+
+    pub fn param(&self) -> &Param {
+        &self.param
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.ramp_samples = Self::samples_for(sample_rate, self.ramp);
+    }
+
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    // Jumps straight to the parameter value, e.g. when the transport relocates
+    pub fn snap(&mut self) {
+        self.target = self.param.get();
+        self.current = self.target;
+        self.remaining = 0;
+    }
+
+    pub fn next_value(&mut self) -> f32 {
+        let target = self.param.get();
+
+        if target != self.target {
+            self.target = target;
+            self.remaining = self.param.ramp_hint().unwrap_or(self.ramp_samples).max(1);
+            self.step = (target - self.current) / self.remaining as f32;
+        }
+
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+
+        self.current
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use nanoid::nanoid;
+
+use super::slot::{ClipSlot, FollowAction, LaunchQuantization, SlotClip, SlotState};
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+// Copies of each follow target kept cued
+const SPARES: usize = 2;
+
+// One row of the session grid
+#[derive(Clone)]
+pub struct Scene {
+    pub id: String,
+    pub name: String,
+}
+
+// A slot (or a stop, when `scene` is None) starting on a track at sample `at`
+#[derive(Clone, Copy, Debug)]
+pub struct Launch {
+    pub scene: Option<usize>,
+    pub at: u64,
+    pub follow_at: Option<u64>,
+}
+
+// Clip launcher grid. It only holds the clips and what was launched when,
+// the studio places launched clips on the tracks.
+pub struct Session {
+    pub scenes: Vec<Scene>,
+    // One slot per scene, per track id
+    pub slots: HashMap<String, Vec<ClipSlot>>,
+    pub quantization: LaunchQuantization,
+    // Per track, oldest first. The last one started is current, later ones are queued.
+    launches: HashMap<String, Vec<Launch>>,
+    seed: u64,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            scenes: Vec::new(),
+            slots: HashMap::new(),
+            quantization: LaunchQuantization::default(),
+            launches: HashMap::new(),
+            seed: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    // Returns the new scene's index
+    pub fn add_scene(&mut self, name: &str) -> usize {
+        self.insert_scene(self.scenes.len(), name)
+    }
+
+    pub fn insert_scene(&mut self, index: usize, name: &str) -> usize {
+        let index = index.min(self.scenes.len());
+        self.scenes.insert(
+            index,
+            Scene {
+                id: nanoid!(),
+                name: name.to_string(),
+            },
+        );
+
+        for column in self.slots.values_mut() {
+            if column.len() >= index {
+                column.insert(index, ClipSlot::default());
+            }
+        }
+        for launch in self.launches.values_mut().flatten() {
+            if let Some(scene) = launch.scene.as_mut().filter(|s| **s >= index) {
+                *scene += 1;
+            }
+        }
+        index
+    }
+
+    // Scenes with a launched clip have to be stopped first
+    pub fn remove_scene(&mut self, index: usize) -> Result<Scene> {
+        if index >= self.scenes.len() {
+            return Err(anyhow!("no scene {}", index));
+        }
+        if self
+            .launches
+            .values()
+            .flatten()
+            .any(|l| l.scene == Some(index))
+        {
+            return Err(anyhow!("scene {} is playing", self.scenes[index].name));
+        }
+
+        for column in self.slots.values_mut() {
+            if index < column.len() {
+                column.remove(index);
+            }
+        }
+        for launch in self.launches.values_mut().flatten() {
+            if let Some(scene) = launch.scene.as_mut().filter(|s| **s > index) {
+                *scene -= 1;
+            }
+        }
+        Ok(self.scenes.remove(index))
+    }
+
+    // Looks a scene up by id or by name
+    pub fn scene_index(&self, key: &str) -> Option<usize> {
+        self.scenes
+            .iter()
+            .position(|s| s.id == key)
+            .or_else(|| self.scenes.iter().position(|s| s.name == key))
+    }
+
+    pub fn slot(&self, track_id: &str, scene: usize) -> Option<&ClipSlot> {
+        self.slots.get(track_id)?.get(scene)
+    }
+
+    // Creates the track's column as needed
+    pub fn slot_mut(&mut self, track_id: &str, scene: usize) -> Option<&mut ClipSlot> {
+        if scene >= self.scenes.len() {
+            return None;
+        }
+
+        let column = self.slots.entry(track_id.to_string()).or_default();
+        if column.len() < self.scenes.len() {
+            column.resize_with(self.scenes.len(), ClipSlot::default);
+        }
+        column.get_mut(scene)
+    }
+
+    pub fn set_clip(&mut self, track_id: &str, scene: usize, clip: SlotClip) -> Result<()> {
+        let slot = self
+            .slot_mut(track_id, scene)
+            .ok_or_else(|| anyhow!("no scene {}", scene))?;
+        slot.clip = Some(clip);
+        slot.spares = Vec::new();
+        Ok(())
+    }
+
+    pub fn clear_clip(&mut self, track_id: &str, scene: usize) -> Option<SlotClip> {
+        let slot = self.slot_mut(track_id, scene)?;
+        slot.spares = Vec::new();
+        slot.clip.take()
+    }
+
+    // Copies every clip a follow action could launch ahead of time. Call from
+    // the control thread after changing slots or follow rules.
+    pub fn cue_follows(&mut self) {
+        for column in self.slots.values_mut() {
+            if column.iter().all(|s| s.follow.is_none()) {
+                continue;
+            }
+            for slot in column.iter_mut() {
+                let Some(clip) = &slot.clip else {
+                    continue;
+                };
+                slot.spares.reserve_exact(SPARES);
+                while slot.spares.len() < SPARES {
+                    slot.spares.push(clip.clone());
+                }
+            }
+        }
+    }
+
+    // A copy of the slot's clip to launch, the cued one when there is one.
+    // Copying allocates, the audio thread takes spares only.
+    pub fn take_clip(&mut self, track_id: &str, scene: usize) -> Option<SlotClip> {
+        let slot = self.slots.get_mut(track_id)?.get_mut(scene)?;
+        slot.spares.pop().or_else(|| slot.clip.clone())
+    }
+
+    // A cued copy of the slot's clip, None once they have run out
+    pub fn take_spare(&mut self, track_id: &str, scene: usize) -> Option<SlotClip> {
+        self.slots.get_mut(track_id)?.get_mut(scene)?.spares.pop()
+    }
+
+    // Lays out every slot clip and its spares, so launching only moves them
+    pub fn layout(&mut self, tempo: &Tempo, sample_rate: u32) {
+        for slot in self.slots.values_mut().flatten() {
+            for clip in slot.clip.iter_mut().chain(slot.spares.iter_mut()) {
+                clip.layout(tempo, sample_rate);
+            }
+        }
+    }
+
+    // Takes a launched copy back once it is done with, cued for its slot's
+    // next launch
+    pub fn recycle(&mut self, track_id: &str, clip: SlotClip) {
+        let slot = self.slots.get_mut(track_id).and_then(|column| {
+            column.iter_mut().find(|s| {
+                s.spares.len() < s.spares.capacity()
+                    && s.clip.as_ref().is_some_and(|c| c.id() == clip.id())
+            })
+        });
+        if let Some(slot) = slot {
+            slot.spares.push(clip);
+        }
+    }
+
+    pub fn remove_track(&mut self, track_id: &str) {
+        self.slots.remove(track_id);
+        self.launches.remove(track_id);
+    }
+
+    pub fn launches(&self, track_id: &str) -> &[Launch] {
+        self.launches.get(track_id).map(|l| &l[..]).unwrap_or(&[])
+    }
+
+    // The launch sounding at `position`
+    pub fn current(&self, track_id: &str, position: u64) -> Option<&Launch> {
+        self.launches(track_id)
+            .iter()
+            .rev()
+            .find(|l| l.at <= position)
+    }
+
+    pub fn slot_state(&self, track_id: &str, scene: usize, position: u64) -> SlotState {
+        if self.slot(track_id, scene).is_none_or(|s| s.clip.is_none()) {
+            return SlotState::Empty;
+        }
+
+        let launches = self.launches(track_id);
+        if launches
+            .iter()
+            .any(|l| l.at > position && l.scene == Some(scene))
+        {
+            SlotState::Queued
+        } else if self.current(track_id, position).and_then(|l| l.scene) == Some(scene) {
+            SlotState::Playing
+        } else {
+            SlotState::Stopped
+        }
+    }
+
+    pub fn quantization_for(&self, track_id: &str, scene: Option<usize>) -> LaunchQuantization {
+        scene
+            .and_then(|s| self.slot(track_id, s))
+            .and_then(|s| s.quantization)
+            .unwrap_or(self.quantization)
+    }
+
+    // Records a launch, replacing anything queued at or after it
+    pub fn push_launch(&mut self, track_id: &str, launch: Launch) {
+        if !self.launches.contains_key(track_id) {
+            self.launches
+                .insert(track_id.to_string(), Vec::with_capacity(4));
+        }
+        let Some(launches) = self.launches.get_mut(track_id) else {
+            return;
+        };
+        launches.retain(|l| l.at < launch.at);
+        launches.push(launch);
+    }
+
+    // Forgets launches that have been superseded by `position`
+    pub fn prune(&mut self, position: u64) {
+        for launches in self.launches.values_mut() {
+            let current = launches.iter().rposition(|l| l.at <= position);
+            if let Some(current) = current.filter(|c| *c > 0) {
+                launches.drain(..current);
+            }
+        }
+    }
+
+    pub fn clear_launches(&mut self) {
+        self.launches.clear();
+    }
+
+    // Where the track's current clip follows on, if it is due before `end`
+    pub fn follow_due(&self, track_id: &str, end: u64) -> Option<(usize, u64)> {
+        let launch = self.launches(track_id).last()?;
+        let at = launch.follow_at.filter(|at| *at < end)?;
+        Some((launch.scene?, at))
+    }
+
+    // Lets the track's current clip play on instead of following
+    pub fn skip_follow(&mut self, track_id: &str) {
+        if let Some(launch) = self.launches.get_mut(track_id).and_then(|l| l.last_mut()) {
+            launch.follow_at = None;
+        }
+    }
+
+    // Rolls the follow rule of a slot. Returns the scene to launch, or None to stop.
+    pub fn follow(&mut self, track_id: &str, scene: usize) -> Option<usize> {
+        let roll = self.random();
+        let action = self
+            .slot(track_id, scene)
+            .and_then(|s| s.follow.as_ref())
+            .and_then(|f| f.pick(roll))?;
+
+        let roll = match action {
+            FollowAction::Any | FollowAction::Random => self.random(),
+            _ => 0.0,
+        };
+
+        // Runs on the audio thread, so the filled slots are walked, not collected
+        let column = self.slots.get(track_id)?;
+        let filled = || {
+            column
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.clip.is_some())
+                .map(|(i, _)| i)
+        };
+        let pick = |count: usize| ((roll * count as f32) as usize).min(count.max(1) - 1);
+
+        match action {
+            FollowAction::Stop => None,
+            FollowAction::Again => Some(scene),
+            FollowAction::Next => filled().find(|i| *i > scene).or_else(|| filled().next()),
+            FollowAction::Previous => filled()
+                .rev()
+                .find(|i| *i < scene)
+                .or_else(|| filled().next_back()),
+            FollowAction::First => filled().next(),
+            FollowAction::Last => filled().next_back(),
+            FollowAction::Any => {
+                let count = filled().count();
+                filled().nth(pick(count))
+            }
+            FollowAction::Random => {
+                let others = || filled().filter(|i| *i != scene);
+                let count = others().count();
+                others().nth(pick(count)).or(Some(scene))
+            }
+        }
+    }
+
+    // xorshift64*, uniform in 0..1
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed >> 12;
+        self.seed ^= self.seed << 25;
+        self.seed ^= self.seed >> 27;
+        let value = self.seed.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (value >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
@@ -0,0 +1,237 @@
+use crate::core::clip::clip::AudioClip;
+use crate::core::clip::midi_clip::MidiClip;
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+#[derive(Clone)]
+pub enum SlotClip {
+    Audio(AudioClip),
+    Midi(MidiClip),
+}
+
+impl SlotClip {
+    pub fn id(&self) -> &str {
+        match self {
+            SlotClip::Audio(clip) => &clip.id,
+            SlotClip::Midi(clip) => &clip.id,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            SlotClip::Audio(clip) => &clip.name,
+            SlotClip::Midi(clip) => &clip.name,
+        }
+    }
+
+    pub fn layout(&mut self, tempo: &Tempo, sample_rate: u32) {
+        match self {
+            SlotClip::Audio(clip) => clip.layout(tempo, sample_rate),
+            SlotClip::Midi(clip) => clip.layout(tempo, sample_rate),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FollowAction {
+    Stop,
+    Again,
+    Next,
+    Previous,
+    First,
+    Last,
+    // Any other clip in the column
+    Random,
+    // Any clip in the column, this one included
+    Any,
+}
+
+// What happens once a clip has played for a while. Actions are picked with
+// a chance proportional to their weight.
+#[derive(Clone)]
+pub struct FollowRule {
+    // Beats to play before following, None for one pass of the clip
+    pub after: Option<Beat>,
+    pub actions: Vec<(FollowAction, f32)>,
+}
+
+impl Default for FollowRule {
+    fn default() -> Self {
+        FollowRule {
+            after: None,
+            actions: vec![(FollowAction::Next, 1.0)],
+        }
+    }
+}
+
+impl FollowRule {
+    pub fn new<A: Into<Option<Beat>>>(after: A, actions: Vec<(FollowAction, f32)>) -> Self {
+        FollowRule {
+            after: after.into(),
+            actions,
+        }
+    }
+
+    // `roll` is uniform in 0..1
+    pub fn pick(&self, roll: f32) -> Option<FollowAction> {
+        let total: f32 = self.actions.iter().map(|(_, w)| w.max(0.0)).sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut target = roll * total;
+        for (action, weight) in &self.actions {
+            target -= weight.max(0.0);
+            if target < 0.0 {
+                return Some(*action);
+            }
+        }
+        self.actions.last().map(|(action, _)| *action)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum LaunchQuantization {
+    None,
+    Beat,
+    // Next group of the meter, the 1, 3 and 5 of 2+2+3/8
+    Group,
+    #[default]
+    Bar,
+    Bars(u32),
+}
+
+impl LaunchQuantization {
+    // First grid line at or after `position`, in beats. Bars follow meter changes.
+    pub fn next_beat(&self, position: u64, tempo: &Tempo, sample_rate: u32) -> Beat {
+        let beats = tempo.sample_to_beats(position as f64, sample_rate);
+        let up = |value: f64, grid: f64| (value / grid - 1e-9).ceil() * grid;
+
+        let beat = match self {
+            LaunchQuantization::None => beats,
+            LaunchQuantization::Beat => up(beats, 1.0),
+            LaunchQuantization::Group => tempo.next_group(beats),
+            LaunchQuantization::Bar => tempo.bar_to_beat(up(tempo.beat_to_bar(beats), 1.0)),
+            LaunchQuantization::Bars(bars) => {
+                let grid = (*bars).max(1) as f64;
+                tempo.bar_to_beat(up(tempo.beat_to_bar(beats), grid))
+            }
+        };
+        beat as Beat
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ClipSlot {
+    pub clip: Option<SlotClip>,
+    pub follow: Option<FollowRule>,
+    // Overrides the session's launch quantization
+    pub quantization: Option<LaunchQuantization>,
+    // Copies of `clip` cued for follow actions, so launching from the audio
+    // thread does not clone. One can be playing while the next is queued.
+    pub(crate) spares: Vec<SlotClip>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SlotState {
+    Empty,
+    Stopped,
+    Queued,
+    Playing,
+}
+
+// A launched copy of a slot clip sitting on its track's timeline, looping
+// until it is cut off
+pub trait LaunchedClip {
+    fn start_sample(&self) -> u64;
+    fn end_sample(&self) -> u64;
+    // Sets up looping from `start` beats, one pass long. Runs on the audio
+    // thread, clips come laid out on `tempo` from the control thread.
+    fn launch(&mut self, start: Beat, tempo: &Tempo, sample_rate: u32);
+    fn cycle_samples(&self) -> u64;
+    // Ends the clip at a timeline sample
+    fn cut(&mut self, at: u64);
+    // Adds passes until the clip reaches `sample`
+    fn extend_to(&mut self, sample: u64);
+}
+
+impl LaunchedClip for AudioClip {
+    fn start_sample(&self) -> u64 {
+        AudioClip::start_sample(self)
+    }
+
+    fn end_sample(&self) -> u64 {
+        AudioClip::end_sample(self)
+    }
+
+    fn launch(&mut self, start: Beat, tempo: &Tempo, sample_rate: u32) {
+        self.start = start;
+        self.layout(tempo, sample_rate);
+        self.loop_length = Some(self.loop_length.unwrap_or(self.length).max(1));
+        self.length = self.cycle_samples();
+    }
+
+    fn cycle_samples(&self) -> u64 {
+        self.loop_length.unwrap_or(self.length).max(1)
+    }
+
+    fn cut(&mut self, at: u64) {
+        self.length = self.length.min(at.saturating_sub(self.start_sample()));
+    }
+
+    fn extend_to(&mut self, sample: u64) {
+        let cycle = self.cycle_samples();
+        while self.end_sample() < sample {
+            self.length += cycle;
+        }
+    }
+}
+
+impl LaunchedClip for MidiClip {
+    fn start_sample(&self) -> u64 {
+        MidiClip::start_sample(self)
+    }
+
+    fn end_sample(&self) -> u64 {
+        MidiClip::end_sample(self)
+    }
+
+    fn launch(&mut self, start: Beat, _tempo: &Tempo, _sample_rate: u32) {
+        self.place(start);
+        let cycle = self.cycle_length().max(1);
+        self.loop_length = Some(cycle);
+        self.length = cycle;
+    }
+
+    fn cycle_samples(&self) -> u64 {
+        self.tick_to_sample(self.cycle_length()) - self.start_sample()
+    }
+
+    fn cut(&mut self, at: u64) {
+        self.length = self.length.min(self.sample_to_tick(at));
+    }
+
+    fn extend_to(&mut self, sample: u64) {
+        let cycle = self.cycle_length().max(1);
+        while self.end_sample() < sample {
+            self.length += cycle;
+        }
+    }
+}
+
+// Cuts a track's launched clips at `at`. Clips that had not started yet are
+// handed to `done`, they sit at the end as launches only ever queue later.
+pub fn cut_clips<C: LaunchedClip>(clips: &mut Vec<C>, at: u64, mut done: impl FnMut(C)) {
+    while let Some(clip) = clips.pop_if(|c| c.start_sample() >= at) {
+        done(clip);
+    }
+    for clip in clips.iter_mut() {
+        clip.cut(at);
+    }
+}
+
+// Hands clips that finished before `position` to `done`
+pub fn prune_clips<C: LaunchedClip>(clips: &mut Vec<C>, position: u64, done: impl FnMut(C)) {
+    let finished = clips.partition_point(|c| c.end_sample() <= position);
+    clips.drain(..finished).for_each(done);
+}
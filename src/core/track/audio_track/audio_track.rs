@@ -1,33 +1,616 @@
-use jack;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
 use nanoid::nanoid;
 
+use super::comp::{Comp, CompSegment};
+use super::take::Take;
+use crate::core::clip::clip::AudioClip;
+use crate::core::engine::node::{Node, ProcessContext};
+use crate::core::wav::wav::write_wav;
+use crate::types::rhythm::tempo::bpm::bar::Bar;
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+// How much audio to reserve when arming without a punch range
+pub const DEFAULT_RECORD_RESERVE: Duration = Duration::from_secs(60);
+
+// Punch range in samples, converted from bars when it is set
+#[derive(Clone)]
+pub struct Punch {
+    pub start_bar: Bar,
+    pub end_bar: Bar,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Punch {
+    pub fn insert_time(&mut self, at: Bar, bars: Bar) {
+        if self.start_bar >= at {
+            self.start_bar += bars;
+        }
+        if self.end_bar > at {
+            self.end_bar += bars;
+        }
+    }
+
+    // Trims the range to what is left outside `start..end`, false when nothing is
+    pub fn delete_time(&mut self, start: Bar, end: Bar) -> bool {
+        let shift = |bar: Bar| {
+            if bar >= end {
+                bar - (end - start)
+            } else {
+                bar.min(start)
+            }
+        };
+
+        self.start_bar = shift(self.start_bar);
+        self.end_bar = shift(self.end_bar);
+        self.end_bar > self.start_bar
+    }
+}
+
+// Audio captured on the audio thread, waiting to be written to disk
+struct Recording {
+    start: Option<u64>,
+    samples: Vec<f32>,
+    sample_rate: u32,
+    stopped: bool,
+}
+
+impl Recording {
+    fn new(capacity: usize, sample_rate: u32) -> Self {
+        Recording {
+            start: None,
+            samples: Vec::with_capacity(capacity),
+            sample_rate,
+            stopped: false,
+        }
+    }
+}
+
 pub struct AudioTrack {
-    id: String,
-    name: String,
-    file_path: Option<String>,
+    pub id: String,
+    pub name: String,
+    pub file_path: Option<String>,
+    pub armed: bool,
+    pub monitor: bool,
+    pub punch: Option<Punch>,
+    // Extra frames to compensate on top of what the backend reports
+    pub latency_offset: u32,
+    pub take_dir: PathBuf,
+    // Longest pass without a punch range, capture stops once it is full
+    pub record_reserve: Duration,
+    // Set when a pass ran out of room and stopped early, cleared by the caller
+    pub record_full: bool,
+    pub takes: Vec<Take>,
+    pub comp: Comp,
+    // Sorted by start and never overlapping
+    pub clips: Vec<AudioClip>,
+    // Clips launched from the session, on the same timeline
+    pub session_clips: Vec<AudioClip>,
+    // The session takes over from the arrangement at this sample
+    pub session_from: Option<u64>,
+    recording: Option<Recording>,
+    // Swapped in when the loop wraps mid pass, so the next pass is a take of
+    // its own without allocating. Refilled by `flush_takes`.
+    spare: Option<Recording>,
+    // Where the transport is due next block while recording, to spot jumps
+    clock: Option<u64>,
+    // Position and frames left of input played before the last jump
+    loop_tail: Option<(u64, u64)>,
+    finished: Vec<Recording>,
 }
 
 impl Default for AudioTrack {
     fn default() -> Self {
-        AudioTrack {
-            id: nanoid!(),
-            name: "Audio Track".to_string(),
-            file_path: None,
-        }
+        AudioTrack::new("Audio Track", None)
     }
 }
 
 impl AudioTrack {
-    fn new<N: Option<&str>, P: Option<&str>>(name: N, path: P) -> Self {
-        if (!path) {
-            // Set path to name
-            path = None;
+    pub fn new<'a, P: Into<Option<&'a str>>>(name: &str, path: P) -> Self {
+        AudioTrack {
+            id: nanoid!(),
+            name: name.to_string(),
+            file_path: path.into().map(|p| p.to_string()),
+            armed: false,
+            monitor: false,
+            punch: None,
+            latency_offset: 0,
+            take_dir: std::env::temp_dir().join("noyz"),
+            record_reserve: DEFAULT_RECORD_RESERVE,
+            record_full: false,
+            takes: Vec::new(),
+            comp: Comp::default(),
+            clips: Vec::new(),
+            session_clips: Vec::with_capacity(4),
+            session_from: None,
+            recording: None,
+            spare: None,
+            clock: None,
+            loop_tail: None,
+            finished: Vec::new(),
         }
+    }
 
+    // Copy with new ids, sharing clip audio. Takes and the comp come along,
+    // an unfinished recording does not.
+    pub fn duplicate(&self) -> Self {
         AudioTrack {
             id: nanoid!(),
-            name: name.to_string(),
-            file_path: path.to_string(),
+            name: self.name.clone(),
+            file_path: self.file_path.clone(),
+            armed: false,
+            monitor: self.monitor,
+            punch: self.punch.clone(),
+            latency_offset: self.latency_offset,
+            take_dir: self.take_dir.clone(),
+            record_reserve: self.record_reserve,
+            record_full: false,
+            takes: self.takes.clone(),
+            comp: self.comp.clone(),
+            clips: self.clips.iter().map(|c| c.duplicate()).collect(),
+            session_clips: Vec::with_capacity(4),
+            session_from: None,
+            recording: None,
+            spare: None,
+            clock: None,
+            loop_tail: None,
+            finished: Vec::new(),
+        }
+    }
+
+    // Records between two bar positions only, counted from the start of the song
+    pub fn set_punch(&mut self, start_bar: Bar, end_bar: Bar, tempo: &Tempo, sample_rate: u32) {
+        let to_samples =
+            |bar: Bar| (tempo.bars_to_time(bar).as_secs_f64() * sample_rate as f64).round() as u64;
+
+        self.punch = Some(Punch {
+            start_bar,
+            end_bar,
+            start: to_samples(start_bar),
+            end: to_samples(end_bar),
+        });
+    }
+
+    pub fn clear_punch(&mut self) {
+        self.punch = None;
+    }
+
+    // Arms the track and reserves the capture buffers up front. The audio
+    // thread never grows them, a pass that fills one stops and sets `record_full`.
+    pub fn arm(&mut self, sample_rate: u32) {
+        let capacity = self.record_capacity(sample_rate);
+
+        self.armed = true;
+        self.clock = None;
+        self.recording = Some(Recording::new(capacity, sample_rate));
+        if self.spare.is_none() {
+            self.spare = Some(Recording::new(capacity, sample_rate));
+        }
+        // Room for a pass the loop wraps out of
+        self.finished.reserve(1);
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed = false;
+        self.finish_recording();
+        self.spare = None;
+    }
+
+    fn record_capacity(&self, sample_rate: u32) -> usize {
+        match &self.punch {
+            Some(punch) => (punch.end.saturating_sub(punch.start)) as usize,
+            None => (self.record_reserve.as_secs_f64() * sample_rate as f64) as usize,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+            .as_ref()
+            .is_some_and(|r| r.start.is_some() && !r.stopped)
+    }
+
+    fn finish_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            if recording.start.is_some() && !recording.samples.is_empty() {
+                self.finished.push(recording);
+            }
+        }
+    }
+
+    // Writes finished passes to `take_dir` as new takes. Call from the
+    // control thread, never from the audio callback.
+    pub fn flush_takes(&mut self) -> Result<usize> {
+        if self.recording.as_ref().is_some_and(|r| r.stopped) {
+            let sample_rate = self
+                .recording
+                .as_ref()
+                .map(|r| r.sample_rate)
+                .unwrap_or(44100);
+            self.finish_recording();
+            self.arm(sample_rate);
+        }
+
+        if self.armed && self.spare.is_none() {
+            let sample_rate = self.recording.as_ref().map_or(44100, |r| r.sample_rate);
+            let capacity = self.record_capacity(sample_rate);
+            self.spare = Some(Recording::new(capacity, sample_rate));
+        }
+
+        if self.finished.is_empty() {
+            return Ok(0);
+        }
+
+        std::fs::create_dir_all(&self.take_dir)?;
+
+        let count = self.finished.len();
+        for recording in self.finished.drain(..) {
+            let id = nanoid!();
+            let path = self.take_dir.join(format!(
+                "{}-take-{}-{}.wav",
+                self.name,
+                self.takes.len() + 1,
+                id
+            ));
+
+            write_wav(&path, recording.sample_rate, &recording.samples)?;
+
+            self.takes.push(Take {
+                id,
+                path,
+                offset: 0,
+                start: recording.start.unwrap_or(0),
+                length: recording.samples.len() as u64,
+                sample_rate: recording.sample_rate,
+                samples: recording.samples,
+            });
+        }
+
+        Ok(count)
+    }
+
+    pub fn take(&self, id: &str) -> Option<&Take> {
+        self.takes.iter().find(|t| t.id == id)
+    }
+
+    pub fn remove_take(&mut self, id: &str) -> Option<Take> {
+        let index = self.takes.iter().position(|t| t.id == id)?;
+        let take = self.takes.remove(index);
+        self.comp.retain_takes(&self.takes);
+        Some(take)
+    }
+
+    // Puts `start..end` (in samples) of a take into the comp
+    pub fn comp_select(&mut self, take_id: &str, start: u64, end: u64) -> Result<()> {
+        if self.take(take_id).is_none() {
+            return Err(anyhow!("track {} has no take {}", self.name, take_id));
+        }
+
+        self.comp.select(take_id, start, end);
+        Ok(())
+    }
+
+    // Opens `length` samples at `at`, moving later takes and comp segments
+    pub fn insert_time(&mut self, at: u64, length: u64) {
+        if length == 0 {
+            return;
+        }
+
+        self.split_takes(at);
+        for take in &mut self.takes {
+            if take.start >= at {
+                take.start += length;
+            }
+        }
+        for segment in &mut self.comp.segments {
+            if segment.start >= at {
+                segment.start += length;
+                segment.end += length;
+            }
+        }
+    }
+
+    // Cuts `start..end` (in samples) out of the takes and the comp
+    pub fn delete_time(&mut self, start: u64, end: u64) {
+        if end <= start {
+            return;
+        }
+
+        if self.takes.iter().any(|t| t.start < end && t.end() > start) {
+            self.comp_newest_take();
+        }
+        self.split_takes(start);
+        self.split_takes(end);
+
+        let length = end - start;
+        self.takes.retain(|t| t.end() <= start || t.start >= end);
+        for take in &mut self.takes {
+            if take.start >= end {
+                take.start -= length;
+            }
+        }
+
+        self.comp
+            .segments
+            .retain(|s| s.end <= start || s.start >= end);
+        for segment in &mut self.comp.segments {
+            if segment.start >= end {
+                segment.start -= length;
+                segment.end -= length;
+            }
+        }
+    }
+
+    // Repeats `start..end` (in samples) of the takes and the comp after itself
+    pub fn duplicate_time(&mut self, start: u64, end: u64) {
+        if end <= start {
+            return;
+        }
+
+        self.comp_newest_take();
+        let length = end - start;
+
+        let mut copies: Vec<(String, Take)> = Vec::new();
+        for take in self
+            .takes
+            .iter()
+            .filter(|t| t.start < end && t.end() > start)
+        {
+            let mut copy = take.slice(start, end);
+            copy.start += length;
+            copies.push((take.id.clone(), copy));
+        }
+
+        let segments: Vec<CompSegment> = self
+            .comp
+            .segments
+            .iter()
+            .filter(|s| s.start < end && s.end > start)
+            .filter_map(|s| {
+                let (_, copy) = copies.iter().find(|(id, _)| *id == s.take_id)?;
+                Some(CompSegment {
+                    take_id: copy.id.clone(),
+                    start: s.start.max(start) + length,
+                    end: s.end.min(end) + length,
+                })
+            })
+            .collect();
+
+        self.insert_time(end, length);
+
+        self.takes.extend(copies.into_iter().map(|(_, copy)| copy));
+        self.comp.segments.extend(segments);
+        self.comp.segments.sort_by_key(|s| s.start);
+    }
+
+    // Without a comp the newest take plays, once takes are cut up and moved
+    // that has to be spelled out
+    fn comp_newest_take(&mut self) {
+        if let Some(take) = self.takes.last().filter(|_| self.comp.is_empty()) {
+            let (id, start, end) = (take.id.clone(), take.start, take.end());
+            self.comp.select(&id, start, end);
+        }
+    }
+
+    // Splits the takes crossing `at`, comp segments on the right move over to
+    // the right halves
+    fn split_takes(&mut self, at: u64) {
+        if !self.takes.iter().any(|t| t.start < at && at < t.end()) {
+            return;
+        }
+
+        self.comp_newest_take();
+        self.comp.split(at);
+
+        let mut index = 0;
+        while index < self.takes.len() {
+            let take = &self.takes[index];
+            if take.start < at && at < take.end() {
+                let (left, right) = take.split(at);
+                for segment in &mut self.comp.segments {
+                    if segment.start >= at && segment.take_id == left.id {
+                        segment.take_id = right.id.clone();
+                    }
+                }
+
+                self.takes[index] = left;
+                self.takes.insert(index + 1, right);
+                index += 1;
+            }
+            index += 1;
+        }
+    }
+
+    // Places a clip on the track, refusing it if it overlaps another one
+    pub fn insert_clip(
+        &mut self,
+        mut clip: AudioClip,
+        tempo: &Tempo,
+        sample_rate: u32,
+    ) -> Result<()> {
+        clip.layout(tempo, sample_rate);
+
+        if let Some(other) = self.clips.iter().find(|c| c.overlaps(&clip)) {
+            return Err(anyhow!("clip {} overlaps clip {}", clip.name, other.name));
+        }
+
+        let index = self
+            .clips
+            .partition_point(|c| c.start_sample() < clip.start_sample());
+        self.clips.insert(index, clip);
+        Ok(())
+    }
+
+    pub fn remove_clip(&mut self, id: &str) -> Option<AudioClip> {
+        let index = self.clips.iter().position(|c| c.id == id)?;
+        Some(self.clips.remove(index))
+    }
+
+    pub fn clip_mut(&mut self, id: &str) -> Option<&mut AudioClip> {
+        self.clips.iter_mut().find(|c| c.id == id)
+    }
+
+    // Re-resolves clip positions and the punch range after a tempo, sample
+    // rate or arrangement change
+    pub fn layout_clips(&mut self, tempo: &Tempo, sample_rate: u32) {
+        for clip in &mut self.clips {
+            clip.layout(tempo, sample_rate);
+        }
+        self.clips.sort_by_key(|c| c.start_sample());
+
+        if let Some(punch) = &self.punch {
+            let (start_bar, end_bar) = (punch.start_bar, punch.end_bar);
+            self.set_punch(start_bar, end_bar, tempo, sample_rate);
+        }
+    }
+
+    // Lets the arrangement play again
+    pub fn back_to_arrangement(&mut self) {
+        self.session_clips.clear();
+        self.session_from = None;
+    }
+
+    fn play_clips(clips: &[AudioClip], position: u64, output: &mut [f32]) {
+        let end = position + output.len() as u64;
+        let first = clips.partition_point(|c| c.end_sample() <= position);
+
+        for clip in clips[first..].iter().take_while(|c| c.start_sample() < end) {
+            clip.render(position, output);
+        }
+    }
+
+    // True where an armed track replaces playback with what it records
+    fn records_at(&self, position: u64) -> bool {
+        self.armed
+            && self
+                .punch
+                .as_ref()
+                .is_none_or(|p| position >= p.start && position < p.end)
+    }
+
+    // Renders the comp from `position` on. Without a comp the newest take
+    // plays, as it sits on top of the others.
+    fn play_takes(&self, position: u64, output: &mut [f32]) {
+        for (i, sample) in output.iter_mut().enumerate() {
+            let position = position + i as u64;
+            if self.records_at(position) {
+                continue;
+            }
+
+            *sample += match self.takes.last() {
+                Some(take) if self.comp.is_empty() => take.sample_at(position),
+                _ => self.comp.sample_at(&self.takes, position),
+            };
+        }
+    }
+
+    fn record(&mut self, context: &ProcessContext, input: &[f32]) {
+        let latency = (context.input_latency + self.latency_offset) as u64;
+        let punch = self.punch.as_ref().map(|p| (p.start, p.end));
+
+        // The play head jumped back, the loop wrapped or it was moved. Input
+        // played before the jump is still arriving for `latency` frames.
+        if let Some(clock) = self.clock.filter(|clock| context.position < *clock) {
+            self.loop_tail = Some((clock.saturating_sub(latency), latency.min(clock)));
+        }
+        self.clock = Some(context.position + context.frames as u64);
+
+        let Some(recording) = self.recording.as_mut().filter(|r| !r.stopped) else {
+            return;
+        };
+
+        for i in 0..context.frames {
+            let tail = match self.loop_tail.as_mut() {
+                Some((position, left)) if *left > 0 => {
+                    *left -= 1;
+                    *position += 1;
+                    Some(*position - 1)
+                }
+                _ => None,
+            };
+
+            let position = match tail {
+                Some(position) => position,
+                None => {
+                    if self.loop_tail.take().is_some() && recording.start.is_some() {
+                        // What comes after the jump is another take. Without
+                        // a spare the pass ends where the jump was.
+                        let Some(spare) = self.spare.take() else {
+                            recording.stopped = true;
+                            return;
+                        };
+                        // `arm` reserved room for it in `finished`
+                        self.finished.push(std::mem::replace(recording, spare));
+                    }
+
+                    // The sample arriving now was played `latency` frames ago
+                    match (context.position + i as u64).checked_sub(latency) {
+                        Some(position) => position,
+                        None => continue,
+                    }
+                }
+            };
+
+            if let Some((start, end)) = punch {
+                if position < start || position >= end {
+                    continue;
+                }
+            }
+
+            if recording.samples.len() == recording.samples.capacity() {
+                // Pushing on would reallocate, the pass ends here
+                recording.stopped = true;
+                self.record_full = true;
+                return;
+            }
+
+            if recording.start.is_none() {
+                recording.start = Some(position);
+            }
+            recording.samples.push(input.get(i).copied().unwrap_or(0.0));
+        }
+    }
+}
+
+impl Node for AudioTrack {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process(&mut self, context: &ProcessContext, input: &[f32], output: &mut [f32]) {
+        if context.playing {
+            // The arrangement only plays up to where the session took over
+            let arranged = self.session_from.map_or(output.len(), |from| {
+                from.saturating_sub(context.position)
+                    .min(output.len() as u64) as usize
+            });
+
+            AudioTrack::play_clips(&self.clips, context.position, &mut output[..arranged]);
+            self.play_takes(context.position, &mut output[..arranged]);
+            AudioTrack::play_clips(&self.session_clips, context.position, output);
+        }
+
+        if self.armed {
+            if context.playing {
+                self.record(context, input);
+            } else {
+                self.clock = None;
+                if self.is_recording() {
+                    // Transport stopped, the pass is picked up by `flush_takes`
+                    if let Some(recording) = self.recording.as_mut() {
+                        recording.stopped = true;
+                    }
+                }
+            }
+
+            // Heard over whatever the track plays
+            if self.monitor {
+                for (out, sample) in output.iter_mut().zip(input) {
+                    *out += sample;
+                }
+            }
         }
     }
 }
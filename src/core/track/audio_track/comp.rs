@@ -0,0 +1,180 @@
+use std::f32::consts::FRAC_PI_2;
+
+use super::take::Take;
+
+pub const DEFAULT_CROSSFADE: u32 = 256;
+
+// Timeline range (in samples) taken from one take
+#[derive(Clone)]
+pub struct CompSegment {
+    pub take_id: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+// Composite playlist built from regions of different takes, kept sorted and
+// non-overlapping
+#[derive(Clone)]
+pub struct Comp {
+    pub segments: Vec<CompSegment>,
+    // Crossfade length in samples, centred on each segment boundary
+    pub crossfade: u32,
+}
+
+impl Default for Comp {
+    fn default() -> Self {
+        Comp {
+            segments: Vec::new(),
+            crossfade: DEFAULT_CROSSFADE,
+        }
+    }
+}
+
+impl Comp {
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    // Uses `take_id` for `start..end`, trimming or splitting whatever was there
+    pub fn select(&mut self, take_id: &str, start: u64, end: u64) {
+        if end <= start {
+            return;
+        }
+
+        let mut segments = Vec::with_capacity(self.segments.len() + 2);
+        for segment in self.segments.drain(..) {
+            if segment.end <= start || segment.start >= end {
+                segments.push(segment);
+                continue;
+            }
+            if segment.start < start {
+                segments.push(CompSegment {
+                    end: start,
+                    ..segment.clone()
+                });
+            }
+            if segment.end > end {
+                segments.push(CompSegment {
+                    start: end,
+                    ..segment
+                });
+            }
+        }
+
+        segments.push(CompSegment {
+            take_id: take_id.to_string(),
+            start,
+            end,
+        });
+        segments.sort_by_key(|s| s.start);
+
+        // Neighbouring regions of the same take need no crossfade between them
+        segments.dedup_by(|next, prev| {
+            if prev.take_id == next.take_id && prev.end == next.start {
+                prev.end = next.end;
+                true
+            } else {
+                false
+            }
+        });
+
+        self.segments = segments;
+    }
+
+    // Cuts the segment crossing `at` in two
+    pub fn split(&mut self, at: u64) {
+        let Some(index) = self.segment_at(at) else {
+            return;
+        };
+
+        let segment = &mut self.segments[index];
+        if segment.start < at {
+            let right = CompSegment {
+                start: at,
+                ..segment.clone()
+            };
+            segment.end = at;
+            self.segments.insert(index + 1, right);
+        }
+    }
+
+    // Drops segments that refer to a take which no longer exists
+    pub fn retain_takes(&mut self, takes: &[Take]) {
+        self.segments
+            .retain(|s| takes.iter().any(|t| t.id == s.take_id));
+    }
+
+    pub fn segment_at(&self, position: u64) -> Option<usize> {
+        let index = self.segments.partition_point(|s| s.start <= position);
+        if index == 0 {
+            return None;
+        }
+
+        let segment = &self.segments[index - 1];
+        (position < segment.end).then_some(index - 1)
+    }
+
+    // Renders the comp at a timeline position with equal-power crossfades at
+    // every boundary, fading to silence where the comp has gaps
+    pub fn sample_at(&self, takes: &[Take], position: u64) -> f32 {
+        let take_sample = |index: usize| {
+            let id = &self.segments[index].take_id;
+            takes
+                .iter()
+                .find(|t| &t.id == id)
+                .map(|t| t.sample_at(position))
+                .unwrap_or(0.0)
+        };
+
+        let half = (self.crossfade / 2) as u64;
+        let length = (self.crossfade.max(1)) as f32;
+
+        // Inside a segment, possibly near one of its edges
+        if let Some(index) = self.segment_at(position) {
+            let segment = &self.segments[index];
+            let current = take_sample(index);
+
+            if half > 0 && position < segment.start + half {
+                let t = (position + half - segment.start) as f32 / length;
+                let previous = match index.checked_sub(1) {
+                    Some(p) if self.segments[p].end == segment.start => take_sample(p),
+                    _ => 0.0,
+                };
+                return current * (t * FRAC_PI_2).sin() + previous * (t * FRAC_PI_2).cos();
+            }
+
+            if half > 0 && position + half >= segment.end {
+                let t = (position + half - segment.end) as f32 / length;
+                let next = match self.segments.get(index + 1) {
+                    Some(n) if n.start == segment.end => take_sample(index + 1),
+                    _ => 0.0,
+                };
+                return current * (t * FRAC_PI_2).cos() + next * (t * FRAC_PI_2).sin();
+            }
+
+            return current;
+        }
+
+        // In a gap, fade the neighbouring segments out and in
+        let next = self.segments.partition_point(|s| s.start <= position);
+        if let Some(previous) = next.checked_sub(1) {
+            let end = self.segments[previous].end;
+            if position < end + half {
+                let t = (position + half - end) as f32 / length;
+                return take_sample(previous) * (t * FRAC_PI_2).cos();
+            }
+        }
+        if let Some(segment) = self.segments.get(next) {
+            if position + half >= segment.start {
+                let t = (position + half - segment.start) as f32 / length;
+                return take_sample(next) * (t * FRAC_PI_2).sin();
+            }
+        }
+
+        0.0
+    }
+}
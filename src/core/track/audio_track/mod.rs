@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+pub mod audio_track;
+pub mod comp;
+pub mod take;
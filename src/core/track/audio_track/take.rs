@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use nanoid::nanoid;
+
+use crate::core::wav::wav::read_wav;
+
+// A recorded pass, placed on the timeline at `start` (in samples). Every take
+// sits in its own lane over the range it was recorded in.
+#[derive(Clone)]
+pub struct Take {
+    pub id: String,
+    pub path: PathBuf,
+    // Frames into the file where the take begins, set on the right half of a split
+    pub offset: u64,
+    pub start: u64,
+    pub length: u64,
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Take {
+    // Loads the take's audio from disk if it is not in memory yet. A length
+    // of 0 reads the file to its end.
+    pub fn load(&mut self) -> Result<()> {
+        if self.samples.is_empty() {
+            let (sample_rate, samples) = read_wav(&self.path)?;
+            let from = (self.offset as usize).min(samples.len());
+            let to = match self.length {
+                0 => samples.len(),
+                length => (from + length as usize).min(samples.len()),
+            };
+
+            self.sample_rate = sample_rate;
+            self.length = (to - from) as u64;
+            self.samples = samples[from..to].to_vec();
+        }
+
+        Ok(())
+    }
+
+    // Copy of the part of the take inside `start..end` (timeline samples)
+    // under a new id
+    pub fn slice(&self, start: u64, end: u64) -> Take {
+        let start = start.clamp(self.start, self.end());
+        let end = end.clamp(start, self.end());
+        let (from, to) = ((start - self.start) as usize, (end - self.start) as usize);
+
+        Take {
+            id: nanoid!(),
+            path: self.path.clone(),
+            offset: self.offset + (start - self.start),
+            start,
+            length: end - start,
+            sample_rate: self.sample_rate,
+            samples: self.samples.get(from..to).unwrap_or(&[]).to_vec(),
+        }
+    }
+
+    // Cuts the take at a timeline position, the left half keeps the id
+    pub fn split(&self, at: u64) -> (Take, Take) {
+        let left = Take {
+            id: self.id.clone(),
+            ..self.slice(self.start, at)
+        };
+        (left, self.slice(at, self.end()))
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+
+    pub fn covers(&self, position: u64) -> bool {
+        position >= self.start && position < self.end()
+    }
+
+    // Sample at a timeline position, silent outside the take
+    pub fn sample_at(&self, position: u64) -> f32 {
+        if !self.covers(position) {
+            return 0.0;
+        }
+
+        self.samples
+            .get((position - self.start) as usize)
+            .copied()
+            .unwrap_or(0.0)
+    }
+}
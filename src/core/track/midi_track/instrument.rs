@@ -0,0 +1,11 @@
+use crate::core::engine::node::Node;
+use crate::core::midi::message::MidiMessage;
+use crate::types::hertz::Hz;
+
+// A node that makes sound from MIDI. `MidiTrack` splits each block at event
+// boundaries, so messages arrive exactly between the samples they belong to.
+// Note messages come with their key's frequency in the track's tuning, which
+// pitched instruments play instead of working it out from the key.
+pub trait Instrument: Node {
+    fn handle(&mut self, message: &MidiMessage, frequency: Option<Hz>);
+}
@@ -0,0 +1,448 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use nanoid::nanoid;
+
+use super::instrument::Instrument;
+use crate::core::clip::midi_clip::{MidiClip, Tick};
+use crate::core::engine::node::{Node, ProcessContext};
+use crate::core::midi::message::MidiMessage;
+use crate::types::pitch::tuning::Tuning;
+use crate::types::rhythm::tempo::tempo::Tempo;
+
+// Messages scheduled per block before the scratch buffer has to grow
+const SCHEDULE_CAPACITY: usize = 1024;
+
+// Sends live MIDI to a track from any thread. The audio side only ever
+// `try_lock`s, so a busy sender delays messages by a block at worst.
+#[derive(Clone)]
+pub struct MidiThru {
+    queue: Arc<Mutex<VecDeque<MidiMessage>>>,
+}
+
+impl MidiThru {
+    pub fn send(&self, message: MidiMessage) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.push_back(message);
+        }
+    }
+}
+
+pub struct MidiTrack {
+    pub id: String,
+    pub name: String,
+    // Sorted by start and never overlapping
+    pub clips: Vec<MidiClip>,
+    // Clips launched from the session, on the same timeline
+    pub session_clips: Vec<MidiClip>,
+    // The session takes over from the arrangement at this sample
+    pub session_from: Option<u64>,
+    pub instrument: Option<Box<dyn Instrument>>,
+    // Pass live input through to the instrument
+    pub thru: bool,
+    tuning: Tuning,
+    thru_queue: Arc<Mutex<VecDeque<MidiMessage>>>,
+    // (frame offset, order, message) for the block being rendered
+    scheduled: Vec<(usize, u8, MidiMessage)>,
+    // Clip notes currently sounding, per channel and pitch
+    active: Box<[[u8; 128]; 16]>,
+    expected_position: Option<u64>,
+}
+
+impl Default for MidiTrack {
+    fn default() -> Self {
+        MidiTrack::new("MIDI Track")
+    }
+}
+
+impl MidiTrack {
+    pub fn new(name: &str) -> Self {
+        MidiTrack {
+            id: nanoid!(),
+            name: name.to_string(),
+            clips: Vec::new(),
+            session_clips: Vec::with_capacity(4),
+            session_from: None,
+            instrument: None,
+            thru: false,
+            tuning: Tuning::default(),
+            thru_queue: Arc::new(Mutex::new(VecDeque::with_capacity(256))),
+            scheduled: Vec::with_capacity(SCHEDULE_CAPACITY),
+            active: Box::new([[0; 128]; 16]),
+            expected_position: None,
+        }
+    }
+
+    // Copy with new ids. Instruments cannot be copied, so the copy has none.
+    pub fn duplicate(&self) -> Self {
+        let mut track = MidiTrack::new(&self.name);
+        track.thru = self.thru;
+        track.tuning = self.tuning.clone();
+        track.clips = self.clips.iter().map(|c| c.duplicate()).collect();
+        track
+    }
+
+    pub fn set_instrument(&mut self, instrument: Box<dyn Instrument>) {
+        self.instrument = Some(instrument);
+    }
+
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    // Notes reach the instrument with their frequency in this tuning
+    pub fn set_tuning(&mut self, tuning: &Tuning) {
+        self.tuning = tuning.clone();
+    }
+
+    pub fn thru_input(&self) -> MidiThru {
+        MidiThru {
+            queue: self.thru_queue.clone(),
+        }
+    }
+
+    pub fn insert_clip(
+        &mut self,
+        mut clip: MidiClip,
+        tempo: &Tempo,
+        sample_rate: u32,
+    ) -> Result<()> {
+        clip.layout(tempo, sample_rate);
+
+        if let Some(other) = self
+            .clips
+            .iter()
+            .find(|c| c.start_sample() < clip.end_sample() && clip.start_sample() < c.end_sample())
+        {
+            return Err(anyhow!("clip {} overlaps clip {}", clip.name, other.name));
+        }
+
+        let index = self
+            .clips
+            .partition_point(|c| c.start_sample() < clip.start_sample());
+        self.clips.insert(index, clip);
+        Ok(())
+    }
+
+    pub fn remove_clip(&mut self, id: &str) -> Option<MidiClip> {
+        let index = self.clips.iter().position(|c| c.id == id)?;
+        Some(self.clips.remove(index))
+    }
+
+    pub fn clip_mut(&mut self, id: &str) -> Option<&mut MidiClip> {
+        self.clips.iter_mut().find(|c| c.id == id)
+    }
+
+    // Re-resolves clip positions after a tempo or sample rate change
+    pub fn layout_clips(&mut self, tempo: &Tempo, sample_rate: u32) {
+        for clip in &mut self.clips {
+            clip.layout(tempo, sample_rate);
+        }
+        self.clips.sort_by_key(|c| c.start_sample());
+
+        for clip in &mut self.session_clips {
+            clip.layout(tempo, sample_rate);
+        }
+    }
+
+    // Lets the arrangement play again
+    pub fn back_to_arrangement(&mut self) {
+        self.session_clips.clear();
+        self.session_from = None;
+    }
+
+    // Queues note-offs for every clip note still sounding
+    fn release_all(&mut self, offset: usize) {
+        for (channel, pitches) in self.active.iter_mut().enumerate() {
+            for (pitch, count) in pitches.iter_mut().enumerate() {
+                for _ in 0..*count {
+                    self.scheduled.push((
+                        offset,
+                        0,
+                        MidiMessage::NoteOff {
+                            channel: channel as u8,
+                            pitch: pitch as u8,
+                            velocity: 64,
+                        },
+                    ));
+                }
+                *count = 0;
+            }
+        }
+    }
+
+    fn schedule_clips(&mut self, position: u64, frames: usize) {
+        let cutoff = self.session_from.unwrap_or(u64::MAX);
+        for clip in &self.clips {
+            MidiTrack::schedule_clip(&mut self.scheduled, clip, position, frames, cutoff);
+        }
+        for clip in &self.session_clips {
+            MidiTrack::schedule_clip(&mut self.scheduled, clip, position, frames, u64::MAX);
+        }
+    }
+
+    // Nothing starts at or after `cutoff`, notes still sounding there end there
+    fn schedule_clip(
+        scheduled: &mut Vec<(usize, u8, MidiMessage)>,
+        clip: &MidiClip,
+        position: u64,
+        frames: usize,
+        cutoff: u64,
+    ) {
+        let end = position + frames as u64;
+        let in_block = |sample: u64| {
+            (sample >= position && sample < end).then(|| (sample - position) as usize)
+        };
+
+        // A clip ending on the block start still owes its final note-offs
+        if clip.start_sample() >= end.min(cutoff) || clip.end_sample() < position {
+            return;
+        }
+
+        let cycle = clip.cycle_length().max(1);
+        let first_tick = clip.sample_to_tick(position.max(clip.start_sample()));
+        let last_tick = clip.sample_to_tick(end).min(clip.length);
+
+        // A note-off on a pass boundary belongs to the pass before it
+        for pass in (first_tick / cycle).saturating_sub(1)..=(last_tick / cycle) {
+            let pass_start: Tick = pass * cycle;
+            let pass_end = (pass_start + cycle).min(clip.length);
+            if pass_start >= pass_end {
+                break;
+            }
+
+            for note in clip.notes.iter().take_while(|n| n.start < cycle) {
+                if pass_start + note.start >= pass_end {
+                    continue;
+                }
+                // A groove never pushes a note out of its pass
+                let (on, velocity) = clip.grooved(pass_start + note.start, note.velocity);
+                let on = on.clamp(pass_start, pass_end - 1);
+                // Notes crossing a loop boundary end there
+                let off = (on + note.length).min(pass_end);
+
+                let on_sample = clip.tick_to_sample(on);
+                if on_sample >= cutoff {
+                    continue;
+                }
+
+                if let Some(offset) = in_block(on_sample) {
+                    scheduled.push((
+                        offset,
+                        1,
+                        MidiMessage::NoteOn {
+                            channel: note.channel,
+                            pitch: note.pitch,
+                            velocity,
+                        },
+                    ));
+                }
+                if let Some(offset) = in_block(clip.tick_to_sample(off).min(cutoff)) {
+                    scheduled.push((
+                        offset,
+                        0,
+                        MidiMessage::NoteOff {
+                            channel: note.channel,
+                            pitch: note.pitch,
+                            velocity: note.release_velocity,
+                        },
+                    ));
+                }
+            }
+
+            for event in clip.events.iter().take_while(|e| e.tick < cycle) {
+                let tick = pass_start + event.tick;
+                if tick >= pass_end {
+                    continue;
+                }
+                let sample = clip.tick_to_sample(tick);
+                if sample >= cutoff {
+                    continue;
+                }
+                if let Some(offset) = in_block(sample) {
+                    scheduled.push((offset, 1, event.message()));
+                }
+            }
+        }
+    }
+
+    fn track_active(&mut self, message: &MidiMessage) {
+        match *message {
+            MidiMessage::NoteOn { channel, pitch, .. } => {
+                let count = &mut self.active[(channel & 0x0F) as usize][(pitch & 0x7F) as usize];
+                *count = count.saturating_add(1);
+            }
+            MidiMessage::NoteOff { channel, pitch, .. } => {
+                let count = &mut self.active[(channel & 0x0F) as usize][(pitch & 0x7F) as usize];
+                *count = count.saturating_sub(1);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Node for MidiTrack {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prepare(&mut self, sample_rate: u32, block_size: usize) {
+        if let Some(instrument) = self.instrument.as_mut() {
+            instrument.prepare(sample_rate, block_size);
+        }
+    }
+
+    fn latency(&self) -> u32 {
+        self.instrument.as_ref().map(|i| i.latency()).unwrap_or(0)
+    }
+
+    fn process(&mut self, context: &ProcessContext, input: &[f32], output: &mut [f32]) {
+        self.scheduled.clear();
+
+        // Stopping, looping back or relocating must not leave notes hanging
+        let jumped = self.expected_position != Some(context.position);
+        if !context.playing || jumped {
+            self.release_all(0);
+        }
+
+        if context.playing {
+            self.schedule_clips(context.position, context.frames);
+            self.expected_position = Some(context.position + context.frames as u64);
+        } else {
+            self.expected_position = None;
+        }
+
+        // Only clip notes are tracked, live notes are the player's business
+        for i in 0..self.scheduled.len() {
+            let (_, _, message) = self.scheduled[i];
+            self.track_active(&message);
+        }
+
+        if let Ok(mut queue) = self.thru_queue.try_lock() {
+            while let Some(message) = queue.pop_front() {
+                if self.thru {
+                    self.scheduled.push((0, 1, message));
+                }
+            }
+        }
+
+        self.scheduled
+            .sort_unstable_by_key(|(offset, order, _)| (*offset, *order));
+
+        let Some(instrument) = self.instrument.as_mut() else {
+            return;
+        };
+
+        let mut rendered = 0;
+        let mut index = 0;
+        while rendered < context.frames {
+            let split = self
+                .scheduled
+                .get(index)
+                .map(|(offset, _, _)| (*offset).min(context.frames))
+                .unwrap_or(context.frames);
+
+            if split > rendered {
+                let slice = ProcessContext {
+                    frames: split - rendered,
+                    position: context.position + rendered as u64,
+                    ..*context
+                };
+                let input = input.get(rendered..split).unwrap_or(&[]);
+                instrument.process(&slice, input, &mut output[rendered..split]);
+                rendered = split;
+            }
+
+            while let Some((offset, _, message)) = self.scheduled.get(index) {
+                if *offset > rendered {
+                    break;
+                }
+                index += 1;
+
+                let frequency = match *message {
+                    MidiMessage::NoteOn { pitch, .. } | MidiMessage::NoteOff { pitch, .. } => {
+                        self.tuning.frequency(pitch)
+                    }
+                    _ => None,
+                };
+                // Keys the tuning leaves silent don't play
+                if matches!(message, MidiMessage::NoteOn { .. }) && frequency.is_none() {
+                    continue;
+                }
+                instrument.handle(message, frequency);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::hertz::Hz;
+    use crate::types::pitch::scala::{KeyboardMapping, Scale};
+
+    type Received = Arc<Mutex<Vec<(MidiMessage, Option<Hz>)>>>;
+
+    // Keeps what it was sent
+    struct Recorder(Received);
+
+    impl Node for Recorder {
+        fn name(&self) -> &str {
+            "Recorder"
+        }
+
+        fn process(&mut self, _: &ProcessContext, _: &[f32], _: &mut [f32]) {}
+    }
+
+    impl Instrument for Recorder {
+        fn handle(&mut self, message: &MidiMessage, frequency: Option<Hz>) {
+            self.0.lock().unwrap().push((*message, frequency));
+        }
+    }
+
+    fn note_on(pitch: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel: 0,
+            pitch,
+            velocity: 100,
+        }
+    }
+
+    #[test]
+    fn instruments_get_frequencies_in_the_track_tuning() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut track = MidiTrack::new("Keys");
+        track.thru = true;
+        track.set_instrument(Box::new(Recorder(received.clone())));
+
+        // Quarter tones, with every other key left silent
+        let mut mapping = KeyboardMapping::linear(69, 69, 440.0);
+        mapping.keys = vec![Some(0), None];
+        mapping.octave_degree = 1;
+        track.set_tuning(&Tuning::new(Scale::equal(24, 1200.0), mapping));
+
+        let thru = track.thru_input();
+        for pitch in [69, 70, 71] {
+            thru.send(note_on(pitch));
+        }
+        let context = ProcessContext {
+            sample_rate: 48000,
+            frames: 64,
+            position: 0,
+            playing: false,
+            input_latency: 0,
+        };
+        track.process(&context, &[], &mut [0.0; 64]);
+
+        let received = received.lock().unwrap();
+        let frequencies: Vec<(MidiMessage, f32)> = received
+            .iter()
+            .map(|(m, f)| (*m, (f.unwrap().hz * 100.0).round() / 100.0))
+            .collect();
+        assert_eq!(
+            frequencies,
+            vec![(note_on(69), 440.0), (note_on(71), 452.89)]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod instrument;
+#[allow(clippy::module_inception)]
+pub mod midi_track;
@@ -0,0 +1,4 @@
+pub mod audio_track;
+pub mod midi_track;
+#[allow(clippy::module_inception)]
+pub mod track;
@@ -1,7 +1,267 @@
-use crate::core::track::audio_track::AudioTrack;
+use nanoid::nanoid;
+
+use crate::core::automation::automation::AutomationLane;
+use crate::core::engine::node::{Node, ProcessContext};
+use crate::core::mixer::channel::Channel;
+use crate::core::track::audio_track::audio_track::AudioTrack;
+use crate::core::track::midi_track::midi_track::MidiTrack;
+
+pub const DEFAULT_COLOR: [u8; 3] = [0x8A, 0x8A, 0x8A];
+
+pub enum TrackKind {
+    Audio(AudioTrack),
+    Midi(MidiTrack),
+    // Bus or return for the tracks and sends routed into it
+    Bus,
+    // Groups other tracks through their `parent`, makes no sound itself
+    Folder,
+}
+
+// Post-fader send into a bus track
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackSend {
+    pub bus: String,
+    pub level: f32,
+}
+
+pub struct Track {
+    pub id: String,
+    pub name: String,
+    pub color: [u8; 3],
+    pub mute: bool,
+    pub solo: bool,
+    pub arm: bool,
+    // Id of the folder track this one sits in
+    pub parent: Option<String>,
+    // Id of the bus track this one plays into instead of the master
+    pub output: Option<String>,
+    pub sends: Vec<TrackSend>,
+    pub channel: Channel,
+    pub kind: TrackKind,
+    pub automation: Vec<AutomationLane>,
+    // Muted by a folder or by another track's solo, kept up to date by the studio
+    pub(crate) silenced: bool,
+}
 
 impl Track {
-    pub fn new_audio_track() -> Self {
-        Track {}
+    fn with_kind(name: &str, kind: TrackKind, sample_rate: u32) -> Self {
+        Track {
+            id: nanoid!(),
+            name: name.to_string(),
+            color: DEFAULT_COLOR,
+            mute: false,
+            solo: false,
+            arm: false,
+            parent: None,
+            output: None,
+            sends: Vec::new(),
+            channel: Channel::new(name, sample_rate),
+            kind,
+            automation: Vec::new(),
+            silenced: false,
+        }
+    }
+
+    pub fn new_audio_track(name: &str, sample_rate: u32) -> Self {
+        let mut track = Track::with_kind(
+            name,
+            TrackKind::Audio(AudioTrack::new(name, None)),
+            sample_rate,
+        );
+        track.sync_inner();
+        track
+    }
+
+    pub fn new_midi_track(name: &str, sample_rate: u32) -> Self {
+        let mut track = Track::with_kind(name, TrackKind::Midi(MidiTrack::new(name)), sample_rate);
+        track.sync_inner();
+        track
+    }
+
+    pub fn new_bus_track(name: &str, sample_rate: u32) -> Self {
+        Track::with_kind(name, TrackKind::Bus, sample_rate)
+    }
+
+    pub fn new_folder_track(name: &str, sample_rate: u32) -> Self {
+        Track::with_kind(name, TrackKind::Folder, sample_rate)
+    }
+
+    // Keeps the inner track's id and name in line with this one
+    fn sync_inner(&mut self) {
+        match &mut self.kind {
+            TrackKind::Audio(audio) => {
+                audio.id = self.id.clone();
+                audio.name = self.name.clone();
+            }
+            TrackKind::Midi(midi) => {
+                midi.id = self.id.clone();
+                midi.name = self.name.clone();
+            }
+            TrackKind::Bus | TrackKind::Folder => {}
+        }
+    }
+
+    pub fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+        self.channel.name = name.to_string();
+        self.sync_inner();
+    }
+
+    pub fn is_bus(&self) -> bool {
+        matches!(self.kind, TrackKind::Bus)
+    }
+
+    pub fn is_folder(&self) -> bool {
+        matches!(self.kind, TrackKind::Folder)
+    }
+
+    pub fn audio(&self) -> Option<&AudioTrack> {
+        match &self.kind {
+            TrackKind::Audio(audio) => Some(audio),
+            _ => None,
+        }
+    }
+
+    pub fn audio_mut(&mut self) -> Option<&mut AudioTrack> {
+        match &mut self.kind {
+            TrackKind::Audio(audio) => Some(audio),
+            _ => None,
+        }
+    }
+
+    pub fn midi(&self) -> Option<&MidiTrack> {
+        match &self.kind {
+            TrackKind::Midi(midi) => Some(midi),
+            _ => None,
+        }
+    }
+
+    pub fn midi_mut(&mut self) -> Option<&mut MidiTrack> {
+        match &mut self.kind {
+            TrackKind::Midi(midi) => Some(midi),
+            _ => None,
+        }
+    }
+
+    // Arming an audio track records it, a MIDI track passes live input through
+    pub fn set_arm(&mut self, arm: bool, sample_rate: u32) {
+        self.arm = arm;
+
+        match &mut self.kind {
+            TrackKind::Audio(audio) if arm => audio.arm(sample_rate),
+            TrackKind::Audio(audio) => audio.disarm(),
+            TrackKind::Midi(midi) => midi.thru = arm,
+            TrackKind::Bus | TrackKind::Folder => {}
+        }
+    }
+
+    // Returns the new lane's id
+    pub fn add_automation(&mut self, lane: AutomationLane) -> String {
+        let id = lane.id.clone();
+        self.automation.push(lane);
+        id
+    }
+
+    pub fn automation_lane(&self, id: &str) -> Option<&AutomationLane> {
+        self.automation.iter().find(|l| l.id == id)
+    }
+
+    pub fn automation_lane_mut(&mut self, id: &str) -> Option<&mut AutomationLane> {
+        self.automation.iter_mut().find(|l| l.id == id)
+    }
+
+    pub fn remove_automation(&mut self, id: &str) -> Option<AutomationLane> {
+        let index = self.automation.iter().position(|l| l.id == id)?;
+        Some(self.automation.remove(index))
+    }
+
+    pub fn is_audible(&self) -> bool {
+        !self.mute && !self.silenced
+    }
+
+    // Copy with new ids and the same settings, named "<name> Copy"
+    pub fn duplicate(&self, sample_rate: u32) -> Self {
+        let name = format!("{} Copy", self.name);
+        let kind = match &self.kind {
+            TrackKind::Audio(audio) => TrackKind::Audio(audio.duplicate()),
+            TrackKind::Midi(midi) => TrackKind::Midi(midi.duplicate()),
+            TrackKind::Bus => TrackKind::Bus,
+            TrackKind::Folder => TrackKind::Folder,
+        };
+
+        let channel = self.channel.duplicate(&name, sample_rate);
+        // Lanes on this track's own channel move over to the copy's channel
+        let automation = self
+            .automation
+            .iter()
+            .map(|lane| lane.duplicate(&lane.param.replace(&self.channel.id, &channel.id)))
+            .collect();
+
+        let mut track = Track {
+            id: nanoid!(),
+            name: name.clone(),
+            color: self.color,
+            mute: self.mute,
+            solo: self.solo,
+            arm: false,
+            parent: self.parent.clone(),
+            output: self.output.clone(),
+            sends: self.sends.clone(),
+            channel,
+            kind,
+            automation,
+            silenced: self.silenced,
+        };
+        track.sync_inner();
+        track
+    }
+}
+
+impl Node for Track {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn prepare(&mut self, sample_rate: u32, block_size: usize) {
+        self.channel.set_sample_rate(sample_rate);
+
+        match &mut self.kind {
+            TrackKind::Audio(audio) => audio.prepare(sample_rate, block_size),
+            TrackKind::Midi(midi) => midi.prepare(sample_rate, block_size),
+            TrackKind::Bus | TrackKind::Folder => {}
+        }
+    }
+
+    fn latency(&self) -> u32 {
+        match &self.kind {
+            TrackKind::Audio(audio) => audio.latency(),
+            TrackKind::Midi(midi) => midi.latency(),
+            TrackKind::Bus | TrackKind::Folder => 0,
+        }
+    }
+
+    fn process(&mut self, context: &ProcessContext, input: &[f32], output: &mut [f32]) {
+        match &mut self.kind {
+            TrackKind::Audio(audio) => audio.process(context, input, output),
+            TrackKind::Midi(midi) => midi.process(context, input, output),
+            // The engine hands a bus what its tracks and sends play into it
+            TrackKind::Bus => {
+                for (out, sample) in output.iter_mut().zip(input) {
+                    *out = *sample;
+                }
+            }
+            TrackKind::Folder => {}
+        }
+
+        // Levels keep smoothing while muted so unmuting does not jump
+        let audible = self.is_audible();
+        for sample in output.iter_mut() {
+            let level = self.channel.next_level();
+            *sample = if audible { *sample * level } else { 0.0 };
+        }
     }
 }
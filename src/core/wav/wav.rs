@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+// Writes mono f32 samples as a 16-bit PCM WAV file
+pub fn write_wav<P: AsRef<Path>>(path: P, sample_rate: u32, samples: &[f32]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    let channels: u16 = 1;
+    let bits_per_sample: u16 = 16;
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = sample_rate * block_align as u32;
+    let data_len = (samples.len() * block_align as usize) as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits_per_sample.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+// Reads a PCM (16/24/32-bit) or 32-bit float WAV file, mixed down to mono.
+// Returns the sample rate along with the samples.
+pub fn read_wav<P: AsRef<Path>>(path: P) -> Result<(u32, Vec<f32>)> {
+    let bytes = std::fs::read(path)?;
+
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(anyhow!("not a WAV file"));
+    }
+
+    // (is float, channels, sample rate, bits per sample)
+    let mut format: Option<(bool, u16, u32, u16)> = None;
+    let mut offset = 12;
+
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into()?) as usize;
+        let body = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+
+        if id == b"fmt " && body.len() >= 16 {
+            let mut tag = u16::from_le_bytes([body[0], body[1]]);
+            if tag == 0xFFFE && body.len() >= 26 {
+                // WAVE_FORMAT_EXTENSIBLE keeps the real format in the sub-format GUID
+                tag = u16::from_le_bytes([body[24], body[25]]);
+            }
+            let channels = u16::from_le_bytes([body[2], body[3]]);
+            let sample_rate = u32::from_le_bytes(body[4..8].try_into()?);
+            let bits = u16::from_le_bytes([body[14], body[15]]);
+            format = Some((tag == 3, channels, sample_rate, bits));
+        } else if id == b"data" {
+            let (float, channels, sample_rate, bits) =
+                format.ok_or_else(|| anyhow!("WAV data chunk before fmt chunk"))?;
+            let samples = decode(body, float, channels.max(1), bits)?;
+            return Ok((sample_rate, samples));
+        }
+
+        // Chunks are padded to an even length
+        offset += 8 + size + (size & 1);
+    }
+
+    Err(anyhow!("WAV file has no data chunk"))
+}
+
+fn decode(data: &[u8], float: bool, channels: u16, bits: u16) -> Result<Vec<f32>> {
+    let width = (bits / 8) as usize;
+    let frame = width * channels as usize;
+    if frame == 0 {
+        return Err(anyhow!("invalid WAV format"));
+    }
+
+    let sample = |b: &[u8]| -> Result<f32> {
+        Ok(match (float, bits) {
+            (true, 32) => f32::from_le_bytes(b.try_into()?),
+            (false, 16) => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (false, 24) => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+            (false, 32) => i32::from_le_bytes(b.try_into()?) as f32 / 2_147_483_648.0,
+            _ => return Err(anyhow!("unsupported WAV sample format ({} bits)", bits)),
+        })
+    };
+
+    data.chunks_exact(frame)
+        .map(|f| {
+            let mut sum = 0.0;
+            for channel in f.chunks_exact(width) {
+                sum += sample(channel)?;
+            }
+            Ok(sum / channels as f32)
+        })
+        .collect()
+}
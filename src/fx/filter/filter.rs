@@ -1,102 +1,117 @@
+use std::f32::consts::PI;
+
+use crate::types::hertz::Hz;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterMode {
+    LowPass,
+    HighPass,
+}
+
+// One-pole low or high pass with its cutoff in Hz
+#[derive(Clone)]
+pub struct Filter {
+    pub mode: FilterMode,
+    pub sample_rate: u32,
+    cutoff: Hz,
+    previous_input: f32,
+    previous_output: f32,
+}
+
 impl Filter {
-    pub fn new() -> Self {
-        Filter { filter_value: 0.0 }
+    pub fn new<F: Into<Hz>>(mode: FilterMode, cutoff: F, sample_rate: u32) -> anyhow::Result<Self> {
+        Ok(Filter {
+            mode,
+            sample_rate,
+            cutoff: cutoff.into().validate(sample_rate)?,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        })
     }
 
-    // Implement the basic filter application logic
-    pub fn set(&self, samples: &mut [i16]) {
-        let mut filtered_samples: Vec<i16> = vec![0; self.samples.len()];
-        let mut previous_sample: f32 = 0.0;
-
-        let high_pass_coefficient = self.filter_value.max(0.0); // 0 to 1
-        let low_pass_coefficient = (-self.filter_value).max(0.0); // 0 to 1
+    pub fn cutoff(&self) -> Hz {
+        self.cutoff
+    }
 
-        for (i, &sample) in self.samples.iter().enumerate() {
-            let current_sample = sample as f32;
+    // Cutoffs at or past Nyquist are rejected, the old one stays
+    pub fn set_cutoff<F: Into<Hz>>(&mut self, cutoff: F) -> anyhow::Result<()> {
+        self.cutoff = cutoff.into().validate(self.sample_rate)?;
+        Ok(())
+    }
 
-            // Simple one-pole low-pass filter
-            let lpf = (previous_sample * low_pass_coefficient)
-                + (current_sample * (1.0 - low_pass_coefficient));
+    pub fn reset(&mut self) {
+        self.previous_input = 0.0;
+        self.previous_output = 0.0;
+    }
 
-            // Simple one-pole high-pass filter
-            let hpf = (current_sample - previous_sample) * high_pass_coefficient
-                + (previous_sample * (1.0 - high_pass_coefficient));
+    // Feedback coefficient of the pole for `cutoff`
+    fn coefficient(&self, cutoff: Hz) -> f32 {
+        let cutoff = cutoff.clamp_to_nyquist(self.sample_rate);
+        (-2.0 * PI * cutoff.hz / self.sample_rate as f32).exp()
+    }
 
-            // Choose which filter to apply based on the filter_value
-            let filtered_sample = if self.filter_value > 0.0 {
-                hpf
-            } else if self.filter_value < 0.0 {
-                lpf
-            } else {
-                current_sample
-            };
+    fn process_sample(&mut self, input: f32, coefficient: f32) -> f32 {
+        let output = match self.mode {
+            FilterMode::LowPass => input * (1.0 - coefficient) + self.previous_output * coefficient,
+            FilterMode::HighPass => {
+                coefficient * (self.previous_output + input - self.previous_input)
+            }
+        };
+
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
 
-            filtered_samples[i] = filtered_sample as i16;
-            previous_sample = filtered_sample; // Store the filtered sample for the next iteration
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let coefficient = self.coefficient(self.cutoff);
+        for sample in samples.iter_mut() {
+            *sample = self.process_sample(*sample, coefficient);
         }
+    }
 
-        self.samples = filtered_samples; // Update the samples with the filtered output
+    pub fn apply(&mut self, samples: &mut [i16]) {
+        let coefficient = self.coefficient(self.cutoff);
+        for sample in samples.iter_mut() {
+            *sample = self.process_sample(*sample as f32, coefficient) as i16;
+        }
     }
 
-    // Implement the modulation logic
-    pub fn modulate<F>(&self, samples: &mut [i16], modulation_fn: F)
+    // Moves the cutoff by `modulation_fn(t)` octaves, `t` running from 0 to 1
+    // over the samples
+    pub fn modulate<F>(&mut self, samples: &mut [i16], modulation_fn: F)
     where
         F: Fn(f32) -> f32,
     {
-        let base_filter_value = self.filter_value; // Home base for filter modulation
-        let mut filtered_samples: Vec<i16> = vec![0; self.samples.len()];
-        let mut previous_sample: f32 = 0.0;
-
-        for (i, &sample) in self.samples.iter().enumerate() {
-            let t = i as f32 / self.samples.len() as f32;
-            let modulation_value = modulation_fn(t);
-            let modulated_filter_value = base_filter_value + modulation_value;
-
-            let high_pass_coefficient = modulated_filter_value.max(0.0); // 0 to 1
-            let low_pass_coefficient = (-modulated_filter_value).max(0.0); // 0 to 1
-
-            let current_sample = sample as f32;
-
-            // Simple one-pole low-pass filter
-            let lpf = (previous_sample * low_pass_coefficient)
-                + (current_sample * (1.0 - low_pass_coefficient));
-
-            // Simple one-pole high-pass filter
-            let hpf = (current_sample - previous_sample) * high_pass_coefficient
-                + (previous_sample * (1.0 - high_pass_coefficient));
-
-            // Choose which filter to apply based on the modulated filter value
-            let filtered_sample = if modulated_filter_value > 0.0 {
-                hpf
-            } else if modulated_filter_value < 0.0 {
-                lpf
-            } else {
-                current_sample
-            };
-
-            filtered_samples[i] = filtered_sample as i16;
-            previous_sample = filtered_sample; // Store the filtered sample for the next iteration
-        }
+        let length = samples.len() as f32;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let t = i as f32 / length;
+            let cutoff = self.cutoff * 2f32.powf(modulation_fn(t));
 
-        self.samples = filtered_samples; // Update the samples with the modulated filtered output
+            let coefficient = self.coefficient(cutoff);
+            *sample = self.process_sample(*sample as f32, coefficient) as i16;
+        }
     }
 }
 
 pub trait Filterable {
     fn get_filter(&mut self) -> &mut Filter;
 
+    // This method must be implemented by the struct to return its sample buffer
+    fn get_samples(&mut self) -> &mut [i16];
+
     fn apply_filter(&mut self) {
-        self.get_filter().apply(&mut self.get_samples());
+        let mut filter = self.get_filter().clone();
+        filter.apply(self.get_samples());
+        *self.get_filter() = filter;
     }
 
     fn modulate_filter<F>(&mut self, modulation_fn: F)
     where
         F: Fn(f32) -> f32,
     {
-        self.get_filter()
-            .modulate(&mut self.get_samples(), modulation_fn);
+        let mut filter = self.get_filter().clone();
+        filter.modulate(self.get_samples(), modulation_fn);
+        *self.get_filter() = filter;
     }
-
-    // This method must be implemented by the struct to return its sample buffer
-    fn get_samples(&mut self) -> &mut [i16];
 }
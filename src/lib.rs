@@ -1,4 +1,12 @@
 // lib.rs is the root of the crate's module tree.
+pub mod backend;
 pub mod core;
+pub mod fx;
 pub mod studio;
 pub mod types;
+
+// Lets the engine's real-time guard see allocations, always on for tests
+#[cfg(any(test, feature = "rt-guard"))]
+#[global_allocator]
+static ALLOCATOR: crate::core::engine::guard::GuardedAllocator =
+    crate::core::engine::guard::GuardedAllocator::new();
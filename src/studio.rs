@@ -1,84 +1,170 @@
 // noyz.rs
-use cpal::{
-    traits::{DeviceTrait, HostTrait},
-    Device, Host,
-};
+use cpal::{traits::HostTrait, Device, Host};
 use std::time;
 
+use super::backend::cpal::device::{self, DeviceInfo, Direction, HostInfo, Selector};
+use super::backend::cpal::stream::CpalStreams;
+use super::backend::jack::jack::JackBackend;
+use super::backend::null::null::NullBackend;
+use super::core::arrangement::{arrangement::Arrangement, edit};
+use super::core::automation::automation::{AutomationLane, AutomationMode};
+use super::core::clip::midi_clip::MidiClip;
+use super::core::engine::node::{Node, ProcessContext};
+use super::core::engine::{
+    engine::Engine, renderer::Renderer, routing::Route, transport::Transport,
+};
+use super::core::metronome::metronome::Metronome;
+use super::core::midi::smf::Smf;
+use super::core::param::smoothed::{SmoothedParam, DEFAULT_RAMP};
+use super::core::param::{param::Param, params::Params};
+use super::core::session::session::{Launch, Session};
+use super::core::session::slot::{self, LaunchedClip, SlotClip};
+use super::core::track::track::{Track, TrackKind, TrackSend};
+use super::types::pitch::tuning::Tuning;
+use super::types::rhythm::position::position::DEFAULT_PPQ;
+use super::types::rhythm::tempo::bpm::bar::Bar;
+use super::types::rhythm::tempo::bpm::beat::Beat;
 use super::types::rhythm::tempo::tempo::Tempo;
+use super::types::theory::chord_track::{ChordTrack, SnapTarget};
+use super::types::theory::scale::Key;
+
+// Longest stretch rendered with a single automation ramp
+const AUTOMATION_INTERVAL: usize = 64;
 
-struct Loop {
-    start: [u32; 2],
-    end: [u32; 2],
-    active: bool,
+pub enum Backend {
+    Cpal,
+    Jack(JackBackend),
+    Null(NullBackend),
 }
 
 pub struct Audio {
     pub host: Host,
-    pub input: Device,
-    pub output: Device,
+    pub input: Option<Device>,
+    pub output: Option<Device>,
+    pub backend: Backend,
 }
 
 impl Default for Audio {
     fn default() -> Self {
-        let host = cpal::default_host();
-        let input = host.default_input_device().unwrap();
-        let output = host.default_output_device().unwrap();
-
-        Audio {
-            host,
-            input,
-            output,
-        }
+        Audio::new(None, None)
     }
 }
 
 impl Audio {
+    // Falls back to the host's default devices, and to the null backend when
+    // there is no output device at all (CI machines, render servers)
     pub fn new<I: Into<Option<Device>>, O: Into<Option<Device>>>(input: I, output: O) -> Self {
         let host = cpal::default_host();
-        let input = match input.into() {
-            Some(input) => input,
-            None => host.default_input_device().unwrap(),
-        };
-        let output = match output.into() {
-            Some(output) => output,
-            None => host.default_output_device().unwrap(),
+        let input = input.into().or_else(|| host.default_input_device());
+        let output = output.into().or_else(|| host.default_output_device());
+
+        let backend = match output {
+            Some(_) => Backend::Cpal,
+            None => Backend::Null(NullBackend::default()),
         };
 
         Audio {
             host,
             input,
             output,
+            backend,
         }
     }
 
-    pub fn list_drivers(&self) {
-        let hosts = cpal::available_hosts();
+    // Headless audio that never touches a device
+    pub fn null(sample_rate: u32, block_size: usize) -> Self {
+        Audio {
+            host: cpal::default_host(),
+            input: None,
+            output: None,
+            backend: Backend::Null(NullBackend::new(sample_rate, block_size)),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.backend, Backend::Null(_))
+    }
+
+    pub fn null_backend(&mut self) -> Option<&mut NullBackend> {
+        match &mut self.backend {
+            Backend::Null(null) => Some(null),
+            _ => None,
+        }
+    }
+
+    // Uses a JACK client named `name` instead of cpal for the audio callback
+    pub fn with_jack(mut self, name: &str) -> anyhow::Result<Self> {
+        self.backend = Backend::Jack(JackBackend::new(name)?);
+        Ok(self)
+    }
+
+    pub fn jack(&mut self) -> Option<&mut JackBackend> {
+        match &mut self.backend {
+            Backend::Jack(jack) => Some(jack),
+            _ => None,
+        }
+    }
+
+    pub fn drivers(&self) -> Vec<HostInfo> {
+        device::hosts()
+    }
+
+    pub fn input_devices(&self) -> anyhow::Result<Vec<DeviceInfo>> {
+        device::devices(&self.host, Direction::Input)
+    }
+
+    pub fn output_devices(&self) -> anyhow::Result<Vec<DeviceInfo>> {
+        device::devices(&self.host, Direction::Output)
+    }
+
+    // Switches driver and picks that driver's default devices
+    pub fn select_driver(&mut self, selector: Selector) -> anyhow::Result<()> {
+        self.host = device::host(selector)?;
+        self.input = self.host.default_input_device();
+        self.output = self.host.default_output_device();
+
+        match (&self.output, &self.backend) {
+            (None, Backend::Cpal) => self.backend = Backend::Null(NullBackend::default()),
+            (Some(_), Backend::Null(_)) => self.backend = Backend::Cpal,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn select_input(&mut self, selector: Selector) -> anyhow::Result<()> {
+        self.input = Some(device::select(&self.host, Direction::Input, selector)?);
+        Ok(())
+    }
+
+    pub fn select_output(&mut self, selector: Selector) -> anyhow::Result<()> {
+        self.output = Some(device::select(&self.host, Direction::Output, selector)?);
+
+        if self.is_null() {
+            self.backend = Backend::Cpal;
+        }
 
+        Ok(())
+    }
+
+    pub fn list_drivers(&self) {
         println!("Available Drivers:");
-        for host_id in hosts {
-            // The `name` method is called on `HostId`, not on `Host`
-            println!("\t{}", host_id.name());
+        for host in self.drivers() {
+            println!("\t{}", host.name);
         }
     }
 
     pub fn list_input_devices(&self) {
-        let inputs = self.host.input_devices().unwrap();
-
         println!("Input Devices: ");
-        for input in inputs {
-            // print input devices
-            println!("\t{}", input.name().unwrap());
+        for input in self.input_devices().unwrap_or_default() {
+            println!("\t{}", input.name);
         }
     }
 
     pub fn list_output_devices(&self) {
-        let outputs = self.host.output_devices().unwrap();
-
         println!("Output Devices: ");
-        for output in outputs {
-            // print output devices
-            println!("\t{}", output.name().unwrap());
+        for output in self.output_devices().unwrap_or_default() {
+            println!("\t{}", output.name);
         }
     }
 
@@ -88,51 +174,68 @@ impl Audio {
     }
 }
 
+#[derive(Default)]
 pub struct Config {
     pub tempo: Tempo,
     pub audio: Audio,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            tempo: Tempo::default(),
-            audio: Audio::default(),
-        }
-    }
-}
-
 pub struct Studio {
     pub tempo: Tempo,
     pub audio: Audio,
+    pub engine: Engine,
+    pub tracks: Vec<Track>,
+    pub arrangement: Arrangement,
+    pub chord_track: ChordTrack,
+    pub session: Session,
+    pub metronome: Metronome,
     pub start_time: time::Instant,
     pub current_time: time::Duration,
-    pub volume: f32,
+    pub volume: Param,
+    pub params: Params,
+    master: SmoothedParam,
+    // Shared by every MIDI track
+    tuning: Tuning,
+    // Automation played the previous block, so lanes can ramp from where they are
+    automating: bool,
 }
 
 impl Default for Studio {
     fn default() -> Self {
-        Studio {
-            tempo: Tempo::default(),
-            audio: Audio::default(),
-            start_time: time::Instant::now(),
-            current_time: time::Duration::new(0, 0),
-            volume: 1.0,
-        }
+        Studio::new(None)
     }
 }
 
 impl Studio {
     pub fn new<C: Into<Option<Config>>>(config: C) -> Studio {
-        match config.into() {
-            Some(config) => Studio {
-                tempo: config.tempo,
-                audio: config.audio,
-                start_time: time::Instant::now(),
-                current_time: time::Duration::new(0, 0),
-                volume: 1.0,
-            },
-            None => Studio::default(),
+        let config = config.into().unwrap_or_default();
+        let mut params = Params::new();
+        let volume = params.register(Param::new("studio.volume", "Volume", 0.0, 1.0, 1.0));
+
+        let engine = match &config.audio.backend {
+            Backend::Jack(jack) => Engine::new(jack.sample_rate(), jack.buffer_size()),
+            Backend::Null(null) => Engine::new(null.sample_rate, null.block_size),
+            Backend::Cpal => Engine::default(),
+        };
+        let master = volume.smoothed(engine.sample_rate, DEFAULT_RAMP);
+        let metronome = Metronome::new(&config.tempo, engine.sample_rate);
+
+        Studio {
+            tempo: config.tempo,
+            audio: config.audio,
+            engine,
+            tracks: Vec::new(),
+            arrangement: Arrangement::new(),
+            chord_track: ChordTrack::default(),
+            session: Session::new(),
+            metronome,
+            start_time: time::Instant::now(),
+            current_time: time::Duration::new(0, 0),
+            volume,
+            params,
+            master,
+            tuning: Tuning::default(),
+            automating: false,
         }
     }
 
@@ -141,8 +244,1148 @@ impl Studio {
         Studio::new(None)
     }
 
-    pub fn set_volume(&mut self, volume: f32) {
-        self.volume = volume;
+    // Lock-free, so this can be called while the audio callback is running
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+    }
+
+    // Sets any registered parameter by id, returns false if it does not exist
+    pub fn set_param(&self, id: &str, value: f32) -> bool {
+        self.params.set(id, value)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.engine.sample_rate
+    }
+
+    pub fn new_audio_track(&self, name: &str) -> Track {
+        Track::new_audio_track(name, self.sample_rate())
+    }
+
+    pub fn new_midi_track(&self, name: &str) -> Track {
+        Track::new_midi_track(name, self.sample_rate())
+    }
+
+    pub fn new_bus_track(&self, name: &str) -> Track {
+        Track::new_bus_track(name, self.sample_rate())
+    }
+
+    pub fn new_folder_track(&self, name: &str) -> Track {
+        Track::new_folder_track(name, self.sample_rate())
+    }
+
+    pub fn track(&self, id: &str) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.id == id)
+    }
+
+    pub fn track_mut(&mut self, id: &str) -> Option<&mut Track> {
+        self.tracks.iter_mut().find(|t| t.id == id)
+    }
+
+    pub fn track_index(&self, id: &str) -> Option<usize> {
+        self.tracks.iter().position(|t| t.id == id)
+    }
+
+    // Appends a track and returns its id
+    pub fn add_track(&mut self, track: Track) -> String {
+        self.insert_track(self.tracks.len(), track)
+    }
+
+    pub fn insert_track(&mut self, index: usize, mut track: Track) -> String {
+        let id = track.id.clone();
+
+        track.prepare(self.engine.sample_rate, self.engine.block_size);
+        if let Some(midi) = track.midi_mut() {
+            midi.set_tuning(&self.tuning);
+        }
+        for param in track.channel.params() {
+            self.params.register(param);
+        }
+        for lane in &mut track.automation {
+            lane.bind(self.params.get(&lane.param).cloned());
+            lane.layout(&self.tempo, self.engine.sample_rate);
+        }
+
+        // A track without ports still plays through the master
+        if let Backend::Jack(jack) = &mut self.audio.backend {
+            let _ = jack.register_strip(&id, &track.name);
+        }
+
+        self.tracks.insert(index.min(self.tracks.len()), track);
+        self.prepare_graph();
+        self.update_mix_state();
+        id
+    }
+
+    // Sizes latency compensation for the tracks and routes them, call after
+    // giving one something with latency such as an instrument. Tracks whose
+    // routing loops back on itself play straight to the master instead.
+    pub fn prepare_graph(&mut self) {
+        self.engine.prepare_graph(&self.tracks);
+        if self.engine.set_routes(self.routes()).is_err() {
+            let routes = self
+                .tracks
+                .iter()
+                .map(|t| Route {
+                    summing: t.is_bus(),
+                    ..Route::default()
+                })
+                .collect();
+            let _ = self.engine.set_routes(routes);
+        }
+    }
+
+    // The engine's view of the tracks' outputs and sends, anything not
+    // pointing at a bus track plays to the master
+    fn routes(&self) -> Vec<Route> {
+        let bus = |id: &str| self.tracks.iter().position(|t| t.id == id && t.is_bus());
+
+        self.tracks
+            .iter()
+            .map(|track| Route {
+                output: track.output.as_deref().and_then(bus),
+                sends: track
+                    .sends
+                    .iter()
+                    .filter_map(|s| Some((bus(&s.bus)?, s.level)))
+                    .collect(),
+                summing: track.is_bus(),
+            })
+            .collect()
+    }
+
+    // Plays the track into a bus track, or the master with `None`
+    pub fn set_output(&mut self, id: &str, bus: Option<&str>) -> anyhow::Result<()> {
+        if let Some(bus) = bus {
+            self.check_bus(id, bus)?;
+        }
+        let track = self
+            .track_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("no track with id {}", id))?;
+
+        let previous = std::mem::replace(&mut track.output, bus.map(|b| b.to_string()));
+        self.reroute(id, |track| track.output = previous)
+    }
+
+    // Sends the track into a bus track at `level`, replacing any send it
+    // already has there
+    pub fn set_send(&mut self, id: &str, bus: &str, level: f32) -> anyhow::Result<()> {
+        self.check_bus(id, bus)?;
+        let track = self
+            .track_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("no track with id {}", id))?;
+
+        let previous = track.sends.clone();
+        match track.sends.iter_mut().find(|s| s.bus == bus) {
+            Some(send) => send.level = level,
+            None => track.sends.push(TrackSend {
+                bus: bus.to_string(),
+                level,
+            }),
+        }
+        self.reroute(id, |track| track.sends = previous)
+    }
+
+    pub fn remove_send(&mut self, id: &str, bus: &str) -> Option<TrackSend> {
+        let track = self.track_mut(id)?;
+        let index = track.sends.iter().position(|s| s.bus == bus)?;
+        let send = track.sends.remove(index);
+        self.prepare_graph();
+        Some(send)
+    }
+
+    fn check_bus(&self, id: &str, bus: &str) -> anyhow::Result<()> {
+        match self.track(bus) {
+            Some(track) if track.id == id => {
+                Err(anyhow::anyhow!("a track cannot play into itself"))
+            }
+            Some(track) if track.is_bus() => Ok(()),
+            Some(_) => Err(anyhow::anyhow!("track {} is not a bus", bus)),
+            None => Err(anyhow::anyhow!("no track with id {}", bus)),
+        }
+    }
+
+    // Hands the engine the tracks' routes, undoing the change to track `id`
+    // when it makes routing loop back on itself
+    fn reroute(&mut self, id: &str, undo: impl FnOnce(&mut Track)) -> anyhow::Result<()> {
+        if let Err(e) = self.engine.set_routes(self.routes()) {
+            if let Some(track) = self.track_mut(id) {
+                undo(track);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Removing a folder moves its tracks up a level
+    pub fn remove_track(&mut self, id: &str) -> Option<Track> {
+        let index = self.track_index(id)?;
+        let track = self.tracks.remove(index);
+
+        for param in track.channel.params() {
+            self.params.unregister(&param.id);
+        }
+        self.session.remove_track(id);
+        if let Backend::Jack(jack) = &mut self.audio.backend {
+            let _ = jack.unregister_strip(id);
+        }
+        for other in &mut self.tracks {
+            if other.parent.as_deref() == Some(id) {
+                other.parent = track.parent.clone();
+            }
+            if other.output.as_deref() == Some(id) {
+                other.output = None;
+            }
+            other.sends.retain(|s| s.bus != id);
+        }
+
+        self.prepare_graph();
+        self.update_mix_state();
+        Some(track)
+    }
+
+    // Puts a copy right below the original and returns the copy's id
+    pub fn duplicate_track(&mut self, id: &str) -> Option<String> {
+        let index = self.track_index(id)?;
+        let copy = self.tracks[index].duplicate(self.engine.sample_rate);
+        Some(self.insert_track(index + 1, copy))
+    }
+
+    pub fn move_track(&mut self, id: &str, index: usize) -> anyhow::Result<()> {
+        let from = self
+            .track_index(id)
+            .ok_or_else(|| anyhow::anyhow!("no track with id {}", id))?;
+
+        let track = self.tracks.remove(from);
+        self.tracks.insert(index.min(self.tracks.len()), track);
+        self.prepare_graph();
+        Ok(())
+    }
+
+    pub fn set_mute(&mut self, id: &str, mute: bool) {
+        if let Some(track) = self.track_mut(id) {
+            track.mute = mute;
+        }
+        self.update_mix_state();
+    }
+
+    pub fn set_solo(&mut self, id: &str, solo: bool) {
+        if let Some(track) = self.track_mut(id) {
+            track.solo = solo;
+        }
+        self.update_mix_state();
+    }
+
+    pub fn set_arm(&mut self, id: &str, arm: bool) {
+        let sample_rate = self.engine.sample_rate;
+        if let Some(track) = self.track_mut(id) {
+            track.set_arm(arm, sample_rate);
+        }
+    }
+
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    // Retunes every MIDI track, instruments get their notes' new frequencies
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        for midi in self.tracks.iter_mut().filter_map(|t| t.midi_mut()) {
+            midi.set_tuning(&tuning);
+        }
+        self.tuning = tuning;
+    }
+
+    // Snaps a MIDI clip's notes to the chord track
+    pub fn snap_clip(
+        &mut self,
+        track_id: &str,
+        clip_id: &str,
+        target: SnapTarget,
+    ) -> anyhow::Result<()> {
+        let chord_track = &self.chord_track;
+        let clip = self
+            .tracks
+            .iter_mut()
+            .find(|t| t.id == track_id)
+            .and_then(|t| t.midi_mut())
+            .and_then(|midi| midi.clip_mut(clip_id))
+            .ok_or_else(|| anyhow::anyhow!("no MIDI clip {} on track {}", clip_id, track_id))?;
+
+        clip.snap_to_chord_track(chord_track, target);
+        Ok(())
+    }
+
+    // Transposes a MIDI clip written in `from` to the chord track's key where
+    // the clip starts
+    pub fn transpose_clip_to_key(
+        &mut self,
+        track_id: &str,
+        clip_id: &str,
+        from: &Key,
+    ) -> anyhow::Result<()> {
+        let chord_track = &self.chord_track;
+        let clip = self
+            .tracks
+            .iter_mut()
+            .find(|t| t.id == track_id)
+            .and_then(|t| t.midi_mut())
+            .and_then(|midi| midi.clip_mut(clip_id))
+            .ok_or_else(|| anyhow::anyhow!("no MIDI clip {} on track {}", clip_id, track_id))?;
+
+        let to = chord_track.key_at(clip.start);
+        clip.transpose_to_key(from, &to);
+        Ok(())
+    }
+
+    // Folder ids above a track, nearest first
+    fn ancestors(&self, track: &Track) -> Vec<String> {
+        let mut ancestors = Vec::new();
+        let mut parent = track.parent.clone();
+
+        while let Some(id) = parent {
+            if ancestors.contains(&id) {
+                break;
+            }
+            parent = self.track(&id).and_then(|t| t.parent.clone());
+            ancestors.push(id);
+        }
+
+        ancestors
+    }
+
+    // Works out which tracks are silenced by folder mutes or by solos. Call
+    // after changing `mute`, `solo` or `parent` directly.
+    pub fn update_mix_state(&mut self) {
+        let any_solo = self.tracks.iter().any(|t| t.solo);
+
+        let silenced: Vec<bool> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                let ancestors = self.ancestors(track);
+                let in_folder = |f: fn(&Track) -> bool| {
+                    ancestors
+                        .iter()
+                        .any(|id| self.track(id).map(f).unwrap_or(false))
+                };
+
+                let folder_muted = in_folder(|t| t.mute);
+                let soloed = track.solo || in_folder(|t| t.solo);
+
+                folder_muted || (any_solo && !soloed && !track.is_folder())
+            })
+            .collect();
+
+        for (track, silenced) in self.tracks.iter_mut().zip(silenced) {
+            track.silenced = silenced;
+        }
+    }
+
+    pub fn bar_to_sample(&self, bar: Bar) -> u64 {
+        self.tempo.bars_to_samples(bar, self.sample_rate())
+    }
+
+    pub fn sample_to_bar(&self, sample: u64) -> Bar {
+        self.tempo.samples_to_bars(sample, self.sample_rate())
+    }
+
+    // Moves the play head to a marker, looked up by id or name
+    pub fn jump_to_marker(&mut self, key: &str) -> anyhow::Result<()> {
+        let bar = self
+            .arrangement
+            .marker(key)
+            .map(|m| m.bar)
+            .ok_or_else(|| anyhow::anyhow!("no marker {}", key))?;
+
+        self.engine.transport.seek(self.bar_to_sample(bar));
+        Ok(())
+    }
+
+    pub fn jump_to_next_marker(&mut self) -> bool {
+        let bar = self.sample_to_bar(self.engine.transport.position);
+        match self.arrangement.next_marker(bar).map(|m| m.bar) {
+            Some(bar) => {
+                self.engine.transport.seek(self.bar_to_sample(bar));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn jump_to_previous_marker(&mut self) -> bool {
+        let bar = self.sample_to_bar(self.engine.transport.position);
+        match self.arrangement.previous_marker(bar).map(|m| m.bar) {
+            Some(bar) => {
+                self.engine.transport.seek(self.bar_to_sample(bar));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Loops the transport between the locators
+    pub fn loop_locators(&mut self) {
+        let locators = self.arrangement.locators;
+        let start = self.bar_to_sample(locators.left);
+        let end = self.bar_to_sample(locators.right);
+        self.engine.transport.set_loop(start, end);
+    }
+
+    pub fn set_loop(&mut self, start: Bar, end: Bar) {
+        self.arrangement.set_locators(start, end);
+        self.loop_locators();
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.engine.transport.clear_loop();
+    }
+
+    // Puts the locators around a section and loops it
+    pub fn loop_section(&mut self, key: &str) -> anyhow::Result<()> {
+        let (start, end) = self
+            .arrangement
+            .section(key)
+            .map(|s| (s.start, s.end))
+            .ok_or_else(|| anyhow::anyhow!("no section {}", key))?;
+
+        self.set_loop(start, end);
+        Ok(())
+    }
+
+    // Re-resolves every clip after a tempo, sample rate or arrangement change
+    pub fn relayout(&mut self) {
+        let sample_rate = self.sample_rate();
+        for track in &mut self.tracks {
+            if let Some(audio) = track.audio_mut() {
+                audio.layout_clips(&self.tempo, sample_rate);
+            }
+            if let Some(midi) = track.midi_mut() {
+                midi.layout_clips(&self.tempo, sample_rate);
+            }
+            for lane in &mut track.automation {
+                lane.layout(&self.tempo, sample_rate);
+            }
+        }
+        self.session.layout(&self.tempo, sample_rate);
+        self.metronome.layout(&self.tempo, sample_rate);
+
+        if self.engine.transport.loop_range.is_some() {
+            self.loop_locators();
+        }
+    }
+
+    // Opens `bars` of silence at `at` on every track, pushing later clips,
+    // markers, sections and tempo and meter changes back
+    pub fn insert_time(&mut self, at: Bar, bars: Bar) {
+        // The gap is measured in the meter it opens in
+        let mut tempo = self.tempo.clone();
+        tempo.insert_time(at, bars);
+        let start = tempo.bars_to_beats(at);
+        let length = tempo.bars_to_beats(at + bars) - start;
+        let sample_rate = self.sample_rate();
+
+        let (from, to) = (
+            tempo.beats_to_samples(start, sample_rate),
+            tempo.beats_to_samples(start + length, sample_rate),
+        );
+
+        for track in &mut self.tracks {
+            if let Some(audio) = track.audio_mut() {
+                edit::insert_time(&mut audio.clips, start, length, &self.tempo, sample_rate);
+                audio.insert_time(from, to - from);
+                if let Some(punch) = audio.punch.as_mut() {
+                    punch.insert_time(at, bars);
+                }
+            }
+            if let Some(midi) = track.midi_mut() {
+                edit::insert_time(&mut midi.clips, start, length, &self.tempo, sample_rate);
+            }
+            for lane in &mut track.automation {
+                lane.insert_time(start, length);
+            }
+        }
+
+        self.arrangement.insert_time(at, bars);
+        self.chord_track.insert_time(start, length);
+        self.tempo = tempo;
+        self.relayout();
+    }
+
+    // Cuts `start..end` out of every track and closes the gap
+    pub fn delete_time(&mut self, start: Bar, end: Bar) {
+        let (from, to) = (
+            self.tempo.bars_to_beats(start),
+            self.tempo.bars_to_beats(end),
+        );
+        let sample_rate = self.sample_rate();
+
+        let samples = (
+            self.tempo.beats_to_samples(from, sample_rate),
+            self.tempo.beats_to_samples(to, sample_rate),
+        );
+
+        for track in &mut self.tracks {
+            if let Some(audio) = track.audio_mut() {
+                edit::delete_time(&mut audio.clips, from, to, &self.tempo, sample_rate);
+                audio.delete_time(samples.0, samples.1);
+                if audio
+                    .punch
+                    .as_mut()
+                    .is_some_and(|p| !p.delete_time(start, end))
+                {
+                    audio.clear_punch();
+                }
+            }
+            if let Some(midi) = track.midi_mut() {
+                edit::delete_time(&mut midi.clips, from, to, &self.tempo, sample_rate);
+            }
+            for lane in &mut track.automation {
+                lane.delete_time(from, to);
+            }
+        }
+
+        self.arrangement.delete_time(start, end);
+        self.chord_track.delete_time(from, to);
+        self.tempo.delete_time(start, end);
+        self.relayout();
+    }
+
+    // Repeats `start..end` on every track right after itself
+    pub fn duplicate_time(&mut self, start: Bar, end: Bar) {
+        let (from, to) = (
+            self.tempo.bars_to_beats(start),
+            self.tempo.bars_to_beats(end),
+        );
+        let sample_rate = self.sample_rate();
+
+        let samples = (
+            self.tempo.beats_to_samples(from, sample_rate),
+            self.tempo.beats_to_samples(to, sample_rate),
+        );
+
+        for track in &mut self.tracks {
+            if let Some(audio) = track.audio_mut() {
+                edit::duplicate_time(&mut audio.clips, from, to, &self.tempo, sample_rate);
+                audio.duplicate_time(samples.0, samples.1);
+                if let Some(punch) = audio.punch.as_mut() {
+                    punch.insert_time(end, end - start);
+                }
+            }
+            if let Some(midi) = track.midi_mut() {
+                edit::duplicate_time(&mut midi.clips, from, to, &self.tempo, sample_rate);
+            }
+            for lane in &mut track.automation {
+                lane.duplicate_time(from, to);
+            }
+        }
+
+        self.arrangement.duplicate_time(start, end);
+        self.chord_track.duplicate_time(from, to);
+        self.tempo.duplicate_time(start, end);
+        self.relayout();
+    }
+
+    // Adds a MIDI track per part of a Standard MIDI File and returns their
+    // ids. The file's tempo map and key signatures replace the song's.
+    pub fn import_midi<P: AsRef<std::path::Path>>(
+        &mut self,
+        path: P,
+    ) -> anyhow::Result<Vec<String>> {
+        let smf = Smf::load(path)?;
+
+        // Everything is built before the song changes, so a bad file leaves it alone
+        let tempo = if smf.has_tempo_map() {
+            smf.tempo()
+        } else {
+            self.tempo.clone()
+        };
+        let sample_rate = self.sample_rate();
+        let mut tracks = Vec::new();
+        for clip in smf.clips(&tempo) {
+            let mut track = self.new_midi_track(&clip.name);
+            if let Some(midi) = track.midi_mut() {
+                midi.insert_clip(clip, &tempo, sample_rate)?;
+            }
+            tracks.push(track);
+        }
+        let keys = smf.keys();
+
+        self.tempo = tempo;
+        if !keys.is_empty() {
+            self.chord_track.keys.clear();
+            for (beat, key) in keys {
+                if beat <= 0.0 {
+                    self.chord_track.key = key;
+                } else {
+                    self.chord_track.set_key(beat, key);
+                }
+            }
+        }
+        let ids = tracks
+            .into_iter()
+            .map(|track| self.add_track(track))
+            .collect();
+        self.relayout();
+        Ok(ids)
+    }
+
+    // Writes every MIDI track's clips, the tempo map and the chord track's keys
+    // as a type 1 file. Clips sharing a resolution keep it.
+    pub fn export_midi<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        let parts: Vec<(&str, &[MidiClip])> = self
+            .tracks
+            .iter()
+            .filter_map(|t| {
+                t.midi()
+                    .map(|midi| (t.name.as_str(), midi.clips.as_slice()))
+            })
+            .collect();
+
+        let mut resolutions = parts
+            .iter()
+            .flat_map(|(_, clips)| clips.iter().map(|c| c.ppq));
+        let ppq = match resolutions.next() {
+            Some(ppq) if resolutions.all(|p| p == ppq) => ppq,
+            _ => DEFAULT_PPQ,
+        };
+
+        let smf = Smf::export(
+            &parts,
+            &self.tempo,
+            Some(&self.chord_track),
+            ppq.min(0x7FFF) as u16,
+        );
+        smf.save(path)
+    }
+
+    // Writes one MIDI clip as a type 0 file at its own resolution
+    pub fn export_midi_clip<P: AsRef<std::path::Path>>(
+        &self,
+        track_id: &str,
+        clip_id: &str,
+        path: P,
+    ) -> anyhow::Result<()> {
+        let clip = self
+            .track(track_id)
+            .and_then(|t| t.midi())
+            .and_then(|midi| midi.clips.iter().find(|c| c.id == clip_id))
+            .ok_or_else(|| anyhow::anyhow!("no MIDI clip {} on track {}", clip_id, track_id))?;
+
+        Smf::from_clip(clip, &self.tempo).save(path)
+    }
+
+    // Puts a clip in a session slot, it has to suit the track
+    pub fn set_slot_clip(
+        &mut self,
+        track_id: &str,
+        scene: usize,
+        mut clip: SlotClip,
+    ) -> anyhow::Result<()> {
+        clip.layout(&self.tempo, self.sample_rate());
+        let track = self
+            .track(track_id)
+            .ok_or_else(|| anyhow::anyhow!("no track with id {}", track_id))?;
+
+        match (&track.kind, &clip) {
+            (TrackKind::Audio(_), SlotClip::Audio(_)) | (TrackKind::Midi(_), SlotClip::Midi(_)) => {
+                self.session.set_clip(track_id, scene, clip)
+            }
+            _ => Err(anyhow::anyhow!(
+                "clip {} does not suit track {}",
+                clip.name(),
+                track.name
+            )),
+        }
+    }
+
+    // Launches a slot on the next quantization boundary and returns the
+    // sample it starts on. Launching while stopped starts right away.
+    pub fn launch_clip(&mut self, track_id: &str, scene: usize) -> anyhow::Result<u64> {
+        if self
+            .session
+            .slot(track_id, scene)
+            .is_none_or(|s| s.clip.is_none())
+        {
+            return Err(anyhow::anyhow!(
+                "slot {} of track {} is empty",
+                scene,
+                track_id
+            ));
+        }
+
+        let index = self.slot_track(track_id)?;
+        let start = self.launch_beat(track_id, Some(scene));
+        let clip = self.session.take_clip(track_id, scene);
+        let at = self.start_slot(index, Some(scene), start, clip)?;
+        self.session.cue_follows();
+        Ok(at)
+    }
+
+    // Launches every slot in a scene together. Tracks with an empty slot in
+    // the scene stop.
+    pub fn launch_scene(&mut self, key: &str) -> anyhow::Result<u64> {
+        let scene = self
+            .session
+            .scene_index(key)
+            .ok_or_else(|| anyhow::anyhow!("no scene {}", key))?;
+
+        let start = self.launch_beat("", None);
+        let mut at = self.tempo.beats_to_samples(start, self.sample_rate());
+
+        for index in 0..self.tracks.len() {
+            let Some(column) = self.session.slots.get(&self.tracks[index].id) else {
+                continue;
+            };
+
+            let filled = column.get(scene).is_some_and(|s| s.clip.is_some());
+            let clip = self.session.take_clip(&self.tracks[index].id, scene);
+            at = self.start_slot(index, filled.then_some(scene), start, clip)?;
+        }
+
+        self.session.cue_follows();
+        Ok(at)
+    }
+
+    // The track's stop button
+    pub fn stop_clip(&mut self, track_id: &str) -> anyhow::Result<u64> {
+        let index = self.slot_track(track_id)?;
+        let start = self.launch_beat(track_id, None);
+        self.start_slot(index, None, start, None)
+    }
+
+    pub fn stop_all_clips(&mut self) -> anyhow::Result<u64> {
+        let start = self.launch_beat("", None);
+        let mut at = self.tempo.beats_to_samples(start, self.sample_rate());
+
+        for index in 0..self.tracks.len() {
+            if self.session.slots.contains_key(&self.tracks[index].id) {
+                at = self.start_slot(index, None, start, None)?;
+            }
+        }
+        Ok(at)
+    }
+
+    fn slot_track(&self, track_id: &str) -> anyhow::Result<usize> {
+        self.track_index(track_id)
+            .ok_or_else(|| anyhow::anyhow!("no track with id {}", track_id))
+    }
+
+    // Hands every track back to the arrangement
+    pub fn back_to_arrangement(&mut self) {
+        for track in &mut self.tracks {
+            if let Some(audio) = track.audio_mut() {
+                audio.back_to_arrangement();
+            }
+            if let Some(midi) = track.midi_mut() {
+                midi.back_to_arrangement();
+            }
+        }
+        self.session.clear_launches();
+    }
+
+    fn launch_beat(&mut self, track_id: &str, scene: Option<usize>) -> Beat {
+        let position = self.engine.transport.position;
+        let sample_rate = self.sample_rate();
+
+        if !self.engine.transport.playing {
+            self.engine.transport.play();
+            return self.tempo.samples_to_beats(position, sample_rate);
+        }
+
+        self.session
+            .quantization_for(track_id, scene)
+            .next_beat(position, &self.tempo, sample_rate)
+    }
+
+    // Places `clip`, a looping copy of a slot's clip, on the track at `index`
+    // from `start` beats, cutting off whatever the session was playing there.
+    // No scene stops. Follow actions call this from `render` with the slot's
+    // cued spare.
+    fn start_slot(
+        &mut self,
+        index: usize,
+        scene: Option<usize>,
+        start: Beat,
+        clip: Option<SlotClip>,
+    ) -> anyhow::Result<u64> {
+        let sample_rate = self.sample_rate();
+        let at = self.tempo.beats_to_samples(start, sample_rate);
+        let track_id = &self.tracks[index].id;
+        let after = scene
+            .and_then(|s| self.session.slot(track_id, s))
+            .and_then(|s| s.follow.as_ref())
+            .map(|f| {
+                f.after
+                    .map(|beats| self.tempo.beats_to_samples(start + beats, sample_rate) - at)
+            });
+
+        let track = &mut self.tracks[index];
+        let session = &mut self.session;
+
+        let mut follow_at = None;
+        match (&mut track.kind, clip) {
+            (TrackKind::Audio(audio), clip) => {
+                slot::cut_clips(&mut audio.session_clips, at, |clip| {
+                    session.recycle(&track.id, SlotClip::Audio(clip))
+                });
+                if let Some(SlotClip::Audio(mut clip)) = clip {
+                    clip.launch(start, &self.tempo, sample_rate);
+                    follow_at = after.map(|a| at + a.unwrap_or(clip.cycle_samples()).max(1));
+                    audio.session_clips.push(clip);
+                }
+                audio.session_from = Some(audio.session_from.map_or(at, |f| f.min(at)));
+            }
+            (TrackKind::Midi(midi), clip) => {
+                slot::cut_clips(&mut midi.session_clips, at, |clip| {
+                    session.recycle(&track.id, SlotClip::Midi(clip))
+                });
+                if let Some(SlotClip::Midi(mut clip)) = clip {
+                    clip.launch(start, &self.tempo, sample_rate);
+                    follow_at = after.map(|a| at + a.unwrap_or(clip.cycle_samples()).max(1));
+                    midi.session_clips.push(clip);
+                }
+                midi.session_from = Some(midi.session_from.map_or(at, |f| f.min(at)));
+            }
+            _ => return Err(anyhow::anyhow!("track {} cannot play clips", track.name)),
+        }
+
+        session.push_launch(
+            &track.id,
+            Launch {
+                scene,
+                at,
+                follow_at,
+            },
+        );
+        Ok(at)
+    }
+
+    // Fires follow actions due in the next `frames` and keeps launched clips
+    // looping past the end of the block
+    fn update_session(&mut self, frames: usize) {
+        if !self.engine.transport.playing {
+            return;
+        }
+
+        let position = self.engine.transport.position;
+        let end = position + frames as u64;
+
+        for index in 0..self.tracks.len() {
+            while let Some((scene, at)) = self.session.follow_due(&self.tracks[index].id, end) {
+                let track_id = &self.tracks[index].id;
+                let next = self.session.follow(track_id, scene);
+                // Copying a clip here would allocate, without a spare the
+                // current clip plays on
+                let clip = match next {
+                    Some(next) => match self.session.take_spare(track_id, next) {
+                        Some(clip) => Some(clip),
+                        None => {
+                            self.session.skip_follow(track_id);
+                            break;
+                        }
+                    },
+                    None => None,
+                };
+                let start = self.tempo.samples_to_beats(at, self.sample_rate());
+                if self.start_slot(index, next, start, clip).is_err() {
+                    break;
+                }
+            }
+
+            let follows = self
+                .session
+                .launches(&self.tracks[index].id)
+                .last()
+                .map(|l| l.scene.is_some());
+            let track = &mut self.tracks[index];
+            let session = &mut self.session;
+            match &mut track.kind {
+                TrackKind::Audio(audio) => {
+                    slot::prune_clips(&mut audio.session_clips, position, |clip| {
+                        session.recycle(&track.id, SlotClip::Audio(clip))
+                    });
+                    if let (Some(true), Some(clip)) = (follows, audio.session_clips.last_mut()) {
+                        clip.extend_to(end);
+                    }
+                }
+                TrackKind::Midi(midi) => {
+                    slot::prune_clips(&mut midi.session_clips, position, |clip| {
+                        session.recycle(&track.id, SlotClip::Midi(clip))
+                    });
+                    if let (Some(true), Some(clip)) = (follows, midi.session_clips.last_mut()) {
+                        clip.extend_to(end);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.session.prune(position);
+    }
+
+    // Adds a lane driving a registered parameter to a track, returns its id
+    pub fn add_automation(&mut self, track_id: &str, param_id: &str) -> anyhow::Result<String> {
+        let param = self
+            .params
+            .get(param_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no parameter {}", param_id))?;
+        let sample_rate = self.sample_rate();
+        let track = self
+            .tracks
+            .iter_mut()
+            .find(|t| t.id == track_id)
+            .ok_or_else(|| anyhow::anyhow!("no track with id {}", track_id))?;
+
+        let mut lane = AutomationLane::new(param_id);
+        lane.bind(Some(param));
+        lane.layout(&self.tempo, sample_rate);
+        Ok(track.add_automation(lane))
+    }
+
+    // Reconnects every lane to its parameter, call after registering new ones
+    pub fn bind_automation(&mut self) {
+        for track in &mut self.tracks {
+            for lane in &mut track.automation {
+                lane.bind(self.params.get(&lane.param).cloned());
+            }
+        }
+    }
+
+    fn has_automation(&self) -> bool {
+        self.tracks
+            .iter()
+            .flat_map(|t| &t.automation)
+            .any(|l| l.mode != AutomationMode::Off && l.target().is_some())
+    }
+
+    // Drives or records every lane for the next stretch and returns its length.
+    // Stretches end on breakpoints, so each ramp lands exactly on one.
+    fn apply_automation(&mut self, frames: usize) -> usize {
+        let position = self.engine.transport.position;
+        let mut frames = self
+            .engine
+            .transport
+            .frames_until_wrap(frames.min(AUTOMATION_INTERVAL));
+        if !self.automating {
+            // Starting up, parameters snap to their lanes
+            frames = 1;
+            self.automating = true;
+        }
+
+        for lane in self.tracks.iter().flat_map(|t| &t.automation) {
+            if !lane.is_reading() {
+                continue;
+            }
+            // A one sample stretch puts breakpoints and jumps exactly in place
+            if lane.has_point_at(position) {
+                frames = 1;
+            } else if let Some(next) = lane.next_point(position) {
+                frames = frames.min((next - position) as usize);
+            }
+        }
+
+        let end = position + frames as u64;
+        for lane in self.tracks.iter_mut().flat_map(|t| &mut t.automation) {
+            let Some(value) = lane.target().map(|p| p.get()) else {
+                continue;
+            };
+
+            if lane.is_writing() {
+                lane.record(position, value);
+                continue;
+            }
+            if lane.has_pass() {
+                // Touch was let go
+                lane.commit();
+            }
+            if lane.is_reading() {
+                if let (Some(param), Some(value)) = (lane.target(), lane.value_at_sample(end - 1)) {
+                    param.set_ramped(value, frames as u32);
+                }
+            }
+        }
+
+        frames
+    }
+
+    // Starts playback from the play head once the metronome has counted in.
+    // Returns the samples until then.
+    pub fn record(&mut self) -> u64 {
+        if self.engine.transport.playing {
+            return 0;
+        }
+
+        let length = self
+            .metronome
+            .start_count_in(self.engine.transport.position);
+        if length == 0 {
+            self.engine.transport.play();
+        }
+        length
+    }
+
+    // Stops playback, or a count-in that is still going
+    pub fn stop(&mut self) {
+        self.metronome.cancel_count_in();
+        self.engine.transport.stop();
+    }
+
+    // Renders every track and the metronome, then the master volume
+    pub fn render(&mut self, input: &[f32], output: &mut [f32]) {
+        // A count-in holds the transport, which starts on the sample it ends
+        let counted = self
+            .metronome
+            .counting_in()
+            .map_or(0, |left| (left as usize).min(output.len()));
+
+        if counted > 0 {
+            let (head, tail) = output.split_at_mut(counted);
+            let split = counted.min(input.len());
+            self.render_span(&input[..split], head);
+
+            if self.metronome.counting_in().is_none() {
+                self.engine.transport.play();
+            }
+            if !tail.is_empty() {
+                self.render_span(&input[split..], tail);
+            }
+        } else {
+            self.render_span(input, output);
+        }
+        self.engine.end_cycle();
+
+        for sample in output.iter_mut() {
+            *sample *= self.master.next_value();
+        }
+    }
+
+    fn render_span(&mut self, input: &[f32], output: &mut [f32]) {
+        let transport = self.engine.transport.clone();
+        self.update_session(output.len());
+
+        if self.engine.transport.playing && self.has_automation() {
+            let mut offset = 0;
+            while offset < output.len() {
+                let frames = self.apply_automation(output.len() - offset);
+                let input = &input[offset.min(input.len())..(offset + frames).min(input.len())];
+                self.engine.render_graph(
+                    &mut self.tracks,
+                    input,
+                    &mut output[offset..offset + frames],
+                );
+                offset += frames;
+            }
+        } else {
+            self.automating = false;
+            if !self.engine.transport.playing {
+                // Stopping ends write passes and latches
+                for lane in self.tracks.iter_mut().flat_map(|t| &mut t.automation) {
+                    lane.stop();
+                }
+            }
+            self.engine.render_graph(&mut self.tracks, input, output);
+        }
+
+        self.render_metronome(transport, output);
+    }
+
+    // Clicks over what was just rendered, wrapping with the loop like the engine
+    fn render_metronome(&mut self, mut transport: Transport, output: &mut [f32]) {
+        let mut offset = 0;
+        while offset < output.len() {
+            let frames = transport.frames_until_wrap(output.len() - offset);
+            let context = ProcessContext {
+                sample_rate: self.engine.sample_rate,
+                frames,
+                position: transport.position,
+                playing: transport.playing,
+                input_latency: self.engine.input_latency,
+            };
+
+            self.metronome
+                .process(&context, &[], &mut output[offset..offset + frames]);
+            transport.advance(frames);
+            offset += frames;
+        }
+    }
+
+    // Drives `render` from the null backend for `blocks` blocks
+    pub fn run_headless(&mut self, blocks: usize) -> anyhow::Result<()> {
+        let backend = std::mem::replace(&mut self.audio.backend, Backend::Cpal);
+        let Backend::Null(mut null) = backend else {
+            self.audio.backend = backend;
+            return Err(anyhow::anyhow!("studio is not using the null backend"));
+        };
+
+        self.engine.input_latency = null.input_latency;
+        null.run_blocks(blocks, |input, output| self.render(input, output));
+        self.audio.backend = Backend::Null(null);
+        Ok(())
+    }
+
+    // Moves the studio into the output device's callback, recording from the
+    // input device when there is one. `Studio::stop_cpal` hands it back, a
+    // studio whose streams cannot start is dropped with the error.
+    pub fn start_cpal(mut self) -> anyhow::Result<CpalStreams> {
+        if !matches!(self.audio.backend, Backend::Cpal) {
+            return Err(anyhow::anyhow!("studio is not using cpal"));
+        }
+        let output = self
+            .audio
+            .output
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("studio has no output device"))?;
+        let input = self.audio.input.take();
+
+        CpalStreams::start(input, output, self)
+    }
+
+    pub fn stop_cpal(streams: CpalStreams) -> anyhow::Result<Studio> {
+        let (mut studio, input, output): (Studio, _, _) = streams.stop()?;
+        studio.audio.input = input;
+        studio.audio.output = Some(output);
+        Ok(studio)
+    }
+
+    // Moves the studio onto the JACK process thread, giving each track a strip
+    // it does not have yet. `Studio::stop_jack` hands it back, a studio not
+    // on JACK is dropped with the error.
+    pub fn start_jack(mut self) -> anyhow::Result<JackBackend> {
+        let backend = std::mem::replace(&mut self.audio.backend, Backend::Cpal);
+        let Backend::Jack(mut jack) = backend else {
+            return Err(anyhow::anyhow!("studio is not using a JACK backend"));
+        };
+
+        for track in &self.tracks {
+            if !jack.has_strip(&track.id) {
+                jack.register_strip(&track.id, &track.name)?;
+            }
+        }
+        jack.start(self)?;
+        Ok(jack)
+    }
+
+    pub fn stop_jack(mut jack: JackBackend) -> anyhow::Result<Studio> {
+        let mut studio: Studio = jack.stop()?;
+        studio.audio.backend = Backend::Jack(jack);
+        Ok(studio)
+    }
+
+    // Re-prepares everything that depends on the backend's sample rate or
+    // block size, keeping the play head at the same time
+    pub fn prepare(&mut self, sample_rate: u32, block_size: usize) {
+        let transport = &mut self.engine.transport;
+        let ratio = sample_rate as f64 / self.engine.sample_rate.max(1) as f64;
+        transport.position = (transport.position as f64 * ratio).round() as u64;
+
+        self.engine.prepare(sample_rate, block_size);
+        for track in &mut self.tracks {
+            track.prepare(sample_rate, block_size);
+        }
+        self.master = self.volume.smoothed(sample_rate, DEFAULT_RAMP);
+        self.prepare_graph();
+        self.relayout();
     }
 
     pub fn update_time(&mut self) {
@@ -154,3 +1397,196 @@ impl Studio {
         self.current_time = time::Duration::new(0, 0);
     }
 }
+
+impl Renderer for Studio {
+    fn prepare(&mut self, sample_rate: u32, block_size: usize) {
+        Studio::prepare(self, sample_rate, block_size);
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.engine.sample_rate
+    }
+
+    fn block_size(&self) -> usize {
+        self.engine.block_size
+    }
+
+    fn transport_mut(&mut self) -> &mut Transport {
+        &mut self.engine.transport
+    }
+
+    fn set_input_latency(&mut self, frames: u32) {
+        self.engine.input_latency = frames;
+    }
+
+    fn node_index(&self, id: &str) -> Option<usize> {
+        self.track_index(id)
+    }
+
+    fn route_input(&mut self, index: usize, samples: &[f32]) {
+        self.engine.route_input(index, samples);
+    }
+
+    fn render(&mut self, input: &[f32], output: &mut [f32]) {
+        Studio::render(self, input, output);
+    }
+
+    fn node_output(&self, index: usize) -> Option<&[f32]> {
+        self.engine.node_output(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::clip::clip::AudioClip;
+    use crate::core::session::slot::{FollowAction, FollowRule};
+    use crate::core::track::audio_track::take::Take;
+
+    fn studio() -> Studio {
+        Studio::new(Config {
+            tempo: Tempo::new(120.0, None),
+            audio: Audio::null(48000, 256),
+        })
+    }
+
+    // A track with a one bar clip in each of two scenes, the first following
+    // on to the second after a beat
+    fn following(studio: &mut Studio) -> String {
+        let track = studio.new_midi_track("Keys");
+        let id = studio.add_track(track);
+        for scene in 0..2 {
+            studio.session.add_scene(&format!("Scene {}", scene + 1));
+            let clip = MidiClip::new(&format!("Clip {}", scene + 1), 4 * DEFAULT_PPQ as u64);
+            studio
+                .set_slot_clip(&id, scene, SlotClip::Midi(clip))
+                .unwrap();
+        }
+        studio.session.slot_mut(&id, 0).unwrap().follow =
+            Some(FollowRule::new(1.0, vec![(FollowAction::Next, 1.0)]));
+        id
+    }
+
+    #[test]
+    fn follow_actions_launch_cued_spares() {
+        let mut studio = studio();
+        let id = following(&mut studio);
+        studio.launch_clip(&id, 0).unwrap();
+        studio.run_headless(100).unwrap();
+
+        let position = studio.engine.transport.position;
+        assert_eq!(
+            studio.session.current(&id, position).unwrap().scene,
+            Some(1)
+        );
+        let midi = studio.track(&id).unwrap().midi().unwrap();
+        assert_eq!(midi.session_clips.last().unwrap().start_sample(), 24000);
+    }
+
+    #[test]
+    fn follow_actions_without_a_spare_play_on() {
+        let mut studio = studio();
+        let id = following(&mut studio);
+        studio.launch_clip(&id, 0).unwrap();
+        studio.session.slot_mut(&id, 1).unwrap().spares.clear();
+        studio.run_headless(100).unwrap();
+
+        let position = studio.engine.transport.position;
+        let launch = studio.session.current(&id, position).unwrap();
+        assert_eq!((launch.scene, launch.follow_at), (Some(0), None));
+    }
+
+    #[test]
+    fn routes_tracks_into_buses_and_refuses_loops() {
+        let mut studio = studio();
+        let keys = studio.new_midi_track("Keys");
+        let keys = studio.add_track(keys);
+        let group = studio.new_bus_track("Group");
+        let group = studio.add_track(group);
+        let reverb = studio.new_bus_track("Reverb");
+        let reverb = studio.add_track(reverb);
+
+        studio.set_output(&keys, Some(&group)).unwrap();
+        studio.set_send(&keys, &reverb, 0.25).unwrap();
+        studio.set_output(&reverb, Some(&group)).unwrap();
+        assert!(studio.set_output(&group, Some(&reverb)).is_err());
+        assert!(studio.set_output(&group, Some(&keys)).is_err());
+        assert_eq!(studio.track(&group).unwrap().output, None);
+
+        // Moving tracks keeps the engine's indices in line
+        studio.move_track(&keys, 2).unwrap();
+        let routes = studio.engine.routes();
+        assert_eq!(routes[2].output, Some(0));
+        assert_eq!(routes[2].sends, vec![(1, 0.25)]);
+        assert_eq!(routes[1].output, Some(0));
+        assert!(routes[0].summing && !routes[2].summing);
+
+        studio.remove_track(&group);
+        let track = studio.track(&keys).unwrap();
+        assert_eq!((track.output.as_deref(), track.sends.len()), (None, 1));
+    }
+
+    // Plays a clip out through a 300 frame loopback cable into an armed track.
+    // Returns the clip's samples and the armed track's id.
+    fn loopback(studio: &mut Studio, dir: &str) -> (Vec<f32>, String) {
+        if let Backend::Null(null) = &mut studio.audio.backend {
+            null.set_loopback(300);
+            null.input_latency = 300;
+        }
+
+        let samples: Vec<f32> = (0..4000).map(|i| (i % 97 + 1) as f32 / 100.0).collect();
+        let player = studio.new_audio_track("Player");
+        let player = studio.add_track(player);
+        let clip = AudioClip::from_buffer("Clip", samples.clone(), 48000);
+        let tempo = studio.tempo.clone();
+        let audio = studio.track_mut(&player).unwrap().audio_mut().unwrap();
+        audio.insert_clip(clip, &tempo, 48000).unwrap();
+
+        let recorder = studio.new_audio_track("Recorder");
+        let recorder = studio.add_track(recorder);
+        let audio = studio.track_mut(&recorder).unwrap().audio_mut().unwrap();
+        audio.take_dir = std::env::temp_dir().join(dir);
+        studio.set_arm(&recorder, true);
+        (samples, recorder)
+    }
+
+    fn takes(studio: &mut Studio, id: &str) -> Vec<Take> {
+        let audio = studio.track_mut(id).unwrap().audio_mut().unwrap();
+        audio.flush_takes().unwrap();
+        audio.takes.clone()
+    }
+
+    #[test]
+    fn loopback_takes_line_up_with_what_played() {
+        let mut studio = studio();
+        let (samples, recorder) = loopback(&mut studio, "noyz-loopback-test");
+        studio.engine.transport.play();
+        studio.run_headless(20).unwrap();
+        studio.stop();
+        studio.run_headless(1).unwrap();
+
+        let takes = takes(&mut studio, &recorder);
+        assert_eq!(takes.len(), 1);
+        assert_eq!((takes[0].start, takes[0].length), (0, 20 * 256 - 300));
+        assert!((0..4000).all(|p| takes[0].sample_at(p) == samples[p as usize]));
+    }
+
+    #[test]
+    fn loop_wraps_start_a_new_take() {
+        let mut studio = studio();
+        let (samples, recorder) = loopback(&mut studio, "noyz-loop-wrap-test");
+        studio.engine.transport.set_loop(0, 2048);
+        studio.engine.transport.play();
+        studio.run_headless(12).unwrap();
+        studio.stop();
+        studio.run_headless(1).unwrap();
+
+        // The first pass ends with what was still arriving when the loop wrapped
+        let takes = takes(&mut studio, &recorder);
+        let spans: Vec<(u64, u64)> = takes.iter().map(|t| (t.start, t.length)).collect();
+        assert_eq!(spans, vec![(0, 2048), (0, 1024 - 300)]);
+        for take in &takes {
+            assert!((0..take.length).all(|p| take.sample_at(p) == samples[p as usize]));
+        }
+    }
+}
@@ -1,178 +1,269 @@
-type Hz = f32;
-type KHz = f32;
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+// Speed of sound in air at 20°C, in metres per second
+pub const SPEED_OF_SOUND: f32 = 343.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Hz {
+    pub hz: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct KHz {
+    pub khz: f32,
+}
 
 impl Default for Hz {
     fn default() -> Self {
-        432.0
+        Hz::new(432.0)
+    }
+}
+
+impl Default for KHz {
+    fn default() -> Self {
+        Hz::default().into()
     }
 }
 
 impl Hz {
-    pub fn new(hz: Hz) -> Self {
+    pub fn new(hz: f32) -> Self {
         Hz { hz }
     }
 
-    pub fn set(&mut self, hz: Hz) {
-        self.hz = hz;
+    // Highest frequency `sample_rate` can carry
+    pub fn nyquist(sample_rate: u32) -> Self {
+        Hz::new(sample_rate as f32 / 2.0)
     }
 
-    pub fn to_khz(&self) -> KHz {
-        self.hz / 1000.0
+    pub fn to_khz(self) -> KHz {
+        self.into()
     }
 
-    pub fn add(&mut self, hz: Hz) {
-        self.hz += hz;
+    // Length of one cycle, None when the frequency is not above 0 Hz or the
+    // cycle is too long to hold
+    pub fn period(self) -> Option<Duration> {
+        Duration::try_from_secs_f64(1.0 / self.hz as f64).ok()
     }
 
-    pub fn sub(&mut self, hz: Hz) {
-        self.hz -= hz;
+    // Length of one cycle in samples, fractional
+    pub fn period_samples(self, sample_rate: u32) -> f32 {
+        sample_rate as f32 / self.hz
     }
 
-    pub fn mul(&mut self, hz: Hz) {
-        self.hz *= hz;
+    // Wavelength in metres for a wave travelling at `speed` metres per second
+    pub fn wavelength(self, speed: f32) -> f32 {
+        speed / self.hz
     }
 
-    pub fn div(&mut self, hz: Hz) {
-        self.hz /= hz;
+    pub fn wavelength_in_air(self) -> f32 {
+        self.wavelength(SPEED_OF_SOUND)
     }
 
-    pub fn rem(&mut self, hz: Hz) {
-        self.hz %= hz;
+    pub fn is_below_nyquist(self, sample_rate: u32) -> bool {
+        self.hz.is_finite() && self.hz >= 0.0 && self < Hz::nyquist(sample_rate)
     }
 
-    pub fn neg(&mut self) {
-        self.hz = -self.hz;
+    // The frequency, if `sample_rate` can play it without aliasing
+    pub fn validate(self, sample_rate: u32) -> anyhow::Result<Self> {
+        if self.is_below_nyquist(sample_rate) {
+            Ok(self)
+        } else {
+            Err(anyhow!(
+                "{} is outside 0 Hz to {} at {} Hz",
+                self,
+                Hz::nyquist(sample_rate),
+                sample_rate
+            ))
+        }
     }
 
-    pub fn inc(&mut self) {
-        self.hz += 1.0;
+    // Keeps the frequency between 0 Hz and just under Nyquist
+    pub fn clamp_to_nyquist(self, sample_rate: u32) -> Self {
+        let highest = Hz::nyquist(sample_rate).hz * (1.0 - f32::EPSILON);
+        Hz::new(self.hz.clamp(0.0, highest))
     }
+}
 
-    pub fn dec(&mut self) {
-        self.hz -= 1.0;
+impl KHz {
+    pub fn new(khz: f32) -> Self {
+        KHz { khz }
     }
 
-    pub fn add_khz(&mut self, khz: KHz) {
-        self.hz += khz * 1000.0;
+    pub fn to_hz(self) -> Hz {
+        self.into()
     }
+}
 
-    pub fn sub_khz(&mut self, khz: KHz) {
-        self.hz -= khz * 1000.0;
+impl From<f32> for Hz {
+    fn from(hz: f32) -> Self {
+        Hz::new(hz)
     }
+}
 
-    pub fn mul_khz(&mut self, khz: KHz) {
-        self.hz *= khz * 1000.0;
+impl From<KHz> for Hz {
+    fn from(khz: KHz) -> Self {
+        Hz::new(khz.khz * 1000.0)
     }
+}
 
-    pub fn div_khz(&mut self, khz: KHz) {
-        self.hz /= khz * 1000.0;
+impl From<Hz> for f32 {
+    fn from(hz: Hz) -> Self {
+        hz.hz
     }
+}
 
-    pub fn rem_khz(&mut self, khz: KHz) {
-        self.hz %= khz * 1000.0;
+impl From<f32> for KHz {
+    fn from(khz: f32) -> Self {
+        KHz::new(khz)
     }
+}
 
-    pub fn neg_khz(&mut self) {
-        self.hz = -self.hz;
+impl From<Hz> for KHz {
+    fn from(hz: Hz) -> Self {
+        KHz::new(hz.hz / 1000.0)
     }
+}
 
-    pub fn inc_khz(&mut self) {
-        self.hz += 1.0 * 1000.0;
+impl From<KHz> for f32 {
+    fn from(khz: KHz) -> Self {
+        khz.khz
     }
+}
 
-    pub fn dec_khz(&mut self) {
-        self.hz -= 1.0 * 1000.0;
-    }
+// Adding or taking away any frequency, `Hz::new(440.0) + KHz::new(1.0)` is 1440 Hz
+impl<T: Into<Hz>> Add<T> for Hz {
+    type Output = Hz;
 
-    pub fn set_khz(&mut self, khz: KHz) {
-        self.hz = khz * 1000.0;
+    fn add(self, other: T) -> Hz {
+        Hz::new(self.hz + other.into().hz)
     }
 }
 
-impl Default for KHz {
-    fn default() -> Self {
-        0.432
+impl<T: Into<Hz>> Sub<T> for Hz {
+    type Output = Hz;
+
+    fn sub(self, other: T) -> Hz {
+        Hz::new(self.hz - other.into().hz)
     }
 }
 
-impl KHz {
-    pub fn new(khz: KHz) -> Self {
-        KHz { khz }
+impl<T: Into<Hz>> AddAssign<T> for Hz {
+    fn add_assign(&mut self, other: T) {
+        self.hz += other.into().hz;
     }
+}
 
-    pub fn add(&mut self, khz: KHz) {
-        self.khz += khz;
+impl<T: Into<Hz>> SubAssign<T> for Hz {
+    fn sub_assign(&mut self, other: T) {
+        self.hz -= other.into().hz;
     }
+}
 
-    pub fn sub(&mut self, khz: KHz) {
-        self.khz -= khz;
-    }
+impl Mul<f32> for Hz {
+    type Output = Hz;
 
-    pub fn mul(&mut self, khz: KHz) {
-        self.khz *= khz;
+    fn mul(self, factor: f32) -> Hz {
+        Hz::new(self.hz * factor)
     }
+}
 
-    pub fn div(&mut self, khz: KHz) {
-        self.khz /= khz;
-    }
+impl Div<f32> for Hz {
+    type Output = Hz;
 
-    pub fn rem(&mut self, khz: KHz) {
-        self.khz %= khz;
+    fn div(self, divisor: f32) -> Hz {
+        Hz::new(self.hz / divisor)
     }
+}
 
-    pub fn neg(&mut self) {
-        self.khz = -self.khz;
-    }
+// Ratio between two frequencies, 2.0 for an octave up
+impl Div<Hz> for Hz {
+    type Output = f32;
 
-    pub fn inc(&mut self) {
-        self.khz += 1.0;
+    fn div(self, other: Hz) -> f32 {
+        self.hz / other.hz
     }
+}
 
-    pub fn dec(&mut self) {
-        self.khz -= 1.0;
-    }
+impl Neg for Hz {
+    type Output = Hz;
 
-    pub fn add_hz(&mut self, hz: Hz) {
-        self.khz += hz / 1000.0;
+    fn neg(self) -> Hz {
+        Hz::new(-self.hz)
     }
+}
+
+impl<T: Into<KHz>> Add<T> for KHz {
+    type Output = KHz;
 
-    pub fn sub_hz(&mut self, hz: Hz) {
-        self.khz -= hz / 1000.0;
+    fn add(self, other: T) -> KHz {
+        KHz::new(self.khz + other.into().khz)
     }
+}
 
-    pub fn mul_hz(&mut self, hz: Hz) {
-        self.khz *= hz / 1000.0;
+impl<T: Into<KHz>> Sub<T> for KHz {
+    type Output = KHz;
+
+    fn sub(self, other: T) -> KHz {
+        KHz::new(self.khz - other.into().khz)
     }
+}
 
-    pub fn div_hz(&mut self, hz: Hz) {
-        self.khz /= hz / 1000.0;
+impl<T: Into<KHz>> AddAssign<T> for KHz {
+    fn add_assign(&mut self, other: T) {
+        self.khz += other.into().khz;
     }
+}
 
-    pub fn rem_hz(&mut self, hz: Hz) {
-        self.khz %= hz / 1000.0;
+impl<T: Into<KHz>> SubAssign<T> for KHz {
+    fn sub_assign(&mut self, other: T) {
+        self.khz -= other.into().khz;
     }
+}
 
-    pub fn neg_hz(&mut self) {
-        self.khz = -self.khz;
+impl Mul<f32> for KHz {
+    type Output = KHz;
+
+    fn mul(self, factor: f32) -> KHz {
+        KHz::new(self.khz * factor)
     }
+}
+
+impl Div<f32> for KHz {
+    type Output = KHz;
 
-    pub fn inc_hz(&mut self) {
-        self.khz += 1.0 / 1000.0;
+    fn div(self, divisor: f32) -> KHz {
+        KHz::new(self.khz / divisor)
     }
+}
+
+impl Div<KHz> for KHz {
+    type Output = f32;
 
-    pub fn dec_hz(&mut self) {
-        self.khz -= 1.0 / 1000.0;
+    fn div(self, other: KHz) -> f32 {
+        self.khz / other.khz
     }
+}
 
-    pub fn set(&mut self, khz: KHz) {
-        self.khz = khz;
+impl Neg for KHz {
+    type Output = KHz;
+
+    fn neg(self) -> KHz {
+        KHz::new(-self.khz)
     }
+}
 
-    pub fn to_hz(&self) -> Hz {
-        self.khz * 1000.0
+impl fmt::Display for Hz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} Hz", self.hz)
     }
+}
 
-    pub fn set_hz(&mut self, hz: Hz) {
-        self.khz = hz / 1000.0;
+impl fmt::Display for KHz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} kHz", self.khz)
     }
 }
@@ -1 +1,4 @@
+pub mod hertz;
+pub mod pitch;
 pub mod rhythm;
+pub mod theory;
@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+pub mod pitch;
+pub mod scala;
+pub mod tuning;
@@ -0,0 +1,296 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::types::hertz::Hz;
+
+// Concert pitch, 432.0 is the usual alternative
+pub const A4: f32 = 440.0;
+pub const A4_MIDI: i32 = 69;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Letter {
+    C,
+    D,
+    E,
+    F,
+    G,
+    A,
+    B,
+}
+
+impl Letter {
+    // Semitones above C
+    pub fn semitone(&self) -> i32 {
+        match self {
+            Letter::C => 0,
+            Letter::D => 2,
+            Letter::E => 4,
+            Letter::F => 5,
+            Letter::G => 7,
+            Letter::A => 9,
+            Letter::B => 11,
+        }
+    }
+
+    pub fn name(&self) -> char {
+        match self {
+            Letter::C => 'C',
+            Letter::D => 'D',
+            Letter::E => 'E',
+            Letter::F => 'F',
+            Letter::G => 'G',
+            Letter::A => 'A',
+            Letter::B => 'B',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Letter> {
+        match c.to_ascii_uppercase() {
+            'C' => Some(Letter::C),
+            'D' => Some(Letter::D),
+            'E' => Some(Letter::E),
+            'F' => Some(Letter::F),
+            'G' => Some(Letter::G),
+            'A' => Some(Letter::A),
+            'B' => Some(Letter::B),
+            _ => None,
+        }
+    }
+}
+
+// A spelled note with an octave, C4 is middle C and MIDI note 60. `cents`
+// detunes it, `nearest` leaves the deviation there.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Pitch {
+    pub letter: Letter,
+    // Sharps above zero and flats below, up to two of either
+    pub accidental: i8,
+    pub octave: i32,
+    pub cents: f32,
+}
+
+impl Default for Pitch {
+    fn default() -> Self {
+        Pitch::new(Letter::A, 0, 4)
+    }
+}
+
+impl Pitch {
+    pub fn new(letter: Letter, accidental: i8, octave: i32) -> Self {
+        Pitch {
+            letter,
+            accidental: accidental.clamp(-2, 2),
+            octave,
+            cents: 0.0,
+        }
+    }
+
+    pub fn with_cents(self, cents: f32) -> Self {
+        Pitch { cents, ..self }
+    }
+
+    // Spelled with sharps
+    pub fn from_midi(note: i32) -> Self {
+        const SHARPS: [(Letter, i8); 12] = [
+            (Letter::C, 0),
+            (Letter::C, 1),
+            (Letter::D, 0),
+            (Letter::D, 1),
+            (Letter::E, 0),
+            (Letter::F, 0),
+            (Letter::F, 1),
+            (Letter::G, 0),
+            (Letter::G, 1),
+            (Letter::A, 0),
+            (Letter::A, 1),
+            (Letter::B, 0),
+        ];
+
+        let (letter, accidental) = SHARPS[note.rem_euclid(12) as usize];
+        Pitch::new(letter, accidental, note.div_euclid(12) - 1)
+    }
+
+    // Spelled with flats
+    pub fn from_midi_flat(note: i32) -> Self {
+        let pitch = Pitch::from_midi(note);
+        match pitch.accidental {
+            0 => pitch,
+            _ => Pitch::from_midi(note + 1).flattened(),
+        }
+    }
+
+    fn flattened(self) -> Self {
+        Pitch {
+            accidental: self.accidental - 1,
+            ..self
+        }
+    }
+
+    // MIDI note number, which may fall outside 0 to 127. B#3 is 60 like C4.
+    pub fn midi(&self) -> i32 {
+        (self.octave + 1) * 12 + self.letter.semitone() + self.accidental as i32
+    }
+
+    pub fn midi_note(&self) -> Option<u8> {
+        u8::try_from(self.midi()).ok().filter(|n| *n <= 127)
+    }
+
+    // MIDI note with the cents as a fraction
+    pub fn midi_fractional(&self) -> f32 {
+        self.midi() as f32 + self.cents / 100.0
+    }
+
+    pub fn is_enharmonic(&self, other: &Pitch) -> bool {
+        self.midi() == other.midi() && (self.cents - other.cents).abs() < 1e-3
+    }
+
+    // Moves by `semitones`, respelled with sharps
+    pub fn transpose(&self, semitones: i32) -> Self {
+        Pitch::from_midi(self.midi() + semitones).with_cents(self.cents)
+    }
+
+    // Frequency in twelve-tone equal temperament with A4 at `a4`
+    pub fn frequency<A: Into<Hz>>(&self, a4: A) -> Hz {
+        midi_to_hz(self.midi_fractional(), a4)
+    }
+
+    // Closest note to `frequency`, `cents` holding how far off it is. None
+    // unless both frequencies are finite and above 0 Hz.
+    pub fn nearest<F: Into<Hz>, A: Into<Hz>>(frequency: F, a4: A) -> Option<Self> {
+        let (frequency, a4) = (frequency.into(), a4.into());
+        if !(frequency.hz.is_finite() && frequency.hz > 0.0 && a4.hz.is_finite() && a4.hz > 0.0) {
+            return None;
+        }
+
+        let note = hz_to_midi(frequency, a4);
+        let nearest = note.round();
+        Some(Pitch::from_midi(nearest as i32).with_cents((note - nearest) * 100.0))
+    }
+}
+
+pub fn midi_to_hz<A: Into<Hz>>(note: f32, a4: A) -> Hz {
+    a4.into() * 2f32.powf((note - A4_MIDI as f32) / 12.0)
+}
+
+// Fractional MIDI note of a frequency
+pub fn hz_to_midi<F: Into<Hz>, A: Into<Hz>>(frequency: F, a4: A) -> f32 {
+    A4_MIDI as f32 + 12.0 * (frequency.into() / a4.into()).log2()
+}
+
+// Cents from `from` up to `to`
+pub fn cents_between<F: Into<Hz>, T: Into<Hz>>(from: F, to: T) -> f32 {
+    1200.0 * (to.into() / from.into()).log2()
+}
+
+// "C#4", "Bb-1" or "A4+12c", cents are left out when there are none
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let accidental = match self.accidental {
+            -2 => "bb",
+            -1 => "b",
+            1 => "#",
+            2 => "##",
+            _ => "",
+        };
+        write!(f, "{}{}{}", self.letter.name(), accidental, self.octave)?;
+
+        if self.cents.abs() >= 0.005 {
+            write!(f, "{:+}c", (self.cents * 100.0).round() / 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+// Takes `#`, `♯`, `x` for sharps, `b` and `♭` for flats
+impl FromStr for Pitch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let mut chars = s.char_indices().peekable();
+
+        let letter = chars
+            .next()
+            .and_then(|(_, c)| Letter::from_char(c))
+            .ok_or_else(|| anyhow!("{} does not start with a note letter", s))?;
+
+        let mut accidental = 0i8;
+        while let Some((_, c)) = chars.peek() {
+            accidental += match c {
+                '#' | '♯' => 1,
+                'x' => 2,
+                'b' | '♭' => -1,
+                _ => break,
+            };
+            chars.next();
+            // Checked as it goes, a long run would overflow the count
+            if !(-2..=2).contains(&accidental) {
+                return Err(anyhow!("too many accidentals in {}", s));
+            }
+        }
+
+        let rest = chars.peek().map_or("", |(i, _)| &s[*i..]);
+        // A sign after the first octave digit starts the cents
+        let split = rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| *c == '+' || *c == '-')
+            .map_or(rest.len(), |(i, _)| i);
+        let (octave, cents) = rest.split_at(split);
+
+        let octave = octave
+            .parse::<i32>()
+            .map_err(|_| anyhow!("missing or invalid octave in {}", s))?;
+        let cents = match cents.strip_suffix('c') {
+            Some(cents) => cents
+                .parse::<f32>()
+                .map_err(|_| anyhow!("invalid cents in {}", s))?,
+            None if cents.is_empty() => 0.0,
+            None => return Err(anyhow!("unexpected {} in {}", cents, s)),
+        };
+
+        Ok(Pitch::new(letter, accidental, octave).with_cents(cents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_keeps_the_deviation_in_cents() {
+        let pitch = Pitch::nearest(445.0, A4).unwrap();
+        assert_eq!(pitch.to_string(), "A4+19.56c");
+        assert_eq!(Pitch::nearest(261.63, A4).unwrap().midi(), 60);
+    }
+
+    #[test]
+    fn nearest_rejects_frequencies_without_a_note() {
+        for hz in [0.0, -440.0, f32::NAN, f32::INFINITY] {
+            assert!(Pitch::nearest(hz, A4).is_none(), "{}", hz);
+        }
+        assert!(Pitch::nearest(440.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn parses_negative_octaves_double_sharps_and_cents() {
+        let pitch: Pitch = "Bb-1".parse().unwrap();
+        assert_eq!(
+            (pitch.letter, pitch.accidental, pitch.octave, pitch.cents),
+            (Letter::B, -1, -1, 0.0)
+        );
+        assert_eq!(pitch.midi(), 10);
+
+        let pitch: Pitch = "C##4+12c".parse().unwrap();
+        assert_eq!(
+            (pitch.letter, pitch.accidental, pitch.octave, pitch.cents),
+            (Letter::C, 2, 4, 12.0)
+        );
+        assert_eq!(pitch.midi(), 62);
+
+        assert!("C###4".parse::<Pitch>().is_err());
+        assert!("H4".parse::<Pitch>().is_err());
+    }
+}
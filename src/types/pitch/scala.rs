@@ -0,0 +1,329 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use super::pitch::{A4, A4_MIDI};
+
+// Scale degrees in cents above the tonic. The last degree is the period the
+// scale repeats at, usually the 1200 cent octave.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    pub degrees: Vec<f64>,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::equal(12, 1200.0)
+    }
+}
+
+impl Scale {
+    pub fn new(description: &str, degrees: Vec<f64>) -> Self {
+        Scale {
+            description: description.to_string(),
+            degrees,
+        }
+    }
+
+    // `divisions` equal steps of `period` cents
+    pub fn equal(divisions: u32, period: f64) -> Self {
+        let divisions = divisions.max(1);
+        let degrees = (1..=divisions)
+            .map(|step| period * step as f64 / divisions as f64)
+            .collect();
+        Scale::new(&format!("{} equal divisions", divisions), degrees)
+    }
+
+    // Degrees given as frequency ratios, e.g. 3/2
+    pub fn from_ratios(description: &str, ratios: &[(u64, u64)]) -> Self {
+        let degrees = ratios.iter().map(|(n, d)| ratio_to_cents(*n, *d)).collect();
+        Scale::new(description, degrees)
+    }
+
+    pub fn len(&self) -> usize {
+        self.degrees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.degrees.is_empty()
+    }
+
+    pub fn period(&self) -> f64 {
+        self.degrees.last().copied().unwrap_or(1200.0)
+    }
+
+    // Cents above the tonic of any degree, counting on through later periods
+    // and back through earlier ones
+    pub fn cents(&self, degree: i64) -> f64 {
+        let size = self.len().max(1) as i64;
+        let (periods, step) = (degree.div_euclid(size), degree.rem_euclid(size));
+
+        let within = match step {
+            0 => 0.0,
+            step => self.degrees[step as usize - 1],
+        };
+        periods as f64 * self.period() + within
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse()
+    }
+}
+
+pub fn ratio_to_cents(numerator: u64, denominator: u64) -> f64 {
+    1200.0 * (numerator as f64 / denominator.max(1) as f64).log2()
+}
+
+// Lines of a Scala file without its `!` comments
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .filter(|l| !l.trim_start().starts_with('!'))
+}
+
+// Scala .scl: a description, the number of degrees, then one degree per line.
+// Degrees with a dot are cents, anything else a ratio such as 5/4 or 2.
+impl std::str::FromStr for Scale {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut lines = lines(text);
+        let description = lines
+            .next()
+            .ok_or_else(|| anyhow!("empty scale file"))?
+            .trim();
+        let count = lines
+            .next()
+            .and_then(|l| l.split_whitespace().next())
+            .and_then(|l| l.parse::<usize>().ok())
+            .ok_or_else(|| anyhow!("missing degree count"))?;
+
+        let degrees = lines
+            .filter_map(|l| l.split_whitespace().next())
+            .take(count)
+            .map(parse_degree)
+            .collect::<Result<Vec<f64>>>()?;
+
+        if degrees.len() != count {
+            return Err(anyhow!(
+                "expected {} degrees, found {}",
+                count,
+                degrees.len()
+            ));
+        }
+        Ok(Scale::new(description, degrees))
+    }
+}
+
+fn parse_degree(degree: &str) -> Result<f64> {
+    let invalid = || anyhow!("invalid scale degree {}", degree);
+
+    if degree.contains('.') {
+        return degree.parse::<f64>().map_err(|_| invalid());
+    }
+
+    let (numerator, denominator) = degree.split_once('/').unwrap_or((degree, "1"));
+    let numerator = numerator.parse::<u64>().map_err(|_| invalid())?;
+    let denominator = denominator.parse::<u64>().map_err(|_| invalid())?;
+    if numerator == 0 || denominator == 0 {
+        return Err(invalid());
+    }
+    Ok(ratio_to_cents(numerator, denominator))
+}
+
+// Which scale degree each MIDI key plays, after Scala's .kbm format. An
+// empty `keys` maps every key to the next degree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    pub first: u8,
+    pub last: u8,
+    // Key that plays the tonic
+    pub middle: i32,
+    pub reference_note: i32,
+    pub reference_frequency: f64,
+    // Degree the pattern repeats at, 0 uses the scale's size
+    pub octave_degree: usize,
+    // Degree per key of the repeating pattern, `None` leaves the key silent
+    pub keys: Vec<Option<usize>>,
+}
+
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        KeyboardMapping::linear(60, A4_MIDI, A4 as f64)
+    }
+}
+
+impl KeyboardMapping {
+    pub fn linear(middle: i32, reference_note: i32, reference_frequency: f64) -> Self {
+        KeyboardMapping {
+            first: 0,
+            last: 127,
+            middle,
+            reference_note,
+            reference_frequency,
+            octave_degree: 0,
+            keys: Vec::new(),
+        }
+    }
+
+    // Scale degree of a key, `None` when it is unmapped
+    pub fn degree(&self, key: i32, scale_size: usize) -> Option<i64> {
+        let offset = (key - self.middle) as i64;
+        if self.keys.is_empty() {
+            return Some(offset);
+        }
+
+        let size = self.keys.len() as i64;
+        let octave = match self.octave_degree {
+            0 => scale_size,
+            degree => degree,
+        } as i64;
+
+        let degree = self.keys[offset.rem_euclid(size) as usize]?;
+        Some(offset.div_euclid(size) * octave + degree as i64)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse()
+    }
+}
+
+// Scala .kbm: map size, first and last key, middle key, reference key and its
+// frequency, the formal octave's degree, then one degree or `x` per key
+impl std::str::FromStr for KeyboardMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut values = lines(text).filter_map(|l| l.split_whitespace().next());
+        let mut next = |name: &str| {
+            values
+                .next()
+                .ok_or_else(|| anyhow!("keyboard mapping is missing its {}", name))
+        };
+
+        let integer = |value: &str, name: &str| {
+            value
+                .parse::<i32>()
+                .map_err(|_| anyhow!("invalid {} {}", name, value))
+        };
+
+        let size = integer(next("size")?, "size")?.max(0) as usize;
+        let first = integer(next("first key")?, "first key")?.clamp(0, 127) as u8;
+        let last = integer(next("last key")?, "last key")?.clamp(0, 127) as u8;
+        let middle = integer(next("middle key")?, "middle key")?;
+        let reference_note = integer(next("reference key")?, "reference key")?;
+        let frequency = next("reference frequency")?;
+        let reference_frequency = frequency
+            .parse::<f64>()
+            .ok()
+            .filter(|f| *f > 0.0)
+            .ok_or_else(|| anyhow!("invalid reference frequency {}", frequency))?;
+        let octave_degree = integer(next("octave degree")?, "octave degree")?.max(0) as usize;
+
+        let mut keys = Vec::with_capacity(size);
+        for _ in 0..size {
+            // Trailing keys may be left out, they are unmapped
+            match next("keys") {
+                Ok("x") | Err(_) => keys.push(None),
+                Ok(degree) => keys.push(Some(integer(degree, "degree")?.max(0) as usize)),
+            }
+        }
+
+        Ok(KeyboardMapping {
+            first,
+            last,
+            middle,
+            reference_note,
+            reference_frequency,
+            octave_degree,
+            keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEANTONE: &str = "! meanquar.scl
+!
+1/4-comma meantone scale. Pietro Aaron's temperament (1523)
+ 12
+!
+ 76.04900
+ 193.15686
+ 310.26471
+ 5/4
+ 503.42157
+ 579.47057
+ 696.57843
+ 25/16
+ 889.73529
+ 1006.84314
+ 1082.89214
+ 2/1
+";
+
+    const WHITE_KEYS: &str = "! white.kbm
+! size, first and last key, middle key, reference key and frequency
+12
+0
+127
+60
+69
+440.0
+! octave degree
+7
+! mapping
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+
+    #[test]
+    fn parses_a_scale_file() {
+        let scale: Scale = MEANTONE.parse().unwrap();
+        assert_eq!(
+            scale.description,
+            "1/4-comma meantone scale. Pietro Aaron's temperament (1523)"
+        );
+        assert_eq!(scale.len(), 12);
+        assert_eq!(scale.degrees[0], 76.049);
+        assert!((scale.degrees[3] - 386.3137).abs() < 1e-4);
+        assert_eq!(scale.period(), 1200.0);
+
+        assert!("short\n3\n100.0\n".parse::<Scale>().is_err());
+        assert!("bad\n1\n0/4\n".parse::<Scale>().is_err());
+    }
+
+    #[test]
+    fn parses_a_keyboard_mapping() {
+        let mapping: KeyboardMapping = WHITE_KEYS.parse().unwrap();
+        assert_eq!(
+            (
+                mapping.middle,
+                mapping.reference_note,
+                mapping.reference_frequency
+            ),
+            (60, 69, 440.0)
+        );
+        assert_eq!(mapping.octave_degree, 7);
+        assert_eq!(mapping.keys.len(), 12);
+        assert_eq!(mapping.keys[..3], [Some(0), None, Some(1)]);
+
+        // A above middle C is the sixth degree, the next C a period up
+        assert_eq!(mapping.degree(69, 7), Some(5));
+        assert_eq!(mapping.degree(72, 7), Some(7));
+        assert_eq!(mapping.degree(61, 7), None);
+    }
+}
@@ -0,0 +1,257 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::pitch::{A4, A4_MIDI};
+use super::scala::{KeyboardMapping, Scale};
+use crate::types::hertz::Hz;
+
+// Twelve-note well temperaments and their forerunners, in cents above C
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Temperament {
+    Pythagorean,
+    QuarterCommaMeantone,
+    WerckmeisterIII,
+    KirnbergerIII,
+    Vallotti,
+}
+
+impl Temperament {
+    pub fn cents(&self) -> [f64; 12] {
+        match self {
+            Temperament::Pythagorean => [
+                0.0, 90.225, 203.910, 294.135, 407.820, 498.045, 611.730, 701.955, 792.180,
+                905.865, 996.090, 1109.775,
+            ],
+            Temperament::QuarterCommaMeantone => [
+                0.0, 76.049, 193.157, 310.265, 386.314, 503.422, 579.471, 696.578, 772.627,
+                889.735, 1006.843, 1082.892,
+            ],
+            Temperament::WerckmeisterIII => [
+                0.0, 90.225, 192.180, 294.135, 390.225, 498.045, 588.270, 696.090, 792.180,
+                888.270, 996.090, 1092.180,
+            ],
+            Temperament::KirnbergerIII => [
+                0.0, 90.225, 193.157, 294.135, 386.314, 498.045, 590.224, 696.578, 792.180,
+                889.735, 996.090, 1088.269,
+            ],
+            Temperament::Vallotti => [
+                0.0, 94.135, 196.090, 298.045, 392.180, 501.955, 592.180, 698.045, 796.090,
+                894.135, 1000.000, 1090.225,
+            ],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Temperament::Pythagorean => "Pythagorean",
+            Temperament::QuarterCommaMeantone => "1/4-comma meantone",
+            Temperament::WerckmeisterIII => "Werckmeister III",
+            Temperament::KirnbergerIII => "Kirnberger III",
+            Temperament::Vallotti => "Vallotti",
+        }
+    }
+}
+
+// 5-limit just intonation over twelve notes
+const JUST_RATIOS: [(u64, u64); 12] = [
+    (16, 15),
+    (9, 8),
+    (6, 5),
+    (5, 4),
+    (4, 3),
+    (45, 32),
+    (3, 2),
+    (8, 5),
+    (5, 3),
+    (9, 5),
+    (15, 8),
+    (2, 1),
+];
+
+// A scale laid over the MIDI keys. Frequencies are worked out up front so
+// instruments can look them up on the audio thread.
+#[derive(Clone)]
+pub struct Tuning {
+    pub name: String,
+    scale: Scale,
+    mapping: KeyboardMapping,
+    frequencies: Box<[Option<f32>; 128]>,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::equal(12, A4)
+    }
+}
+
+impl Tuning {
+    pub fn new(scale: Scale, mapping: KeyboardMapping) -> Self {
+        let mut tuning = Tuning {
+            name: scale.description.clone(),
+            scale,
+            mapping,
+            frequencies: Box::new([None; 128]),
+        };
+        tuning.retune();
+        tuning
+    }
+
+    // `divisions` equal steps to the octave, A4 on `a4`
+    pub fn equal<A: Into<Hz>>(divisions: u32, a4: A) -> Self {
+        let mapping = KeyboardMapping::linear(A4_MIDI, A4_MIDI, a4.into().hz as f64);
+        Tuning::new(Scale::equal(divisions, 1200.0), mapping)
+    }
+
+    // Just intonation on `tonic` (0 for C, 2 for D...), A4 stays on `a4`
+    pub fn just<A: Into<Hz>>(tonic: u8, a4: A) -> Self {
+        let scale = Scale::from_ratios("5-limit just intonation", &JUST_RATIOS);
+        Tuning::twelve(scale, tonic, a4)
+    }
+
+    pub fn temperament<A: Into<Hz>>(temperament: Temperament, tonic: u8, a4: A) -> Self {
+        let cents = temperament.cents();
+        let mut degrees: Vec<f64> = cents[1..].to_vec();
+        degrees.push(1200.0);
+
+        Tuning::twelve(Scale::new(temperament.name(), degrees), tonic, a4)
+    }
+
+    // Twelve-note scale with its tonic on middle C's octave
+    fn twelve<A: Into<Hz>>(scale: Scale, tonic: u8, a4: A) -> Self {
+        let middle = 60 + (tonic % 12) as i32;
+        Tuning::new(
+            scale,
+            KeyboardMapping::linear(middle, A4_MIDI, a4.into().hz as f64),
+        )
+    }
+
+    // Scala scale, with a .kbm keyboard mapping or the scale's degrees on
+    // consecutive keys from middle C and A4 at 440 Hz
+    pub fn from_scala<P: AsRef<Path>>(scl: P, kbm: Option<P>) -> Result<Self> {
+        let scale = Scale::load(scl)?;
+        let mapping = match kbm {
+            Some(kbm) => KeyboardMapping::load(kbm)?,
+            None => KeyboardMapping::default(),
+        };
+        Ok(Tuning::new(scale, mapping))
+    }
+
+    pub fn scale(&self) -> &Scale {
+        &self.scale
+    }
+
+    pub fn mapping(&self) -> &KeyboardMapping {
+        &self.mapping
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+        self.retune();
+    }
+
+    pub fn set_mapping(&mut self, mapping: KeyboardMapping) {
+        self.mapping = mapping;
+        self.retune();
+    }
+
+    // Moves the reference key, e.g. to 432 Hz
+    pub fn set_reference<F: Into<Hz>>(&mut self, note: i32, frequency: F) {
+        self.mapping.reference_note = note;
+        self.mapping.reference_frequency = frequency.into().hz as f64;
+        self.retune();
+    }
+
+    fn retune(&mut self) {
+        let mapping = &self.mapping;
+        let size = self.scale.len();
+
+        // An unmapped reference key still sets the pitch, as if mapped linearly
+        let reference = mapping
+            .degree(mapping.reference_note, size)
+            .unwrap_or((mapping.reference_note - mapping.middle) as i64);
+        let reference_cents = self.scale.cents(reference);
+
+        for (key, frequency) in self.frequencies.iter_mut().enumerate() {
+            let key = key as i32;
+            let mapped = key >= mapping.first as i32 && key <= mapping.last as i32;
+
+            *frequency = mapped
+                .then(|| mapping.degree(key, size))
+                .flatten()
+                .filter(|_| !self.scale.is_empty())
+                .map(|degree| {
+                    let cents = self.scale.cents(degree) - reference_cents;
+                    (mapping.reference_frequency * 2f64.powf(cents / 1200.0)) as f32
+                });
+        }
+    }
+
+    // Frequency of a MIDI key, `None` for keys the mapping leaves silent
+    pub fn frequency(&self, note: u8) -> Option<Hz> {
+        self.frequencies
+            .get(note as usize)
+            .copied()
+            .flatten()
+            .map(Hz::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hz(tuning: &Tuning, note: u8) -> f32 {
+        (tuning.frequency(note).unwrap().hz * 100.0).round() / 100.0
+    }
+
+    #[test]
+    fn equal_divisions_step_from_a4() {
+        let tuning = Tuning::equal(12, 440.0);
+        assert_eq!(
+            (hz(&tuning, 69), hz(&tuning, 60), hz(&tuning, 81)),
+            (440.0, 261.63, 880.0)
+        );
+
+        // 19 steps to the octave, a step is about 63 cents
+        let tuning = Tuning::equal(19, 440.0);
+        assert_eq!((hz(&tuning, 70), hz(&tuning, 88)), (456.35, 880.0));
+    }
+
+    #[test]
+    fn temperaments_keep_a4_and_shape_the_rest() {
+        let tuning = Tuning::temperament(Temperament::QuarterCommaMeantone, 0, 440.0);
+        assert_eq!(hz(&tuning, 69), 440.0);
+        // A pure major third above C
+        let third = tuning.frequency(64).unwrap().hz / tuning.frequency(60).unwrap().hz;
+        assert!((third - 1.25).abs() < 1e-4);
+
+        let tuning = Tuning::just(0, 440.0);
+        assert_eq!(
+            (hz(&tuning, 60), hz(&tuning, 64), hz(&tuning, 67)),
+            (264.0, 330.0, 396.0)
+        );
+    }
+
+    #[test]
+    fn scala_files_tune_the_mapped_keys() {
+        let scale: Scale = "Pentatonic\n5\n9/8\n5/4\n3/2\n5/3\n2/1\n".parse().unwrap();
+        let mapping: KeyboardMapping =
+            "12\n0\n127\n60\n60\n261.0\n5\n0\nx\n1\nx\n2\nx\nx\n3\nx\n4\nx\nx\n"
+                .parse()
+                .unwrap();
+        let tuning = Tuning::new(scale, mapping);
+
+        assert_eq!(
+            (
+                hz(&tuning, 60),
+                hz(&tuning, 62),
+                hz(&tuning, 67),
+                hz(&tuning, 72)
+            ),
+            (261.0, 293.63, 391.5, 522.0)
+        );
+        assert_eq!(hz(&tuning, 57), 217.5);
+        assert!(tuning.frequency(61).is_none());
+    }
+}
@@ -1,2 +1,3 @@
+pub mod position;
 pub mod tempo;
 pub mod time_signature;
@@ -0,0 +1,376 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::types::rhythm::tempo::tempo::Tempo;
+use crate::types::rhythm::time_signature::time_signature::TimeSignatures;
+
+// Ticks per quarter note
+pub const DEFAULT_PPQ: u32 = 960;
+
+// Bar, beat and tick, zero-based and shown one-based as "1.2.240". Beats count
+// in the meter's note value, ticks run at `ppq` per quarter note.
+#[derive(Clone, Copy, Debug)]
+pub struct MusicalPosition {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+    pub ppq: u32,
+    pub time_signature: TimeSignatures,
+}
+
+impl Default for MusicalPosition {
+    fn default() -> Self {
+        MusicalPosition {
+            bar: 0,
+            beat: 0,
+            tick: 0,
+            ppq: DEFAULT_PPQ,
+            time_signature: TimeSignatures::default(),
+        }
+    }
+}
+
+impl MusicalPosition {
+    // Carries overflowing ticks and beats over
+    pub fn new(bar: u32, beat: u32, tick: u32) -> Self {
+        MusicalPosition {
+            bar,
+            beat,
+            tick,
+            ..Default::default()
+        }
+        .normalized()
+    }
+
+    pub fn with_ppq(self, ppq: u32) -> Self {
+        MusicalPosition::from_ticks(self.total_ticks_at(ppq.max(1)), ppq, self.time_signature)
+    }
+
+    pub fn with_time_signature(self, time_signature: TimeSignatures) -> Self {
+        MusicalPosition::from_ticks(self.total_ticks(), self.ppq, time_signature)
+    }
+
+    pub fn ticks_per_beat(&self) -> u64 {
+        (self.ppq.max(1) as u64 * 4 / self.time_signature.note() as u64).max(1)
+    }
+
+    pub fn ticks_per_bar(&self) -> u64 {
+        self.ticks_per_beat() * self.time_signature.beats() as u64
+    }
+
+    pub fn total_ticks(&self) -> u64 {
+        self.bar as u64 * self.ticks_per_bar()
+            + self.beat as u64 * self.ticks_per_beat()
+            + self.tick as u64
+    }
+
+    // Total ticks at another resolution, rounded to the nearest
+    fn total_ticks_at(&self, ppq: u32) -> u64 {
+        let ticks = self.total_ticks() as u128 * ppq as u128;
+        let own = self.ppq.max(1) as u128;
+        ((ticks + own / 2) / own) as u64
+    }
+
+    pub fn from_ticks(ticks: u64, ppq: u32, time_signature: TimeSignatures) -> Self {
+        let mut position = MusicalPosition {
+            ppq: ppq.max(1),
+            time_signature,
+            ..Default::default()
+        };
+
+        let (per_bar, per_beat) = (position.ticks_per_bar(), position.ticks_per_beat());
+        position.bar = (ticks / per_bar) as u32;
+        position.beat = (ticks % per_bar / per_beat) as u32;
+        position.tick = (ticks % per_beat) as u32;
+        position
+    }
+
+    fn normalized(self) -> Self {
+        MusicalPosition::from_ticks(self.total_ticks(), self.ppq, self.time_signature)
+    }
+
+    // Quarter notes from the start, in this position's own meter
+    pub fn beats(&self) -> f64 {
+        self.total_ticks() as f64 / self.ppq.max(1) as f64
+    }
+
+    pub fn from_beats(beats: f64, ppq: u32, time_signature: TimeSignatures) -> Self {
+        let ticks = (beats.max(0.0) * ppq.max(1) as f64).round() as u64;
+        MusicalPosition::from_ticks(ticks, ppq, time_signature)
+    }
+
+    // Position of a quarter note count through the tempo map's meter changes,
+    // in the meter of its bar
+    pub fn from_beats_in(beats: f64, tempo: &Tempo, ppq: u32) -> Self {
+        let (bar, beat, tick) = tempo.beats_to_bbt(beats, ppq.max(1));
+
+        MusicalPosition {
+            bar,
+            beat,
+            tick,
+            ppq: ppq.max(1),
+            time_signature: tempo.time_signature_at(bar as f32),
+        }
+    }
+
+    // Quarter notes from the start through the tempo map's meter changes.
+    // Unlike `beats`, every bar counts in the meter the map gives it.
+    pub fn beats_in(&self, tempo: &Tempo) -> f64 {
+        tempo.bbt_to_beats(self.bar, self.beat, self.tick, self.ppq.max(1))
+    }
+
+    // Orders positions taken from different meters of one song
+    pub fn cmp_in(&self, other: &MusicalPosition, tempo: &Tempo) -> Ordering {
+        self.beats_in(tempo).total_cmp(&other.beats_in(tempo))
+    }
+
+    // Moves by a length across meter changes, in the meter of the bar it lands in
+    pub fn offset_in(self, length: MusicalPosition, tempo: &Tempo) -> Self {
+        MusicalPosition::from_beats_in(self.beats_in(tempo) + length.beats(), tempo, self.ppq)
+    }
+
+    // Position of a sample through the tempo map, in the meter of its bar
+    pub fn from_samples(sample: u64, sample_rate: u32, tempo: &Tempo, ppq: u32) -> Self {
+        let beats = tempo.sample_to_beats(sample as f64, sample_rate);
+        MusicalPosition::from_beats_in(beats, tempo, ppq)
+    }
+
+    // Nearest sample through the tempo map. Converting back with
+    // `from_samples` gives this position again as long as a tick lasts at
+    // least a sample.
+    pub fn to_samples(&self, tempo: &Tempo, sample_rate: u32) -> u64 {
+        tempo
+            .beats_to_sample(self.beats_in(tempo), sample_rate)
+            .round() as u64
+    }
+}
+
+impl PartialEq for MusicalPosition {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+// Compares the time from the start whatever the resolutions, as long as both
+// are in one meter. Bars of different meters only line up through the tempo
+// map, see `cmp_in`.
+impl PartialOrd for MusicalPosition {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.time_signature != other.time_signature {
+            return None;
+        }
+
+        let left = self.total_ticks() as u128 * other.ppq.max(1) as u128;
+        let right = other.total_ticks() as u128 * self.ppq.max(1) as u128;
+        Some(left.cmp(&right))
+    }
+}
+
+// Adds a length counted in its own meter. Keeps the left hand side's
+// resolution and meter, so the result is only right while no meter change is
+// crossed; `offset_in` follows the tempo map.
+impl Add for MusicalPosition {
+    type Output = MusicalPosition;
+
+    fn add(self, other: MusicalPosition) -> MusicalPosition {
+        let ticks = self.total_ticks() + other.total_ticks_at(self.ppq);
+        MusicalPosition::from_ticks(ticks, self.ppq, self.time_signature)
+    }
+}
+
+// Takes a length off, stopping at zero. The same meter caveat as `Add` applies.
+impl Sub for MusicalPosition {
+    type Output = MusicalPosition;
+
+    fn sub(self, other: MusicalPosition) -> MusicalPosition {
+        let ticks = self
+            .total_ticks()
+            .saturating_sub(other.total_ticks_at(self.ppq));
+        MusicalPosition::from_ticks(ticks, self.ppq, self.time_signature)
+    }
+}
+
+impl AddAssign for MusicalPosition {
+    fn add_assign(&mut self, other: MusicalPosition) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for MusicalPosition {
+    fn sub_assign(&mut self, other: MusicalPosition) {
+        *self = *self - other;
+    }
+}
+
+impl fmt::Display for MusicalPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.bar + 1, self.beat + 1, self.tick)
+    }
+}
+
+// Parses "bar.beat.tick" as displayed, the tick may be left out
+impl FromStr for MusicalPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = s.trim().split('.').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(anyhow!("expected bar.beat.tick, got {}", s));
+        }
+
+        let number = |part: &str| {
+            part.parse::<u32>()
+                .map_err(|_| anyhow!("invalid number {} in {}", part, s))
+        };
+        let bar = number(parts[0])?;
+        let beat = number(parts[1])?;
+        let tick = parts.get(2).map(|p| number(p)).transpose()?.unwrap_or(0);
+
+        if bar == 0 || beat == 0 {
+            return Err(anyhow!("bars and beats count from 1 in {}", s));
+        }
+        Ok(MusicalPosition::new(bar - 1, beat - 1, tick))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::rhythm::tempo::tempo::TempoShape;
+
+    fn meter(beats: u8, note: u8) -> TimeSignatures {
+        TimeSignatures::new(beats, note).unwrap()
+    }
+
+    // 4/4 at 120, a ramp to 150 at bar 2, 3/4 from bar 4, 7/8 from bar 6 and
+    // a jump to 90 at bar 7
+    fn tempo_map() -> Tempo {
+        let mut tempo = Tempo::default();
+        tempo.set_bpm(120.0);
+        tempo.add_tempo_change(2.0, 150.0, TempoShape::Linear);
+        tempo.add_meter_change(4, meter(3, 4));
+        tempo.add_meter_change(6, meter(7, 8));
+        tempo.add_tempo_change(7.0, 90.0, TempoShape::Jump);
+        tempo
+    }
+
+    #[test]
+    fn displays_and_parses_one_based() {
+        let position: MusicalPosition = "1.2.240".parse().unwrap();
+        assert_eq!((position.bar, position.beat, position.tick), (0, 1, 240));
+        assert_eq!(position.to_string(), "1.2.240");
+
+        let short: MusicalPosition = "3.4".parse().unwrap();
+        assert_eq!(short.to_string(), "3.4.0");
+
+        // Overflowing ticks carry into the next beat
+        let carried: MusicalPosition = "1.1.1000".parse().unwrap();
+        assert_eq!(carried.to_string(), "1.2.40");
+
+        for invalid in ["", "1", "0.1.0", "1.0.0", "1.2.3.4", "a.b"] {
+            assert!(invalid.parse::<MusicalPosition>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn adds_and_subtracts_lengths() {
+        let position = MusicalPosition::new(0, 3, 480);
+        let beat = MusicalPosition::new(0, 1, 0);
+
+        assert_eq!((position + beat).to_string(), "2.1.480");
+        assert_eq!((position - beat).to_string(), "1.3.480");
+        assert_eq!((beat - position).to_string(), "1.1.0");
+
+        let mut moved = position;
+        moved += MusicalPosition::new(1, 0, 480);
+        assert_eq!(moved.to_string(), "3.1.0");
+        moved -= MusicalPosition::new(2, 0, 0);
+        assert_eq!(moved.to_string(), "1.1.0");
+
+        // Lengths at another resolution are converted first
+        let half = MusicalPosition::new(0, 0, 480).with_ppq(480);
+        assert_eq!((position + half).to_string(), "2.1.0");
+    }
+
+    #[test]
+    fn with_ppq_rounds_to_the_nearest_tick() {
+        let position = MusicalPosition::new(1, 2, 3);
+        let coarse = position.with_ppq(480);
+        assert_eq!(
+            (coarse.bar, coarse.beat, coarse.tick, coarse.ppq),
+            (1, 2, 2, 480)
+        );
+        assert_eq!(MusicalPosition::new(0, 0, 2).with_ppq(480).tick, 1);
+        assert_eq!(MusicalPosition::new(0, 0, 1).with_ppq(480).tick, 1);
+        assert_eq!(MusicalPosition::new(0, 0, 719).with_ppq(480).tick, 360);
+
+        // Finer resolutions are exact and compare equal
+        let fine = position.with_ppq(1920);
+        assert_eq!(fine.tick, 6);
+        assert_eq!(fine, position);
+    }
+
+    #[test]
+    fn refuses_to_order_mixed_meters() {
+        let tempo = tempo_map();
+        let last_of_four = MusicalPosition::new(3, 3, 0);
+        let first_of_three = MusicalPosition::from_samples(
+            MusicalPosition::new(4, 0, 0).to_samples(&tempo, 48000),
+            48000,
+            &tempo,
+            DEFAULT_PPQ,
+        );
+        assert_eq!(first_of_three.to_string(), "5.1.0");
+        assert_eq!(first_of_three.time_signature, meter(3, 4));
+
+        // Counted in 3/4 throughout, bar 5 would come before bar 4 beat 4
+        assert_eq!(last_of_four.partial_cmp(&first_of_three), None);
+        assert_ne!(last_of_four, first_of_three);
+        assert_eq!(last_of_four.cmp_in(&first_of_three, &tempo), Ordering::Less);
+        assert_eq!(first_of_three.beats_in(&tempo), 16.0);
+
+        let crossed = last_of_four.offset_in(MusicalPosition::new(0, 2, 0), &tempo);
+        assert_eq!(crossed.to_string(), "5.2.0");
+        assert_eq!(crossed.time_signature, meter(3, 4));
+    }
+
+    #[test]
+    fn samples_round_trip_across_ramps_and_meter_changes() {
+        let tempo = tempo_map();
+        let sample_rate = 48000;
+
+        for bar in 0..9 {
+            let time_signature = tempo.time_signature_at(bar as f32);
+            for beat in 0..time_signature.beats() as u32 {
+                for tick in [0, 1, 239, 480] {
+                    let position = MusicalPosition {
+                        bar,
+                        beat,
+                        tick,
+                        ppq: DEFAULT_PPQ,
+                        time_signature,
+                    };
+                    if tick as u64 >= position.ticks_per_beat() {
+                        continue;
+                    }
+
+                    let sample = position.to_samples(&tempo, sample_rate);
+                    let back =
+                        MusicalPosition::from_samples(sample, sample_rate, &tempo, DEFAULT_PPQ);
+                    assert_eq!(back, position, "{} came back as {}", position, back);
+                    assert_eq!(back.to_samples(&tempo, sample_rate), sample);
+                }
+            }
+        }
+
+        // Later positions land on later samples
+        let samples: Vec<u64> = (0..9)
+            .map(|bar| MusicalPosition::new(bar, 0, 0).to_samples(&tempo, sample_rate))
+            .collect();
+        assert!(samples.windows(2).all(|w| w[0] < w[1]));
+    }
+}
@@ -1 +1 @@
-type Bar = f32;
+pub type Bar = f32;
@@ -1,6 +1,6 @@
 use crate::types::rhythm::tempo::bpm::beat::Beat;
-use std::time;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct BPM {
     pub bpm: Beat,
 }
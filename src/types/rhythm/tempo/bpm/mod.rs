@@ -1,3 +1,4 @@
 pub mod bar;
 pub mod beat;
+#[allow(clippy::module_inception)]
 pub mod bpm;
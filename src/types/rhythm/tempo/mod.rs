@@ -1,2 +1,3 @@
 pub mod bpm;
+#[allow(clippy::module_inception)]
 pub mod tempo;
@@ -1,22 +1,107 @@
 use crate::types::rhythm::tempo::bpm::bpm::BPM;
-use crate::types::rhythm::time_signature::time_signature::TimeSignatures;
+use crate::types::rhythm::time_signature::time_signature::{Accent, TimeSignatures};
 
 use std::time::Duration;
 
+use super::bpm::bar::Bar;
 use super::bpm::beat::Beat;
 
-pub struct Tempo {
-    pub bpm: BPM,
+// How the tempo moves from a change to the next one
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum TempoShape {
+    // Holds, so the next change is an instant jump
+    #[default]
+    Jump,
+    // BPM rises or falls by the same amount every beat
+    Linear,
+    // The curved ramp, BPM changes by the same ratio every beat
+    Exponential,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TempoChange {
+    pub bar: Bar,
+    pub bpm: f32,
+    pub shape: TempoShape,
+}
+
+// Meters change on whole bars
+#[derive(Clone, Copy)]
+pub struct MeterChange {
+    pub bar: u32,
     pub time_signature: TimeSignatures,
 }
 
-impl Default for Tempo {
-    fn default() -> Self {
-        Tempo {
-            bpm: Default::default(),
-            time_signature: TimeSignatures::default(),
+// A stretch of constant or ramping tempo, positions in quarter notes
+struct Segment {
+    beat: f64,
+    length: f64,
+    from: f64,
+    to: f64,
+    shape: TempoShape,
+}
+
+impl Segment {
+    // Seconds to play `beats` into the segment
+    fn seconds(&self, beats: f64) -> f64 {
+        let (from, to, length) = (self.from, self.to, self.length);
+
+        match self.shape {
+            _ if (to - from).abs() < 1e-9 || !length.is_finite() => 60.0 * beats / from,
+            TempoShape::Jump => 60.0 * beats / from,
+            TempoShape::Linear => {
+                let slope = (to - from) / length;
+                60.0 / slope * ((from + slope * beats) / from).ln()
+            }
+            TempoShape::Exponential => {
+                let rate = (to / from).ln() / length;
+                60.0 / (from * rate) * (1.0 - (-rate * beats).exp())
+            }
         }
     }
+
+    // Inverse of `seconds`
+    fn beats(&self, seconds: f64) -> f64 {
+        let (from, to, length) = (self.from, self.to, self.length);
+
+        match self.shape {
+            _ if (to - from).abs() < 1e-9 || !length.is_finite() => seconds * from / 60.0,
+            TempoShape::Jump => seconds * from / 60.0,
+            TempoShape::Linear => {
+                let slope = (to - from) / length;
+                from * ((seconds * slope / 60.0).exp() - 1.0) / slope
+            }
+            TempoShape::Exponential => {
+                let rate = (to / from).ln() / length;
+                -(1.0 - seconds * from * rate / 60.0).ln() / rate
+            }
+        }
+    }
+
+    fn bpm(&self, beats: f64) -> f64 {
+        let t = if self.length.is_finite() && self.length > 0.0 {
+            (beats / self.length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        match self.shape {
+            TempoShape::Jump => self.from,
+            TempoShape::Linear => self.from + (self.to - self.from) * t,
+            TempoShape::Exponential => self.from * (self.to / self.from).powf(t),
+        }
+    }
+}
+
+// Tempo map of a song. `bpm` and `time_signature` apply from the start until
+// the first change. Beats are quarter notes throughout, so a bar of 6/8 is
+// three beats long.
+#[derive(Clone, Default)]
+pub struct Tempo {
+    pub bpm: BPM,
+    pub time_signature: TimeSignatures,
+    tempo_changes: Vec<TempoChange>,
+    meter_changes: Vec<MeterChange>,
 }
 
 impl Tempo {
@@ -28,14 +113,15 @@ impl Tempo {
             (Some(bpm), Some(time_signature)) => Tempo {
                 bpm: BPM::new(bpm),
                 time_signature,
+                ..Default::default()
             },
             (Some(bpm), None) => Tempo {
                 bpm: BPM::new(bpm),
-                time_signature: TimeSignatures::default(),
+                ..Default::default()
             },
             (None, Some(time_signature)) => Tempo {
-                bpm: Default::default(),
-                time_signature: time_signature,
+                time_signature,
+                ..Default::default()
             },
             (None, None) => Tempo::default(),
         }
@@ -49,24 +135,448 @@ impl Tempo {
         self.time_signature = time_signature;
     }
 
-    pub fn beats_over_time(&self, time: Duration) -> Beat {
-        let seconds = time.as_secs_f32();
-        let beats_per_second = self.bpm.bpm / 60.0;
+    // Adds a tempo change, replacing one on the same bar
+    pub fn add_tempo_change(&mut self, bar: Bar, bpm: f32, shape: TempoShape) {
+        let change = TempoChange {
+            bar: bar.max(0.0),
+            bpm: bpm.max(1.0),
+            shape,
+        };
+
+        self.tempo_changes.retain(|c| c.bar != change.bar);
+        let index = self.tempo_changes.partition_point(|c| c.bar < change.bar);
+        self.tempo_changes.insert(index, change);
+    }
+
+    pub fn remove_tempo_change(&mut self, index: usize) -> Option<TempoChange> {
+        (index < self.tempo_changes.len()).then(|| self.tempo_changes.remove(index))
+    }
+
+    pub fn tempo_changes(&self) -> &[TempoChange] {
+        &self.tempo_changes
+    }
+
+    // Adds a meter change, replacing one on the same bar
+    pub fn add_meter_change(&mut self, bar: u32, time_signature: TimeSignatures) {
+        self.meter_changes.retain(|m| m.bar != bar);
+        let index = self.meter_changes.partition_point(|m| m.bar < bar);
+        self.meter_changes.insert(
+            index,
+            MeterChange {
+                bar,
+                time_signature,
+            },
+        );
+    }
+
+    pub fn remove_meter_change(&mut self, bar: u32) -> Option<MeterChange> {
+        let index = self.meter_changes.iter().position(|m| m.bar == bar)?;
+        Some(self.meter_changes.remove(index))
+    }
+
+    pub fn meter_changes(&self) -> &[MeterChange] {
+        &self.meter_changes
+    }
+
+    pub fn clear_changes(&mut self) {
+        self.tempo_changes.clear();
+        self.meter_changes.clear();
+    }
+
+    // Opens `bars` at `at`, moving later changes back. A ramp across `at`
+    // stretches over the gap.
+    pub fn insert_time(&mut self, at: Bar, bars: Bar) {
+        if bars <= 0.0 {
+            return;
+        }
+
+        for change in &mut self.tempo_changes {
+            if change.bar >= at {
+                change.bar += bars;
+            }
+        }
+        for change in &mut self.meter_changes {
+            if change.bar as Bar >= at {
+                change.bar = (change.bar as Bar + bars).round() as u32;
+            }
+        }
+    }
+
+    // Cuts `start..end` out. What was in effect at `end` starts at `start`.
+    pub fn delete_time(&mut self, start: Bar, end: Bar) {
+        if end <= start {
+            return;
+        }
+
+        let length = end - start;
+        let inside = |bar: Bar| bar >= start && bar < end;
+        let tempo = self.tempo_changes.iter().rfind(|c| inside(c.bar)).copied();
+        self.tempo_changes.retain(|c| !inside(c.bar));
+        for change in &mut self.tempo_changes {
+            if change.bar >= end {
+                change.bar -= length;
+            }
+        }
+        if let Some(change) = tempo.filter(|_| self.tempo_changes.iter().all(|c| c.bar != start)) {
+            self.add_tempo_change(start, change.bpm, change.shape);
+        }
+
+        let (first, last) = (start.round() as u32, end.round() as u32);
+        let meter = self
+            .meter_changes
+            .iter()
+            .rfind(|m| m.bar >= first && m.bar < last)
+            .copied();
+        self.meter_changes
+            .retain(|m| m.bar < first || m.bar >= last);
+        for change in &mut self.meter_changes {
+            if change.bar >= last {
+                change.bar -= last - first;
+            }
+        }
+        if let Some(change) = meter.filter(|_| self.meter_changes.iter().all(|m| m.bar != first)) {
+            self.add_meter_change(first, change.time_signature);
+        }
+    }
+
+    // Repeats the changes in `start..end` right after it, restating the tempo
+    // and meter at `start` where the copy begins
+    pub fn duplicate_time(&mut self, start: Bar, end: Bar) {
+        if end <= start {
+            return;
+        }
+
+        let length = end - start;
+        let bpm = self.bpm_at(self.bar_to_beat(start as f64));
+        let time_signature = self.time_signature_at(start);
+        let tempos: Vec<TempoChange> = self
+            .tempo_changes
+            .iter()
+            .filter(|c| c.bar >= start && c.bar < end)
+            .copied()
+            .collect();
+
+        let (first, last) = (start.round() as u32, end.round() as u32);
+        let meters: Vec<MeterChange> = self
+            .meter_changes
+            .iter()
+            .filter(|m| m.bar >= first && m.bar < last)
+            .copied()
+            .collect();
+
+        self.insert_time(end, length);
+
+        if !self.tempo_changes.is_empty() && tempos.first().is_none_or(|c| c.bar != start) {
+            self.add_tempo_change(end, bpm, TempoShape::Jump);
+        }
+        for change in tempos {
+            self.add_tempo_change(change.bar + length, change.bpm, change.shape);
+        }
+
+        if !self.meter_changes.is_empty() && meters.first().is_none_or(|m| m.bar != first) {
+            self.add_meter_change(last, time_signature);
+        }
+        for change in meters {
+            self.add_meter_change(change.bar + last - first, change.time_signature);
+        }
+    }
+
+    // A meter change on bar 0 replaces `time_signature`
+    fn initial_meter(&self) -> TimeSignatures {
+        self.meter_changes
+            .first()
+            .filter(|m| m.bar == 0)
+            .map(|m| m.time_signature)
+            .unwrap_or(self.time_signature)
+    }
+
+    // (first bar, first beat, beats per bar, meter) for every stretch of one meter
+    fn meters(&self) -> impl Iterator<Item = (u32, f64, f64, TimeSignatures)> + '_ {
+        let initial = self.initial_meter();
+        let first = (0, 0.0, initial.quarter_notes() as f64, initial);
+
+        let mut current = first;
+        std::iter::once(first).chain(self.meter_changes.iter().filter(|m| m.bar > 0).map(
+            move |m| {
+                let beat = current.1 + (m.bar - current.0) as f64 * current.2;
+                current = (
+                    m.bar,
+                    beat,
+                    m.time_signature.quarter_notes() as f64,
+                    m.time_signature,
+                );
+                current
+            },
+        ))
+    }
+
+    fn meter_at_bar(&self, bar: f64) -> (u32, f64, f64, TimeSignatures) {
+        self.meters()
+            .take_while(|m| m.0 == 0 || m.0 as f64 <= bar)
+            .last()
+            .unwrap()
+    }
+
+    fn meter_at_beat(&self, beats: f64) -> (u32, f64, f64, TimeSignatures) {
+        self.meters()
+            .take_while(|m| m.0 == 0 || m.1 <= beats)
+            .last()
+            .unwrap()
+    }
+
+    pub fn time_signature_at(&self, bar: Bar) -> TimeSignatures {
+        self.meter_at_bar(bar as f64).3
+    }
+
+    pub fn bars_to_beats(&self, bars: Bar) -> Beat {
+        self.bar_to_beat(bars as f64) as Beat
+    }
+
+    pub fn beats_to_bars(&self, beats: Beat) -> Bar {
+        self.beat_to_bar(beats as f64) as Bar
+    }
+
+    pub fn bar_to_beat(&self, bar: f64) -> f64 {
+        let (first, beat, per_bar, _) = self.meter_at_bar(bar);
+        beat + (bar - first as f64) * per_bar
+    }
+
+    pub fn beat_to_bar(&self, beats: f64) -> f64 {
+        let (first, beat, per_bar, _) = self.meter_at_beat(beats);
+        first as f64 + (beats - beat) / per_bar
+    }
+
+    // Zero-based (bar, beat in the bar, tick in the beat). Beats count in the
+    // meter's own note value and ticks at `ppq` per quarter note.
+    pub fn beats_to_bbt(&self, beats: f64, ppq: u32) -> (u32, u32, u32) {
+        let (first, start, per_bar, time_signature) = self.meter_at_beat(beats.max(0.0));
+        let beat_length = time_signature.beat_length() as f64;
+        let ticks_per_beat = (ppq as f64 * beat_length).round().max(1.0) as u64;
+
+        let ticks = ((beats.max(0.0) - start) * ppq as f64).round() as u64;
+        let ticks_per_bar = (per_bar * ppq as f64).round().max(1.0) as u64;
+
+        let bar = first as u64 + ticks / ticks_per_bar;
+        let in_bar = ticks % ticks_per_bar;
+        (
+            bar as u32,
+            (in_bar / ticks_per_beat) as u32,
+            (in_bar % ticks_per_beat) as u32,
+        )
+    }
+
+    pub fn bbt_to_beats(&self, bar: u32, beat: u32, tick: u32, ppq: u32) -> f64 {
+        let time_signature = self.time_signature_at(bar as Bar);
+        let beat_length = time_signature.beat_length() as f64;
+
+        self.bar_to_beat(bar as f64) + beat as f64 * beat_length + tick as f64 / ppq.max(1) as f64
+    }
+
+    // First beat of the meter at or after `beats`, with its accent. Pulses
+    // come in the meter's note value, so 7/8 pulses every eighth.
+    pub fn next_pulse(&self, beats: f64) -> (f64, Accent) {
+        let beats = beats.max(0.0);
+        let (_, start, _, time_signature) = self.meter_at_beat(beats);
+        let length = time_signature.beat_length() as f64;
+
+        // Meters change on bar lines, which are always pulses of the old meter
+        let index = ((beats - start) / length - 1e-9).ceil().max(0.0) as u64;
+        let accent = time_signature.accent((index % time_signature.beats() as u64) as u32);
+        (start + index as f64 * length, accent)
+    }
+
+    // Every pulse from `from` up to `to`
+    pub fn pulses(&self, from: f64, to: f64) -> impl Iterator<Item = (f64, Accent)> + '_ {
+        std::iter::successors(Some(self.next_pulse(from)), move |(beat, _)| {
+            Some(self.next_pulse(beat + 1e-6))
+        })
+        .take_while(move |(beat, _)| *beat < to)
+    }
+
+    // First group start at or after `beats`, a bar line counts as one
+    pub fn next_group(&self, beats: f64) -> f64 {
+        let mut pulse = self.next_pulse(beats);
+        while pulse.1 == Accent::Beat {
+            pulse = self.next_pulse(pulse.0 + 1e-6);
+        }
+        pulse.0
+    }
+
+    // (beat, bpm, shape) where the tempo changes, starting on beat 0
+    fn points(&self) -> impl Iterator<Item = (f64, f64, TempoShape)> + '_ {
+        let initial = self
+            .tempo_changes
+            .first()
+            .is_none_or(|c| c.bar > 0.0)
+            .then(|| (0.0, self.bpm.bpm.max(1.0) as f64, TempoShape::Jump));
+
+        initial.into_iter().chain(
+            self.tempo_changes
+                .iter()
+                .map(|c| (self.bar_to_beat(c.bar as f64), c.bpm as f64, c.shape)),
+        )
+    }
+
+    // Tempo stretches in order, the last one runs forever
+    fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        let mut points = self.points().peekable();
+
+        std::iter::from_fn(move || {
+            let (beat, from, shape) = points.next()?;
+            let next = points.peek().copied();
+
+            Some(Segment {
+                beat,
+                length: next.map(|n| n.0 - beat).unwrap_or(f64::INFINITY),
+                from,
+                to: match (shape, next) {
+                    (TempoShape::Jump, _) | (_, None) => from,
+                    (_, Some(next)) => next.1,
+                },
+                shape,
+            })
+        })
+    }
+
+    // Walks the map with the seconds elapsed before each segment, without
+    // allocating, until `f` returns Some
+    fn walk<T>(&self, mut f: impl FnMut(&Segment, f64) -> Option<T>) -> Option<T> {
+        let mut seconds = 0.0;
+
+        for segment in self.segments() {
+            if let Some(result) = f(&segment, seconds) {
+                return Some(result);
+            }
+            seconds += segment.seconds(segment.length);
+        }
+        None
+    }
+
+    pub fn bpm_at(&self, beats: f64) -> f32 {
+        self.walk(|segment, _| {
+            (beats < segment.beat + segment.length)
+                .then(|| segment.bpm(beats.max(0.0) - segment.beat) as f32)
+        })
+        .unwrap_or(self.bpm.bpm)
+    }
+
+    // Exact seconds from the start of the song, integrating across ramps
+    pub fn beats_to_seconds(&self, beats: f64) -> f64 {
+        let beats = beats.max(0.0);
+        self.walk(|segment, seconds| {
+            (beats < segment.beat + segment.length)
+                .then(|| seconds + segment.seconds(beats - segment.beat))
+        })
+        .unwrap_or(0.0)
+    }
 
-        seconds / beats_per_second
+    pub fn seconds_to_beats(&self, seconds: f64) -> f64 {
+        let seconds = seconds.max(0.0);
+        self.walk(|segment, start| {
+            let end = start + segment.seconds(segment.length);
+            (seconds < end).then(|| segment.beat + segment.beats(seconds - start))
+        })
+        .unwrap_or(0.0)
+    }
+
+    // Beats played in `time` from the start of the song
+    pub fn beats_over_time(&self, time: Duration) -> Beat {
+        self.seconds_to_beats(time.as_secs_f64()) as Beat
     }
 
     pub fn beats_to_time(&self, beats: Beat) -> Duration {
-        let seconds = beats * 60.0 / self.bpm.bpm;
-        Duration::from_secs_f32(seconds)
+        Duration::from_secs_f64(self.beats_to_seconds(beats as f64))
+    }
+
+    pub fn bars_to_time(&self, bars: Bar) -> Duration {
+        Duration::from_secs_f64(self.beats_to_seconds(self.bar_to_beat(bars as f64)))
+    }
+
+    pub fn beats_to_sample(&self, beats: f64, sample_rate: u32) -> f64 {
+        self.beats_to_seconds(beats) * sample_rate as f64
+    }
+
+    pub fn sample_to_beats(&self, sample: f64, sample_rate: u32) -> f64 {
+        self.seconds_to_beats(sample / sample_rate.max(1) as f64)
     }
 
-    pub fn bars_to_beats(&self, bars: f32) -> f32 {
-        bars * self.time_signature.beats() as f32
+    pub fn beats_to_samples(&self, beats: Beat, sample_rate: u32) -> u64 {
+        self.beats_to_sample(beats as f64, sample_rate).round() as u64
+    }
+
+    pub fn samples_to_beats(&self, samples: u64, sample_rate: u32) -> Beat {
+        self.sample_to_beats(samples as f64, sample_rate) as Beat
+    }
+
+    pub fn bars_to_samples(&self, bars: Bar, sample_rate: u32) -> u64 {
+        self.beats_to_sample(self.bar_to_beat(bars as f64), sample_rate)
+            .round() as u64
+    }
+
+    pub fn samples_to_bars(&self, samples: u64, sample_rate: u32) -> Bar {
+        self.beat_to_bar(self.sample_to_beats(samples as f64, sample_rate)) as Bar
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (bar, bpm) and (bar, quarter notes per bar) of every change
+    type Changes = (Vec<(Bar, f32)>, Vec<(u32, f32)>);
+
+    fn changes(tempo: &Tempo) -> Changes {
+        (
+            tempo
+                .tempo_changes()
+                .iter()
+                .map(|c| (c.bar, c.bpm))
+                .collect(),
+            tempo
+                .meter_changes()
+                .iter()
+                .map(|m| (m.bar, m.time_signature.quarter_notes()))
+                .collect(),
+        )
+    }
+
+    fn song() -> Tempo {
+        let mut tempo = Tempo::new(120.0, TimeSignatures::FourFour);
+        tempo.add_tempo_change(2.0, 90.0, TempoShape::Jump);
+        tempo.add_meter_change(3, TimeSignatures::ThreeFour);
+        tempo
+    }
+
+    #[test]
+    fn inserting_time_moves_later_changes() {
+        let mut tempo = song();
+        tempo.insert_time(1.0, 2.0);
+        assert_eq!(changes(&tempo), (vec![(4.0, 90.0)], vec![(5, 3.0)]));
+        // The gap is in the meter it opens in
+        assert_eq!(tempo.bar_to_beat(3.0) - tempo.bar_to_beat(1.0), 8.0);
+    }
+
+    #[test]
+    fn deleting_time_keeps_what_was_in_effect_at_its_end() {
+        let mut tempo = song();
+        tempo.delete_time(1.0, 4.0);
+        assert_eq!(changes(&tempo), (vec![(1.0, 90.0)], vec![(1, 3.0)]));
+
+        let mut tempo = song();
+        tempo.delete_time(3.0, 4.0);
+        assert_eq!(changes(&tempo), (vec![(2.0, 90.0)], vec![(3, 3.0)]));
     }
 
-    pub fn bars_to_time(&self, bars: f32) -> Duration {
-        let beats = self.bars_to_beats(bars);
-        self.beats_to_time(beats)
+    #[test]
+    fn duplicating_time_copies_and_restates_changes() {
+        let mut tempo = song();
+        tempo.duplicate_time(1.0, 3.0);
+        assert_eq!(
+            changes(&tempo),
+            (
+                vec![(2.0, 90.0), (3.0, 120.0), (4.0, 90.0)],
+                vec![(3, 4.0), (5, 3.0)]
+            )
+        );
     }
 }
@@ -1,48 +1,281 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+// Most groups a bar can be split into
+pub const MAX_GROUPS: usize = 16;
+
+// How strongly a beat of the bar is played
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Accent {
+    // First beat of the bar
+    Downbeat,
+    // First beat of a later group, the 3 and 5 of 2+2+3/8
+    Group,
+    Beat,
+}
+
+// Any meter. `groups` split the beats of a bar into the additive groups of
+// 2+2+3/8 and set where the accents fall.
+#[derive(Clone, Copy, Debug)]
 pub struct TimeSignature {
-    name: &'static str,
-    signature: &'static str,
-    beats: u8,
-    note: u8,
+    pub beats: u8,
+    pub note: u8,
+    groups: [u8; MAX_GROUPS],
+    group_count: u8,
 }
 
+impl PartialEq for TimeSignature {
+    fn eq(&self, other: &Self) -> bool {
+        self.beats == other.beats && self.note == other.note && self.groups() == other.groups()
+    }
+}
+
+impl TimeSignature {
+    // Compound meters such as 6/8 and 12/8 are grouped in threes, anything
+    // else is a single group
+    pub fn new(beats: u8, note: u8) -> anyhow::Result<Self> {
+        if beats == 0 {
+            return Err(anyhow!("a bar needs at least one beat"));
+        }
+        if !note.is_power_of_two() || note > 64 {
+            return Err(anyhow!("invalid note value {}", note));
+        }
+
+        let mut time_signature = TimeSignature {
+            beats,
+            note,
+            groups: [0; MAX_GROUPS],
+            group_count: 0,
+        };
+
+        let compound =
+            note >= 8 && beats > 3 && beats.is_multiple_of(3) && beats / 3 <= MAX_GROUPS as u8;
+        if compound {
+            time_signature.set_groups(&[3; MAX_GROUPS][..beats as usize / 3]);
+        } else {
+            time_signature.set_groups(&[beats]);
+        }
+        Ok(time_signature)
+    }
+
+    // Meter made of the groups, 2+2+3/8 is `additive(&[2, 2, 3], 8)`
+    pub fn additive(groups: &[u8], note: u8) -> anyhow::Result<Self> {
+        let beats = groups.iter().map(|g| *g as u32).sum::<u32>();
+        if beats > u8::MAX as u32 {
+            return Err(anyhow!("too many beats in a bar: {}", beats));
+        }
+
+        let mut time_signature = TimeSignature::new(beats as u8, note)?;
+        time_signature.with_groups(groups)?;
+        Ok(time_signature)
+    }
+
+    // Regroups the bar, the groups must add up to its beats
+    pub fn with_groups(&mut self, groups: &[u8]) -> anyhow::Result<()> {
+        if groups.is_empty() || groups.len() > MAX_GROUPS {
+            return Err(anyhow!("a bar takes 1 to {} groups", MAX_GROUPS));
+        }
+        if groups.contains(&0) {
+            return Err(anyhow!("empty group in {:?}", groups));
+        }
+        if groups.iter().map(|g| *g as u32).sum::<u32>() != self.beats as u32 {
+            return Err(anyhow!(
+                "groups {:?} don't add up to {} beats",
+                groups,
+                self.beats
+            ));
+        }
+
+        self.set_groups(groups);
+        Ok(())
+    }
+
+    fn set_groups(&mut self, groups: &[u8]) {
+        self.groups = [0; MAX_GROUPS];
+        self.groups[..groups.len()].copy_from_slice(groups);
+        self.group_count = groups.len() as u8;
+    }
+
+    pub fn groups(&self) -> &[u8] {
+        &self.groups[..self.group_count as usize]
+    }
+
+    // Beats of the bar that start a group, zero-based
+    pub fn group_starts(&self) -> impl Iterator<Item = u32> + '_ {
+        self.groups().iter().scan(0, |start, group| {
+            let beat = *start;
+            *start += *group as u32;
+            Some(beat)
+        })
+    }
+
+    pub fn is_additive(&self) -> bool {
+        self.group_count > 1
+    }
+
+    // Beats past the bar wrap into the next one
+    pub fn accent(&self, beat: u32) -> Accent {
+        let beat = beat % self.beats as u32;
+
+        if beat == 0 {
+            Accent::Downbeat
+        } else if self.group_starts().any(|start| start == beat) {
+            Accent::Group
+        } else {
+            Accent::Beat
+        }
+    }
+
+    // Length of a beat in quarter notes
+    pub fn beat_length(&self) -> f32 {
+        4.0 / self.note as f32
+    }
+
+    pub fn quarter_notes(&self) -> f32 {
+        self.beats as f32 * self.beat_length()
+    }
+}
+
+// Groupings are only spelled out when they aren't the meter's own
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let own = TimeSignature::new(self.beats, self.note).ok();
+        if own.is_some_and(|own| own.groups() != self.groups()) {
+            let groups: Vec<String> = self.groups().iter().map(|g| g.to_string()).collect();
+            write!(f, "{}/{}", groups.join("+"), self.note)
+        } else {
+            write!(f, "{}/{}", self.beats, self.note)
+        }
+    }
+}
+
+// "7/8", "2+2+3/8" or "(2+2+3)/8"
+impl FromStr for TimeSignature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (top, bottom) = s
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| anyhow!("expected beats/note, got {}", s))?;
+
+        let number = |part: &str| {
+            part.trim()
+                .parse::<u8>()
+                .map_err(|_| anyhow!("invalid number {} in {}", part.trim(), s))
+        };
+
+        let note = number(bottom)?;
+        let top = top.trim().trim_start_matches('(').trim_end_matches(')');
+        let groups = top
+            .split('+')
+            .map(number)
+            .collect::<anyhow::Result<Vec<u8>>>()?;
+
+        if groups.len() == 1 {
+            TimeSignature::new(groups[0], note)
+        } else {
+            TimeSignature::additive(&groups, note)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
 pub enum TimeSignatures {
     ShuffleTime,
+    #[default]
     CommonTime,
     CutTime,
     TwoTwo,
     TwoFour,
     ThreeFour,
     FourFour,
+    Custom(TimeSignature),
 }
 
-impl Default for TimeSignatures {
-    fn default() -> Self {
-        TimeSignatures::CommonTime
+// Meters are equal when they play the same, Common Time is 4/4
+impl PartialEq for TimeSignatures {
+    fn eq(&self, other: &Self) -> bool {
+        self.time_signature() == other.time_signature()
+    }
+}
+
+impl From<TimeSignature> for TimeSignatures {
+    // Named meters are kept when the grouping is their own
+    fn from(time_signature: TimeSignature) -> Self {
+        let named = [
+            TimeSignatures::FourFour,
+            TimeSignatures::ThreeFour,
+            TimeSignatures::TwoFour,
+            TimeSignatures::TwoTwo,
+            TimeSignatures::ShuffleTime,
+        ];
+
+        named
+            .into_iter()
+            .find(|named| named.time_signature() == time_signature)
+            .unwrap_or(TimeSignatures::Custom(time_signature))
     }
 }
 
 impl TimeSignatures {
+    pub fn new(beats: u8, note: u8) -> anyhow::Result<Self> {
+        Ok(TimeSignature::new(beats, note)?.into())
+    }
+
+    pub fn additive(groups: &[u8], note: u8) -> anyhow::Result<Self> {
+        Ok(TimeSignature::additive(groups, note)?.into())
+    }
+
+    pub fn time_signature(&self) -> TimeSignature {
+        let (beats, note) = match self {
+            TimeSignatures::Custom(time_signature) => return *time_signature,
+            TimeSignatures::ShuffleTime => (3, 8),
+            TimeSignatures::CommonTime | TimeSignatures::FourFour => (4, 4),
+            TimeSignatures::CutTime | TimeSignatures::TwoTwo => (2, 2),
+            TimeSignatures::TwoFour => (2, 4),
+            TimeSignatures::ThreeFour => (3, 4),
+        };
+        TimeSignature::new(beats, note).unwrap()
+    }
+
     pub fn beats(&self) -> u8 {
-        match self {
-            TimeSignatures::ShuffleTime | &TimeSignatures::ThreeFour => 3,
-            TimeSignatures::CommonTime | &TimeSignatures::FourFour => 4,
-            TimeSignatures::CutTime | TimeSignatures::TwoTwo | &TimeSignatures::TwoFour => 2,
-        }
+        self.time_signature().beats
     }
 
     pub fn note(&self) -> u8 {
-        match self {
-            TimeSignatures::ShuffleTime => 8,
-            TimeSignatures::CommonTime | TimeSignatures::CutTime | TimeSignatures::TwoTwo => 4,
-            TimeSignatures::TwoFour | TimeSignatures::ThreeFour | TimeSignatures::FourFour => 4,
-        }
+        self.time_signature().note
+    }
+
+    pub fn groups(&self) -> Vec<u8> {
+        self.time_signature().groups().to_vec()
+    }
+
+    pub fn accent(&self, beat: u32) -> Accent {
+        self.time_signature().accent(beat)
+    }
+
+    // Length of a beat in quarter notes
+    pub fn beat_length(&self) -> f32 {
+        self.time_signature().beat_length()
+    }
+
+    // Length of a bar in quarter notes
+    pub fn quarter_notes(&self) -> f32 {
+        self.time_signature().quarter_notes()
     }
 
     pub fn note_name(&self) -> &'static str {
-        match self {
-            TimeSignatures::ShuffleTime => "8th",
-            TimeSignatures::CommonTime | TimeSignatures::CutTime | TimeSignatures::TwoTwo => "4th",
-            TimeSignatures::TwoFour | TimeSignatures::ThreeFour | TimeSignatures::FourFour => "4th",
+        match self.note() {
+            1 => "1st",
+            2 => "2nd",
+            4 => "4th",
+            8 => "8th",
+            16 => "16th",
+            32 => "32nd",
+            _ => "64th",
         }
     }
 
@@ -55,6 +288,26 @@ impl TimeSignatures {
             TimeSignatures::TwoFour => "Two-Four",
             TimeSignatures::ThreeFour => "Three-Four",
             TimeSignatures::FourFour => "Four-Four",
+            TimeSignatures::Custom(_) => "Custom",
+        }
+    }
+}
+
+impl fmt::Display for TimeSignatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.time_signature())
+    }
+}
+
+// Also takes "C" for Common Time and "¢" for Cut Time
+impl FromStr for TimeSignatures {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim() {
+            "C" | "c" => Ok(TimeSignatures::CommonTime),
+            "¢" => Ok(TimeSignatures::CutTime),
+            s => Ok(s.parse::<TimeSignature>()?.into()),
         }
     }
 }
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use super::scale::{parse_pitch_class, pitch_class, pitch_class_name, Key};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ChordQuality {
+    #[default]
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Power,
+    Major6,
+    Minor6,
+    Dominant7,
+    Major7,
+    Minor7,
+    MinorMajor7,
+    HalfDiminished7,
+    Diminished7,
+    Dominant7Sus4,
+    Add9,
+    Dominant9,
+    Major9,
+    Minor9,
+}
+
+// In the order chords are tried when naming notes, simpler first
+const QUALITIES: [ChordQuality; 20] = [
+    ChordQuality::Major,
+    ChordQuality::Minor,
+    ChordQuality::Diminished,
+    ChordQuality::Augmented,
+    ChordQuality::Sus4,
+    ChordQuality::Sus2,
+    ChordQuality::Power,
+    ChordQuality::Dominant7,
+    ChordQuality::Major7,
+    ChordQuality::Minor7,
+    ChordQuality::HalfDiminished7,
+    ChordQuality::Diminished7,
+    ChordQuality::MinorMajor7,
+    ChordQuality::Major6,
+    ChordQuality::Minor6,
+    ChordQuality::Dominant7Sus4,
+    ChordQuality::Add9,
+    ChordQuality::Dominant9,
+    ChordQuality::Major9,
+    ChordQuality::Minor9,
+];
+
+impl ChordQuality {
+    // Semitones above the root, the ninth an octave up
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Diminished => &[0, 3, 6],
+            ChordQuality::Augmented => &[0, 4, 8],
+            ChordQuality::Sus2 => &[0, 2, 7],
+            ChordQuality::Sus4 => &[0, 5, 7],
+            ChordQuality::Power => &[0, 7],
+            ChordQuality::Major6 => &[0, 4, 7, 9],
+            ChordQuality::Minor6 => &[0, 3, 7, 9],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Major7 => &[0, 4, 7, 11],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+            ChordQuality::MinorMajor7 => &[0, 3, 7, 11],
+            ChordQuality::HalfDiminished7 => &[0, 3, 6, 10],
+            ChordQuality::Diminished7 => &[0, 3, 6, 9],
+            ChordQuality::Dominant7Sus4 => &[0, 5, 7, 10],
+            ChordQuality::Add9 => &[0, 4, 7, 14],
+            ChordQuality::Dominant9 => &[0, 4, 7, 10, 14],
+            ChordQuality::Major9 => &[0, 4, 7, 11, 14],
+            ChordQuality::Minor9 => &[0, 3, 7, 10, 14],
+        }
+    }
+
+    // Pitch classes above the root as bits
+    pub fn mask(&self) -> u16 {
+        self.intervals()
+            .iter()
+            .fold(0, |mask, i| mask | 1 << (i % 12))
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Diminished => "dim",
+            ChordQuality::Augmented => "aug",
+            ChordQuality::Sus2 => "sus2",
+            ChordQuality::Sus4 => "sus4",
+            ChordQuality::Power => "5",
+            ChordQuality::Major6 => "6",
+            ChordQuality::Minor6 => "m6",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Major7 => "maj7",
+            ChordQuality::Minor7 => "m7",
+            ChordQuality::MinorMajor7 => "m(maj7)",
+            ChordQuality::HalfDiminished7 => "m7b5",
+            ChordQuality::Diminished7 => "dim7",
+            ChordQuality::Dominant7Sus4 => "7sus4",
+            ChordQuality::Add9 => "add9",
+            ChordQuality::Dominant9 => "9",
+            ChordQuality::Major9 => "maj9",
+            ChordQuality::Minor9 => "m9",
+        }
+    }
+
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "M" | "maj" => Some(ChordQuality::Major),
+            "min" | "-" => Some(ChordQuality::Minor),
+            "°" | "o" => Some(ChordQuality::Diminished),
+            "+" => Some(ChordQuality::Augmented),
+            "sus" => Some(ChordQuality::Sus4),
+            "M7" | "Δ" | "Δ7" => Some(ChordQuality::Major7),
+            "min7" | "-7" => Some(ChordQuality::Minor7),
+            "ø" | "ø7" => Some(ChordQuality::HalfDiminished7),
+            "°7" | "o7" => Some(ChordQuality::Diminished7),
+            "mM7" => Some(ChordQuality::MinorMajor7),
+            suffix => QUALITIES.into_iter().find(|q| q.suffix() == suffix),
+        }
+    }
+}
+
+// Moves every pitch class in a mask up `semitones`
+fn rotate(mask: u16, semitones: u8) -> u16 {
+    let semitones = semitones % 12;
+    (mask << semitones | mask >> (12 - semitones)) & 0xfff
+}
+
+// A chord as pitch classes, `bass` set for slash chords such as C/E
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Chord {
+    pub root: u8,
+    pub quality: ChordQuality,
+    pub bass: Option<u8>,
+}
+
+impl Default for Chord {
+    fn default() -> Self {
+        Chord::new(0, ChordQuality::Major)
+    }
+}
+
+impl Chord {
+    pub fn new(root: u8, quality: ChordQuality) -> Self {
+        Chord {
+            root: root % 12,
+            quality,
+            bass: None,
+        }
+    }
+
+    // Bass on the root is no slash
+    pub fn with_bass(self, bass: u8) -> Self {
+        let bass = Some(bass % 12).filter(|b| *b != self.root);
+        Chord { bass, ..self }
+    }
+
+    pub fn mask(&self) -> u16 {
+        let mask = rotate(self.quality.mask(), self.root);
+        match self.bass {
+            Some(bass) => mask | 1 << bass,
+            None => mask,
+        }
+    }
+
+    // Pitch classes from the root up, then the bass when it is not a chord tone
+    pub fn pitch_classes(&self) -> Vec<u8> {
+        let mut classes: Vec<u8> = self
+            .quality
+            .intervals()
+            .iter()
+            .map(|i| (self.root + i) % 12)
+            .collect();
+        if let Some(bass) = self.bass.filter(|b| !classes.contains(b)) {
+            classes.push(bass);
+        }
+        classes
+    }
+
+    pub fn contains(&self, note: u8) -> bool {
+        self.mask() & 1 << (note % 12) != 0
+    }
+
+    // Root position voicing from the root nearest `around`, a slash chord's
+    // bass below it. Notes past the MIDI range are left out.
+    pub fn notes(&self, around: u8) -> Vec<u8> {
+        let offset = pitch_class(self.root as i32 - around as i32) as i32;
+        let offset = if offset > 6 { offset - 12 } else { offset };
+        let root = around as i32 + offset;
+
+        let mut notes: Vec<i32> = self
+            .quality
+            .intervals()
+            .iter()
+            .map(|i| root + *i as i32)
+            .collect();
+        if let Some(bass) = self.bass {
+            notes.insert(0, root - pitch_class(root - bass as i32) as i32);
+        }
+
+        notes
+            .into_iter()
+            .filter(|n| (0..=127).contains(n))
+            .map(|n| n as u8)
+            .collect()
+    }
+
+    // Nearest chord tone, ties go down
+    pub fn snap(&self, note: u8) -> u8 {
+        (0..=6)
+            .flat_map(|distance| [note as i32 - distance, note as i32 + distance])
+            .filter(|n| (0..=127).contains(n))
+            .find(|n| self.contains(*n as u8))
+            .unwrap_or(note as i32) as u8
+    }
+
+    // Names the chord the notes make. A chord on the lowest note wins,
+    // otherwise it is written over its bass, which may lie outside it as in D/C.
+    pub fn identify(notes: &[u8]) -> Option<Chord> {
+        let bass = *notes.iter().min()? % 12;
+        let mask = notes.iter().fold(0u16, |mask, n| mask | 1 << (n % 12));
+
+        let upper = notes.iter().filter(|n| *n % 12 != bass);
+        let upper = upper.fold(0u16, |mask, n| mask | 1 << (n % 12));
+
+        Chord::find(mask, bass)
+            .or_else(|| Chord::find(upper, bass))
+            .map(|chord| chord.with_bass(bass))
+    }
+
+    fn find(mask: u16, bass: u8) -> Option<Chord> {
+        let roots = std::iter::once(bass).chain((0..12).filter(|r| *r != bass));
+        for root in roots {
+            let intervals = rotate(mask, 12 - root);
+            if intervals & 1 == 0 {
+                continue;
+            }
+
+            if let Some(quality) = QUALITIES.into_iter().find(|q| q.mask() == intervals) {
+                return Some(Chord::new(root, quality));
+            }
+        }
+        None
+    }
+
+    // The chord stacked in thirds on a degree of `key`, 0 for the tonic. Only
+    // seven note scales stack in thirds.
+    pub fn in_key(key: &Key, degree: usize, sevenths: bool) -> Option<Chord> {
+        if key.scale.len() != 7 {
+            return None;
+        }
+
+        let tones = if sevenths { 4 } else { 3 };
+        let notes: Vec<u8> = (0..tones)
+            .map(|i| (key.tonic as i32 + key.scale.interval((degree + i * 2) as i32)) as u8)
+            .collect();
+        Chord::identify(&notes)
+    }
+
+    pub fn name(&self, flats: bool) -> String {
+        let mut name = pitch_class_name(self.root, flats) + self.quality.suffix();
+        if let Some(bass) = self.bass {
+            name = name + "/" + &pitch_class_name(bass, flats);
+        }
+        name
+    }
+}
+
+// "F#m7/E" or "Bb/D", roots spelled the usual way with F# the only sharp.
+// `name` picks the spelling, e.g. from the key.
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name([1, 3, 8, 10].contains(&self.root)))
+    }
+}
+
+impl FromStr for Chord {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let (root, rest) =
+            parse_pitch_class(s).ok_or_else(|| anyhow!("{} does not start with a note", s))?;
+
+        let (suffix, bass) = match rest.rsplit_once('/') {
+            Some((suffix, bass)) => {
+                let bass = parse_pitch_class(bass)
+                    .filter(|(_, rest)| rest.is_empty())
+                    .ok_or_else(|| anyhow!("invalid bass note {}", bass))?;
+                (suffix, Some(bass.0))
+            }
+            None => (rest, None),
+        };
+
+        let quality =
+            ChordQuality::from_suffix(suffix).ok_or_else(|| anyhow!("unknown chord {}", s))?;
+        let chord = Chord::new(root, quality);
+        Ok(match bass {
+            Some(bass) => chord.with_bass(bass),
+            None => chord,
+        })
+    }
+}
@@ -0,0 +1,194 @@
+use super::chord::Chord;
+use super::scale::Key;
+use crate::types::rhythm::tempo::bpm::beat::Beat;
+
+// Values that hold from their beat until the next change, kept sorted
+#[derive(Clone, Debug)]
+pub struct Changes<T> {
+    changes: Vec<(Beat, T)>,
+}
+
+impl<T> Default for Changes<T> {
+    fn default() -> Self {
+        Changes {
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy + PartialEq> Changes<T> {
+    pub fn as_slice(&self) -> &[(Beat, T)] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // Replaces any change on the same beat
+    pub fn set(&mut self, beat: Beat, value: T) {
+        let beat = beat.max(0.0);
+        let index = self.changes.partition_point(|(b, _)| *b < beat);
+        match self.changes.get_mut(index) {
+            Some(change) if change.0 == beat => change.1 = value,
+            _ => self.changes.insert(index, (beat, value)),
+        }
+    }
+
+    pub fn remove(&mut self, beat: Beat) -> Option<T> {
+        let index = self.changes.iter().position(|(b, _)| *b == beat)?;
+        Some(self.changes.remove(index).1)
+    }
+
+    pub fn at(&self, beat: Beat) -> Option<T> {
+        let index = self.changes.partition_point(|(b, _)| *b <= beat);
+        index.checked_sub(1).map(|i| self.changes[i].1)
+    }
+
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    pub fn insert_time(&mut self, at: Beat, length: Beat) {
+        if length <= 0.0 {
+            return;
+        }
+        for change in &mut self.changes {
+            if change.0 >= at {
+                change.0 += length;
+            }
+        }
+    }
+
+    // Changes inside are dropped, whatever held at `end` carries on from `start`
+    pub fn delete_time(&mut self, start: Beat, end: Beat) {
+        if end <= start {
+            return;
+        }
+
+        let leaving = self.at(end);
+        let changed = self.changes.iter().any(|(b, _)| *b >= start && *b <= end);
+
+        self.changes.retain(|(b, _)| *b < start || *b >= end);
+        for change in &mut self.changes {
+            if change.0 >= end {
+                change.0 -= end - start;
+            }
+        }
+
+        if let Some(leaving) = leaving.filter(|_| changed) {
+            if self.at(start) != Some(leaving) {
+                self.set(start, leaving);
+            }
+        }
+    }
+
+    // Repeats `start..end` after itself, the copy starting on what held at
+    // `start` and handing back to what held at `end`
+    pub fn duplicate_time(&mut self, start: Beat, end: Beat) {
+        if end <= start {
+            return;
+        }
+
+        let length = end - start;
+        let (entering, leaving) = (self.at(start), self.at(end));
+        let copied: Vec<(Beat, T)> = self
+            .changes
+            .iter()
+            .filter(|(b, _)| *b >= start && *b < end)
+            .copied()
+            .collect();
+
+        self.insert_time(end, length);
+
+        if let Some(entering) = entering {
+            self.set(end, entering);
+        }
+        for (beat, value) in copied {
+            self.set(beat + length, value);
+        }
+        if let Some(leaving) = leaving {
+            if self.at(end + length) != Some(leaving) {
+                self.set(end + length, leaving);
+            }
+        }
+    }
+}
+
+// What scale-locked edits snap notes to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnapTarget {
+    Key,
+    // Chord tones, the key where there is no chord
+    Chord,
+}
+
+// Key and chord changes across the song in beats, laid over the tracks
+#[derive(Clone, Debug, Default)]
+pub struct ChordTrack {
+    // Key before the first change
+    pub key: Key,
+    pub keys: Changes<Key>,
+    pub chords: Changes<Chord>,
+}
+
+impl ChordTrack {
+    pub fn new(key: Key) -> Self {
+        ChordTrack {
+            key,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_key(&mut self, beat: Beat, key: Key) {
+        self.keys.set(beat, key);
+    }
+
+    pub fn remove_key(&mut self, beat: Beat) -> Option<Key> {
+        self.keys.remove(beat)
+    }
+
+    pub fn key_at(&self, beat: Beat) -> Key {
+        self.keys.at(beat).unwrap_or(self.key)
+    }
+
+    pub fn set_chord(&mut self, beat: Beat, chord: Chord) {
+        self.chords.set(beat, chord);
+    }
+
+    pub fn remove_chord(&mut self, beat: Beat) -> Option<Chord> {
+        self.chords.remove(beat)
+    }
+
+    pub fn chord_at(&self, beat: Beat) -> Option<Chord> {
+        self.chords.at(beat)
+    }
+
+    // Note nearest `note` that fits at `beat`
+    pub fn snap(&self, beat: Beat, note: u8, target: SnapTarget) -> u8 {
+        match (target, self.chord_at(beat)) {
+            (SnapTarget::Chord, Some(chord)) => chord.snap(note),
+            _ => self.key_at(beat).snap(note),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.chords.clear();
+    }
+
+    pub fn insert_time(&mut self, at: Beat, length: Beat) {
+        self.keys.insert_time(at, length);
+        self.chords.insert_time(at, length);
+    }
+
+    pub fn delete_time(&mut self, start: Beat, end: Beat) {
+        self.keys.delete_time(start, end);
+        self.chords.delete_time(start, end);
+    }
+
+    pub fn duplicate_time(&mut self, start: Beat, end: Beat) {
+        self.keys.duplicate_time(start, end);
+        self.chords.duplicate_time(start, end);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod chord;
+pub mod chord_track;
+pub mod scale;
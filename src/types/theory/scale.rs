@@ -0,0 +1,309 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::types::pitch::pitch::{Letter, Pitch};
+
+// Scales as sets of semitones above the tonic
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ScaleType {
+    #[default]
+    Major,
+    Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    HarmonicMinor,
+    MelodicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    WholeTone,
+    Chromatic,
+    // Bit n set for n semitones above the tonic, the tonic is always in
+    Custom(u16),
+}
+
+const NAMED: [ScaleType; 14] = [
+    ScaleType::Major,
+    ScaleType::Minor,
+    ScaleType::Dorian,
+    ScaleType::Phrygian,
+    ScaleType::Lydian,
+    ScaleType::Mixolydian,
+    ScaleType::Locrian,
+    ScaleType::HarmonicMinor,
+    ScaleType::MelodicMinor,
+    ScaleType::MajorPentatonic,
+    ScaleType::MinorPentatonic,
+    ScaleType::Blues,
+    ScaleType::WholeTone,
+    ScaleType::Chromatic,
+];
+
+impl ScaleType {
+    // Scale from its semitones above the tonic, e.g. [0, 2, 3, 7, 8]
+    pub fn custom(semitones: &[u8]) -> Self {
+        let mask = semitones.iter().fold(1u16, |mask, s| mask | 1 << (s % 12));
+        NAMED
+            .into_iter()
+            .find(|s| s.mask() == mask)
+            .unwrap_or(ScaleType::Custom(mask))
+    }
+
+    pub fn mask(&self) -> u16 {
+        let semitones: &[u8] = match self {
+            ScaleType::Custom(mask) => return (mask | 1) & 0xfff,
+            ScaleType::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleType::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleType::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleType::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            ScaleType::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            ScaleType::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleType::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            ScaleType::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleType::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            ScaleType::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleType::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleType::Blues => &[0, 3, 5, 6, 7, 10],
+            ScaleType::WholeTone => &[0, 2, 4, 6, 8, 10],
+            ScaleType::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        };
+        semitones.iter().fold(0, |mask, s| mask | 1 << s)
+    }
+
+    // Semitones above the tonic, lowest first
+    pub fn semitones(&self) -> impl Iterator<Item = u8> {
+        let mask = self.mask();
+        (0..12u8).filter(move |s| mask & 1 << s != 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.mask().count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Semitones above the tonic of a degree, counting on into later octaves
+    pub fn interval(&self, degree: i32) -> i32 {
+        let size = self.len() as i32;
+        let within = self
+            .semitones()
+            .nth(degree.rem_euclid(size) as usize)
+            .unwrap_or(0);
+        degree.div_euclid(size) * 12 + within as i32
+    }
+
+    // The scale started from another of its degrees, Dorian is mode 1 of Major
+    pub fn mode(&self, degree: usize) -> Self {
+        let start = self.interval(degree as i32) as u8 % 12;
+        let semitones: Vec<u8> = self.semitones().map(|s| (s + 12 - start) % 12).collect();
+        ScaleType::custom(&semitones)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleType::Major => "major",
+            ScaleType::Minor => "minor",
+            ScaleType::Dorian => "dorian",
+            ScaleType::Phrygian => "phrygian",
+            ScaleType::Lydian => "lydian",
+            ScaleType::Mixolydian => "mixolydian",
+            ScaleType::Locrian => "locrian",
+            ScaleType::HarmonicMinor => "harmonic minor",
+            ScaleType::MelodicMinor => "melodic minor",
+            ScaleType::MajorPentatonic => "major pentatonic",
+            ScaleType::MinorPentatonic => "minor pentatonic",
+            ScaleType::Blues => "blues",
+            ScaleType::WholeTone => "whole tone",
+            ScaleType::Chromatic => "chromatic",
+            ScaleType::Custom(_) => "custom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        match name.as_str() {
+            "ionian" | "maj" => Some(ScaleType::Major),
+            "aeolian" | "natural minor" | "min" | "m" => Some(ScaleType::Minor),
+            name => NAMED.into_iter().find(|s| s.name() == name),
+        }
+    }
+}
+
+// Pitch class 0 to 11 from C
+pub fn pitch_class(note: i32) -> u8 {
+    note.rem_euclid(12) as u8
+}
+
+// "C#" or "Db" for a pitch class
+pub fn pitch_class_name(class: u8, flats: bool) -> String {
+    let pitch = match flats {
+        true => Pitch::from_midi_flat(class as i32),
+        false => Pitch::from_midi(class as i32),
+    };
+    let name = pitch.to_string();
+    name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '-')
+        .to_string()
+}
+
+// Reads a note name without an octave from the front of `s`, returning its
+// pitch class and the rest
+pub fn parse_pitch_class(s: &str) -> Option<(u8, &str)> {
+    let mut chars = s.char_indices();
+    let letter = Letter::from_char(chars.next()?.1)?;
+
+    let mut semitone = letter.semitone();
+    let mut rest = &s[1..];
+    for (i, c) in chars {
+        match c {
+            '#' | '♯' => semitone += 1,
+            'b' | '♭' => semitone -= 1,
+            _ => {
+                rest = &s[i..];
+                return Some((pitch_class(semitone), rest));
+            }
+        }
+        rest = &s[i + c.len_utf8()..];
+    }
+    Some((pitch_class(semitone), rest))
+}
+
+// A tonic and the scale built on it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Key {
+    // Pitch class, 0 is C
+    pub tonic: u8,
+    pub scale: ScaleType,
+}
+
+impl Default for Key {
+    fn default() -> Self {
+        Key::new(0, ScaleType::Major)
+    }
+}
+
+impl Key {
+    pub fn new(tonic: u8, scale: ScaleType) -> Self {
+        Key {
+            tonic: tonic % 12,
+            scale,
+        }
+    }
+
+    // Pitch classes of the key, starting on the tonic
+    pub fn pitch_classes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.scale.semitones().map(|s| (s + self.tonic) % 12)
+    }
+
+    pub fn contains(&self, note: u8) -> bool {
+        let interval = pitch_class(note as i32 - self.tonic as i32);
+        self.scale.mask() & 1 << interval != 0
+    }
+
+    // Degree of a note, 0 for the tonic, when it is in the key
+    pub fn degree_of(&self, note: u8) -> Option<usize> {
+        let interval = pitch_class(note as i32 - self.tonic as i32);
+        self.scale.semitones().position(|s| s == interval)
+    }
+
+    // Note of a degree counted from the tonic in the octave of `reference`
+    // (from its tonic below it). Negative degrees go down.
+    fn note_of(&self, reference: i32, degree: i32) -> i32 {
+        let interval = pitch_class(reference - self.tonic as i32) as i32;
+        reference - interval + self.scale.interval(degree)
+    }
+
+    // Nearest note in the key, ties go down
+    pub fn snap(&self, note: u8) -> u8 {
+        (0..=6)
+            .flat_map(|distance| [note as i32 - distance, note as i32 + distance])
+            .filter(|n| (0..=127).contains(n))
+            .find(|n| self.contains(*n as u8))
+            .unwrap_or(note as i32) as u8
+    }
+
+    // Moves a note `steps` degrees along the scale, snapping it first.
+    // Notes that would leave the MIDI range stay where they are.
+    pub fn transpose(&self, note: u8, steps: i32) -> u8 {
+        let snapped = self.snap(note);
+        let degree = self.degree_of(snapped).unwrap_or(0) as i32;
+
+        let moved = self.note_of(snapped as i32, degree + steps);
+        if (0..=127).contains(&moved) {
+            moved as u8
+        } else {
+            note
+        }
+    }
+
+    // The same degree in `other`, notes outside this key move with the tonic.
+    // Tonics move by the smaller interval, so G major to C major goes up a fourth.
+    pub fn transpose_to(&self, other: &Key, note: u8) -> u8 {
+        let shift = pitch_class(other.tonic as i32 - self.tonic as i32) as i32;
+        let shift = if shift > 6 { shift - 12 } else { shift };
+
+        let moved = match self.degree_of(note) {
+            Some(degree) => {
+                let tonic = note as i32 - self.scale.interval(degree as i32);
+                let size = other.scale.len().max(1);
+                tonic + shift + other.scale.interval((degree % size) as i32)
+            }
+            None => note as i32 + shift,
+        };
+
+        if (0..=127).contains(&moved) {
+            moved as u8
+        } else {
+            note
+        }
+    }
+
+    // Flats for keys whose major scale has them
+    pub fn uses_flats(&self) -> bool {
+        let major = match self.scale {
+            ScaleType::Minor
+            | ScaleType::MinorPentatonic
+            | ScaleType::Blues
+            | ScaleType::HarmonicMinor
+            | ScaleType::MelodicMinor => self.tonic + 3,
+            ScaleType::Dorian => self.tonic + 10,
+            ScaleType::Phrygian => self.tonic + 8,
+            ScaleType::Lydian => self.tonic + 7,
+            ScaleType::Mixolydian => self.tonic + 5,
+            ScaleType::Locrian => self.tonic + 1,
+            _ => self.tonic,
+        } % 12;
+        [5, 10, 3, 8, 1, 6].contains(&major)
+    }
+}
+
+// "C major", "F# dorian" or "Bb minor pentatonic"
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tonic = pitch_class_name(self.tonic, self.uses_flats());
+        write!(f, "{} {}", tonic, self.scale.name())
+    }
+}
+
+// Also takes "Am" and "C" for C major
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let (tonic, rest) =
+            parse_pitch_class(s).ok_or_else(|| anyhow!("{} does not start with a note", s))?;
+
+        let scale = match rest.trim() {
+            "" => ScaleType::Major,
+            name => ScaleType::from_name(name).ok_or_else(|| anyhow!("unknown scale {}", name))?,
+        };
+        Ok(Key::new(tonic, scale))
+    }
+}